@@ -0,0 +1,548 @@
+use bevy::{
+    color::Color,
+    ecs::{
+        component::Component,
+        entity::Entity,
+        query::With,
+        system::{Commands, Query, Res, ResMut, Resource, Single},
+    },
+    input::{ButtonInput, keyboard::KeyCode},
+    log::warn,
+    text::{FontSmoothing, JustifyText, Text2d, TextColor, TextFont, TextLayout},
+    transform::components::Transform,
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use rand::{Rng, seq::IndexedRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    GameAssets, TetrisEntity,
+    brick::{BrickNode, BrickShape, brick_at},
+    constants::BRICKS_DICT,
+    finder::{Route, find_routes},
+    game_data::{Board, EraseAnimationStep, GameAction, GameData, InputBuffer},
+    rng::GameRng,
+};
+
+/// How much each feature of the board left by a placement counts, higher
+/// scores are better.
+#[derive(Debug, Clone, Copy, PartialEq, Resource, Serialize, Deserialize)]
+pub struct AiWeights {
+    pub aggregate_height: f32,
+    pub holes: f32,
+    pub bumpiness: f32,
+    pub wells: f32,
+    pub lines: f32,
+}
+
+impl Default for AiWeights {
+    fn default() -> Self {
+        // Yiyuan Lee's tuned weights, with deep wells slightly penalized
+        AiWeights {
+            aggregate_height: -0.510066,
+            holes: -0.35663,
+            bumpiness: -0.184483,
+            wells: -0.05,
+            lines: 0.760666,
+        }
+    }
+}
+
+const AI_WEIGHTS_FILE: &str = "ai_weights.json";
+
+impl AiWeights {
+    /// Where tuned weights are picked up from.
+    pub fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("rs-tetris").join(AI_WEIGHTS_FILE))
+    }
+
+    /// Loads the tuned weights, the defaults when there are none or they
+    /// can't be read.
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+        match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|err| {
+                warn!("Ignoring AI weights in {}: {}", path.display(), err);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn to_array(&self) -> [f32; 5] {
+        [
+            self.aggregate_height,
+            self.holes,
+            self.bumpiness,
+            self.wells,
+            self.lines,
+        ]
+    }
+
+    pub fn from_array([aggregate_height, holes, bumpiness, wells, lines]: [f32; 5]) -> Self {
+        AiWeights {
+            aggregate_height,
+            holes,
+            bumpiness,
+            wells,
+            lines,
+        }
+    }
+
+    pub fn score(&self, features: &Features) -> f32 {
+        self.aggregate_height * features.aggregate_height as f32
+            + self.holes * features.holes as f32
+            + self.bumpiness * features.bumpiness as f32
+            + self.wells * features.wells as f32
+            + self.lines * features.lines as f32
+    }
+}
+
+/// What the evaluator looks at on a board after a placement.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Features {
+    /// Sum of the column heights.
+    pub aggregate_height: u32,
+    /// Empty cells with a filled cell somewhere above them.
+    pub holes: u32,
+    /// Sum of the height differences between neighboring columns.
+    pub bumpiness: u32,
+    /// Sum of how far each column sits below both of its neighbors, walls
+    /// count as neighbors as high as the column next to them.
+    pub wells: u32,
+    /// Lines cleared by the placement.
+    pub lines: u32,
+}
+
+impl Features {
+    pub fn of(board: &Board, lines: usize) -> Self {
+        let heights = column_heights(board);
+        let holes = (0..board.cols)
+            .map(|col| {
+                (0..heights[col])
+                    .filter(|row| !board.is_occupied(col, *row))
+                    .count() as u32
+            })
+            .sum();
+        let bumpiness = heights
+            .windows(2)
+            .map(|pair| pair[0].abs_diff(pair[1]) as u32)
+            .sum();
+        let wells = (0..board.cols)
+            .map(|col| {
+                let left = if col == 0 {
+                    None
+                } else {
+                    Some(heights[col - 1])
+                };
+                let right = heights.get(col + 1).copied();
+                let walls = left.into_iter().chain(right).min().unwrap_or(0);
+                walls.saturating_sub(heights[col]) as u32
+            })
+            .sum();
+        Features {
+            aggregate_height: heights.iter().sum::<usize>() as u32,
+            holes,
+            bumpiness,
+            wells,
+            lines: lines as u32,
+        }
+    }
+}
+
+/// Height of each column's top filled cell.
+pub fn column_heights(board: &Board) -> Vec<usize> {
+    (0..board.cols)
+        .map(|col| {
+            (0..board.rows)
+                .rev()
+                .find(|row| board.is_occupied(col, *row))
+                .map_or(0, |row| row + 1)
+        })
+        .collect()
+}
+
+/// Where a brick ends up and the board it leaves behind, lines cleared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    pub shape: BrickShape,
+    /// The falling brick's node once locked.
+    pub node: BrickNode,
+    pub lines: usize,
+    pub board: Board,
+}
+
+impl Placement {
+    /// Locks the brick of `shape` at `node` into a copy of `board` and clears
+    /// the lines it fills.
+    pub fn new(board: &Board, shape: BrickShape, node: BrickNode) -> Self {
+        let mut board = board.clone();
+        board.update_occupied_by_brick(&brick_at(shape, node));
+        let lines = board.get_clean_lines();
+        board.clean(lines);
+        Placement {
+            shape,
+            node,
+            lines: lines.1,
+            board,
+        }
+    }
+}
+
+/// Drops the brick straight down from above the board in every rotation and
+/// column it fits, skipping placements that would top out.
+pub fn placements(board: &Board, shape: BrickShape) -> Vec<Placement> {
+    let mut placements = vec![];
+    for rotation in 0..BRICKS_DICT[shape.0].len() {
+        let shape = BrickShape(shape.0, rotation);
+        for x in -3..board.cols as i8 {
+            let mut node = BrickNode(x, board.rows as i8 + 3);
+            if !board.fits(shape, node) {
+                continue;
+            }
+            while board.fits(shape, BrickNode(node.0, node.1 - 1)) {
+                node.move_down();
+            }
+            let brick = brick_at(shape, node);
+            if brick.nodes.iter().any(|node| board.is_move_to_top(node)) {
+                continue;
+            }
+            placements.push(Placement::new(board, shape, node));
+        }
+    }
+    placements
+}
+
+/// The placement of `shape` the weights like best.
+pub fn best_placement(board: &Board, shape: BrickShape, weights: &AiWeights) -> Option<Placement> {
+    placements(board, shape)
+        .into_iter()
+        .map(|placement| {
+            let score = weights.score(&Features::of(&placement.board, placement.lines));
+            (placement, score)
+        })
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(placement, _)| placement)
+}
+
+/// The route for `shape` from `spawn` that leaves the best board once `next`
+/// is placed too. Tucks and spins count, so it can suggest more than the CPU
+/// plays.
+pub fn best_route(
+    board: &Board,
+    shape: BrickShape,
+    spawn: BrickNode,
+    next: BrickShape,
+    weights: &AiWeights,
+) -> Option<Route> {
+    find_routes(board, shape, spawn)
+        .into_iter()
+        .map(|route| {
+            let placement = Placement::new(board, route.shape, route.node);
+            let score = placements(&placement.board, next)
+                .iter()
+                .map(|then| weights.score(&Features::of(&then.board, placement.lines + then.lines)))
+                .max_by(f32::total_cmp)
+                .unwrap_or_else(|| weights.score(&Features::of(&placement.board, placement.lines)));
+            (route, score)
+        })
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(route, _)| route)
+}
+
+/// How well the CPU plays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AiLevel {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl AiLevel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AiLevel::Easy => "Easy",
+            AiLevel::Normal => "Normal",
+            AiLevel::Hard => "Hard",
+        }
+    }
+
+    /// Ticks between two inputs.
+    pub fn move_ticks(&self) -> u32 {
+        match self {
+            AiLevel::Easy => 12,
+            AiLevel::Normal => 6,
+            AiLevel::Hard => 2,
+        }
+    }
+
+    /// Chance of taking a random placement instead of the best one.
+    pub fn mistake_chance(&self) -> f64 {
+        match self {
+            AiLevel::Easy => 0.25,
+            AiLevel::Normal => 0.08,
+            AiLevel::Hard => 0.,
+        }
+    }
+}
+
+/// Whether the CPU plays and how well, cycled with C on the Ready screen. It
+/// plays the second player in versus and the player's board in the other
+/// modes.
+#[derive(Debug, Default, Resource)]
+pub struct AiSettings {
+    pub level: Option<AiLevel>,
+}
+
+impl AiSettings {
+    /// Off, then each level from the easiest.
+    pub fn cycle(&mut self) {
+        self.level = match self.level {
+            None => Some(AiLevel::Easy),
+            Some(AiLevel::Easy) => Some(AiLevel::Normal),
+            Some(AiLevel::Normal) => Some(AiLevel::Hard),
+            Some(AiLevel::Hard) => None,
+        };
+    }
+}
+
+/// A board played by the CPU, one input every few ticks.
+#[derive(Debug, Clone, Component)]
+pub struct AiPlayer {
+    pub level: AiLevel,
+    /// Where the falling brick should go, picked once it spawns.
+    pub target: Option<(BrickShape, BrickNode)>,
+    /// Ticks until the next input.
+    pub cooldown: u32,
+    /// The last sideways move and the column it was made from, to notice
+    /// the brick being blocked.
+    pub last_move: Option<(GameAction, i8)>,
+    /// Picks the mistakes, apart from the bricks so a game plays out the same.
+    pub rng: GameRng,
+}
+
+impl AiPlayer {
+    pub fn new(level: AiLevel, seed: u64) -> Self {
+        AiPlayer {
+            level,
+            target: None,
+            cooldown: 0,
+            last_move: None,
+            rng: GameRng::new(seed.rotate_left(17)),
+        }
+    }
+
+    fn choose(
+        &mut self,
+        game_data: &GameData,
+        weights: &AiWeights,
+    ) -> Option<(BrickShape, BrickNode)> {
+        let shape = game_data.falling_brick_shape;
+        let placement = if self.rng.random_bool(self.level.mistake_chance()) {
+            placements(&game_data.board, shape)
+                .choose(&mut self.rng)
+                .cloned()
+        } else {
+            best_placement(&game_data.board, shape, weights)
+        };
+        placement.map(|placement| (placement.shape, placement.node))
+    }
+
+    /// The next input for the falling brick: rotate, then move sideways,
+    /// then drop. Nothing while the brick drops or lines are cleared.
+    pub fn act(&mut self, game_data: &GameData, weights: &AiWeights) -> Option<GameAction> {
+        if !game_data.is_running()
+            || game_data.is_dropping()
+            || game_data.erase_animation_step == EraseAnimationStep::Playing
+        {
+            return None;
+        }
+        if self.cooldown > 0 {
+            self.cooldown -= 1;
+            return None;
+        }
+        self.cooldown = self.level.move_ticks();
+        if self.target.is_none() {
+            self.target = self.choose(game_data, weights);
+        }
+        let x = game_data.falling_brick_node.0;
+        let is_blocked = self.last_move.is_some_and(|(_, from)| from == x);
+        let action = match self.target {
+            Some((shape, _)) if shape != game_data.falling_brick_shape => GameAction::Rotate,
+            Some((_, node)) if node.0 > x && !is_blocked => GameAction::Right,
+            Some((_, node)) if node.0 < x && !is_blocked => GameAction::Left,
+            _ => GameAction::Drop,
+        };
+        self.last_move = None;
+        match action {
+            GameAction::Left | GameAction::Right => self.last_move = Some((action, x)),
+            GameAction::Drop => self.target = None,
+            _ => {}
+        }
+        Some(action)
+    }
+}
+
+/// Plays the boards the CPU has, at the start of each tick.
+pub fn ai_input_system(
+    weights: Res<AiWeights>,
+    mut boards: Query<(&GameData, &mut InputBuffer, &mut AiPlayer)>,
+) {
+    for (game_data, mut input_buffer, mut ai_player) in &mut boards {
+        if let Some(action) = ai_player.act(game_data, &weights) {
+            input_buffer.push(action);
+        }
+    }
+}
+
+/// Hands the boards back to the players on the Ready screen.
+pub fn end_ai(mut commands: Commands, boards: Query<Entity, With<AiPlayer>>) {
+    for board in &boards {
+        commands.entity(board).remove::<AiPlayer>();
+    }
+}
+
+#[derive(Component)]
+pub struct AiText;
+
+fn ai_text(ai_settings: &AiSettings) -> String {
+    format!(
+        "CPU {}",
+        ai_settings.level.map_or("Off", |level| level.name())
+    )
+}
+
+pub fn spawn_ai_text(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    ai_settings: Res<AiSettings>,
+) {
+    commands.spawn((
+        TetrisEntity,
+        Text2d::new(ai_text(&ai_settings)),
+        TextColor(Color::BLACK),
+        TextLayout::new_with_justify(JustifyText::Center),
+        TextFont {
+            font: game_assets.font.clone(),
+            font_size: 12.0,
+            font_smoothing: FontSmoothing::AntiAliased,
+        },
+        Transform::from_xyz(-40., 60., 300.),
+        AiText,
+    ));
+}
+
+pub fn toggle_ai_system(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut ai_settings: ResMut<AiSettings>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyC) {
+        ai_settings.cycle();
+    }
+}
+
+pub fn despawn_ai_text(mut commands: Commands, ai_text: Single<Entity, With<AiText>>) {
+    commands.entity(ai_text.into_inner()).despawn();
+}
+
+pub fn ai_text_system(ai_settings: Res<AiSettings>, ai_text: Single<&mut Text2d, With<AiText>>) {
+    let text = self::ai_text(&ai_settings);
+    let mut ai_text = ai_text.into_inner();
+    if **ai_text != text {
+        **ai_text = text;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AiLevel, AiPlayer, AiWeights, Features, best_placement, best_route, column_heights,
+    };
+
+    use crate::{
+        brick::{BrickNode, BrickShape},
+        game_data::{Board, GameAction, GameData},
+    };
+
+    fn board_from_rows(cols: usize, rows: &[&str]) -> Board {
+        let mut board = Board::new(cols, 20);
+        for (y, row) in rows.iter().rev().enumerate() {
+            for (x, cell) in row.chars().enumerate() {
+                board.set_occupied(x, y, cell == '#');
+            }
+        }
+        board
+    }
+
+    #[test]
+    fn test_features() {
+        let board = board_from_rows(5, &["#....", "#.#..", "###.#"]);
+        assert_eq!(column_heights(&board), vec![3, 1, 2, 0, 1]);
+        let features = Features::of(&board, 1);
+        assert_eq!(features.aggregate_height, 7);
+        assert_eq!(features.holes, 0);
+        assert_eq!(features.bumpiness, 2 + 1 + 2 + 1);
+        assert_eq!(features.wells, 1 + 1);
+        assert_eq!(features.lines, 1);
+
+        let board = board_from_rows(3, &["###", "#.#"]);
+        assert_eq!(Features::of(&board, 0).holes, 1);
+    }
+
+    #[test]
+    fn test_best_placement_clears_lines() {
+        // a flat I brick fills the gap on the right of the bottom row
+        let board = board_from_rows(10, &["######...."]);
+        let placement = best_placement(&board, BrickShape(1, 0), &AiWeights::default()).unwrap();
+        assert_eq!(placement.shape, BrickShape(1, 0));
+        assert_eq!(placement.node, BrickNode(6, 1));
+        assert_eq!(placement.lines, 1);
+        assert!(placement.board.occupied().all(|cell| !cell));
+    }
+
+    #[test]
+    fn test_best_route_tucks_under_overhang() {
+        // an I brick slid under the roof leaves no holes
+        let board = board_from_rows(10, &["####......", "####......", ".........."]);
+        let spawn = BrickNode(5, 23);
+        let route = best_route(
+            &board,
+            BrickShape(1, 0),
+            spawn,
+            BrickShape(0, 0),
+            &AiWeights::default(),
+        )
+        .unwrap();
+        assert_eq!(route.node, BrickNode(0, 1));
+        assert!(route.inputs.contains(&GameAction::Down));
+    }
+
+    #[test]
+    fn test_ai_plays_a_game() {
+        let mut game_data = GameData::default();
+        game_data.start(21);
+        let mut ai_player = AiPlayer::new(AiLevel::Hard, 21);
+        let weights = AiWeights::default();
+        for _ in 0..30_000 {
+            let action = ai_player.act(&game_data, &weights);
+            game_data.tick_with(action);
+            if game_data.ended.is_some() {
+                break;
+            }
+        }
+        assert!(game_data.cleans >= 20, "cleared {}", game_data.cleans);
+    }
+}
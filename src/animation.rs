@@ -1,18 +1,25 @@
 use std::time::Duration;
 
 use bevy::{
-    color::Srgba, ecs::{
+    asset::Assets,
+    ecs::{
         component::Component,
-        query::{With, Without},
-        system::{Query, Res, ResMut, Single},
-    }, hierarchy::Children, prelude::{Deref, DerefMut}, sprite::Sprite, time::{Time, Timer}
+        entity::Entity,
+        query::With,
+        system::{Commands, Query, Res, ResMut, Single},
+    },
+    math::UVec2,
+    prelude::{Deref, DerefMut},
+    sprite::{Sprite, TextureAtlas, TextureAtlasLayout},
+    time::{Time, Timer, TimerMode},
+    transform::components::Transform,
 };
-use bevy_prototype_lyon::draw::{Fill, Stroke};
 
 use crate::{
-    board::{clean_board_lines, BoardBrickNode, FallingBrickNode},
-    brick::BrickNode,
-    game_data::{EraseAnimationStep, GameData},
+    GameAssets, TetrisEntity,
+    board::PlayerBoard,
+    constants::{BRICKS_CONTAINER_BOUNDING_LEFT, BRICKS_CONTAINER_WIDTH},
+    game_data::GameData,
 };
 
 #[derive(Component)]
@@ -24,9 +31,12 @@ pub struct AnimationIndices {
 #[derive(Component, Deref, DerefMut)]
 pub struct AnimationTimer(pub(crate) Timer);
 
+#[derive(Component)]
+pub struct VictoryAnimation;
+
 pub fn play_ready_animation(
     time: Res<Time>,
-    mut game_data: ResMut<GameData>,
+    mut game_data: Single<&mut GameData, With<PlayerBoard>>,
     query: Single<(&mut AnimationIndices, &mut AnimationTimer, &mut Sprite)>,
 ) {
     let delta: std::time::Duration = time.delta();
@@ -54,8 +64,70 @@ pub fn play_ready_animation(
 
     timer.tick(delta);
 
-    if timer.just_finished() {
-        if let Some(atlas) = &mut sprite.texture_atlas {
+    if timer.just_finished()
+        && let Some(atlas) = &mut sprite.texture_atlas
+    {
+        atlas.index = if atlas.index >= indices.last {
+            indices.first
+        } else {
+            atlas.index + 1
+        };
+    }
+}
+
+pub fn spawn_victory_animation(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    game_data: Single<&GameData, With<PlayerBoard>>,
+    mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
+) {
+    if !game_data.mode.has_goal() {
+        return;
+    }
+    let texture = game_assets.dino.clone();
+    let layout = TextureAtlasLayout::from_grid(UVec2::new(80, 86), 4, 1, None, None);
+    let texture_atlas_layout = texture_atlas_layouts.add(layout);
+    let animation_indices = AnimationIndices { first: 2, last: 3 };
+    commands.spawn((
+        TetrisEntity,
+        Sprite::from_atlas_image(
+            texture,
+            TextureAtlas {
+                layout: texture_atlas_layout,
+                index: animation_indices.first,
+            },
+        ),
+        Transform::from_xyz(BRICKS_CONTAINER_BOUNDING_LEFT, 195., 600.)
+            .with_scale((0.5, 0.5, 1.).into()),
+        animation_indices,
+        AnimationTimer(Timer::from_seconds(0.1, TimerMode::Repeating)),
+        VictoryAnimation,
+    ));
+}
+
+/// The dino runs across the top of the board while the results are shown.
+pub fn play_victory_animation(
+    time: Res<Time>,
+    mut query: Query<
+        (
+            &AnimationIndices,
+            &mut AnimationTimer,
+            &mut Sprite,
+            &mut Transform,
+        ),
+        With<VictoryAnimation>,
+    >,
+) {
+    for (indices, mut timer, mut sprite, mut transform) in query.iter_mut() {
+        transform.translation.x += 60. * time.delta_secs();
+        if transform.translation.x > BRICKS_CONTAINER_BOUNDING_LEFT + BRICKS_CONTAINER_WIDTH {
+            transform.translation.x = BRICKS_CONTAINER_BOUNDING_LEFT;
+        }
+
+        timer.tick(time.delta());
+        if timer.just_finished()
+            && let Some(atlas) = &mut sprite.texture_atlas
+        {
             atlas.index = if atlas.index >= indices.last {
                 indices.first
             } else {
@@ -65,51 +137,11 @@ pub fn play_ready_animation(
     }
 }
 
-pub fn play_erase_animation(
-    time: Res<Time>,
-    mut game_data: ResMut<GameData>,
-    mut board_brick_nodes_query: Query<(&mut Children, &BrickNode), (With<BoardBrickNode>, Without<FallingBrickNode>)>,
-    mut fill_query: Query<&mut Fill>,
-    mut stroke_query: Query<&mut Stroke>,
+pub fn despawn_victory_animation(
+    mut commands: Commands,
+    query: Query<Entity, With<VictoryAnimation>>,
 ) {
-    if game_data.erase_animation_step == EraseAnimationStep::Playing {
-        let delta: std::time::Duration = time.delta();
-        game_data.erase_animation_duration = game_data.erase_animation_duration.saturating_add(delta);
-        let (start, lines) = game_data.clean_lines;
-
-        game_data.erase_animation_timer.tick(delta);
-        if game_data.erase_animation_timer.finished() {
-            game_data.erase_animation_index += 1;
-            board_brick_nodes_query
-                .iter()
-                .filter(|(_, node)| node.1 >= (start as i8) && node.1 < (start + lines) as i8).for_each(|(children,..)| {
-                    for child in children.iter() {
-                                if let Ok(mut fill) = fill_query.get_mut(*child) {
-                                    fill.color = if game_data.erase_animation_index % 2 == 0 {
-                                        Srgba::hex("#000000").unwrap().into()
-                                    } else {
-                                        Srgba::hex("#879372").unwrap().into()
-                                    }
-                                }
-                                if let Ok(mut stroke) = stroke_query.get_mut(*child) {
-                                    stroke.color = if game_data.erase_animation_index % 2 == 0 {
-                                        Srgba::hex("#000000").unwrap().into()
-                                    } else {
-                                        Srgba::hex("#879372").unwrap().into()
-                                    }
-                                }
-                            }
-                });
-        }
-        
-        if game_data.erase_animation_duration.as_secs() >= 2 {
-            clean_board_lines(
-                &mut board_brick_nodes_query,
-                &mut game_data,
-                &mut fill_query,
-                &mut stroke_query,
-            );
-            game_data.erase_animation_step = EraseAnimationStep::End;
-        }
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
     }
 }
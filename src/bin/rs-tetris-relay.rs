@@ -0,0 +1,24 @@
+use std::{env, net::TcpListener, process};
+
+use bevy::log::{error, info, tracing_subscriber};
+use rs_tetris::{constants::DEFAULT_RELAY_ADDR, net::run_relay};
+
+/// Relay for online versus, run with the address to listen on.
+fn main() {
+    tracing_subscriber::fmt().init();
+    let addr = env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_RELAY_ADDR.to_string());
+    let listener = match TcpListener::bind(&addr) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Failed to listen on {}: {}", addr, err);
+            process::exit(1);
+        }
+    };
+    info!("Relay listening on {}", addr);
+    if let Err(err) = run_relay(listener) {
+        error!("Relay stopped: {}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,96 @@
+use std::{env, io, process, str::FromStr, time::Instant};
+
+use bevy::log::tracing_subscriber;
+use rs_tetris::{
+    ai::{AiLevel, AiWeights},
+    mode::GameMode,
+    sim::{Randomizer, SimBot, SimConfig, Summary, simulate},
+};
+
+fn value<'a>(args: &'a [String], flag: &str) -> Option<&'a String> {
+    args.iter().skip_while(|arg| *arg != flag).nth(1)
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(2);
+}
+
+fn parse<T: FromStr>(args: &[String], flag: &str, default: T) -> T {
+    match value(args, flag) {
+        Some(value) => value
+            .parse()
+            .unwrap_or_else(|_| fail(format!("Bad value for {}: {}", flag, value))),
+        None => default,
+    }
+}
+
+/// Mode names without spaces or case, e.g. `marathon`, `dig10`, `zen`.
+fn parse_mode(name: &str) -> GameMode {
+    let key = |name: &str| name.replace(' ', "").to_lowercase();
+    let modes: Vec<GameMode> = GameMode::all()
+        .into_iter()
+        .filter(|mode| !mode.is_versus())
+        .collect();
+    modes
+        .iter()
+        .find(|mode| key(&mode.name()) == key(name))
+        .copied()
+        .unwrap_or_else(|| {
+            let names: Vec<String> = modes.iter().map(|mode| key(&mode.name())).collect();
+            fail(format!(
+                "Unknown mode {}, one of: {}",
+                name,
+                names.join(", ")
+            ))
+        })
+}
+
+/// Plays seeded games without a window and prints their statistics.
+///
+/// `--bot easy|normal|hard`, `--tbp <command>`, `--mode <name>`,
+/// `--randomizer uniform|bag`, `--games n`, `--seed n`, `--pieces n`,
+/// `--threads n`.
+fn main() {
+    // the summary goes to stdout, bot messages are logged next to the progress
+    tracing_subscriber::fmt().with_writer(io::stderr).init();
+    let args: Vec<String> = env::args().collect();
+    let defaults = SimConfig::default();
+    let bot = match (value(&args, "--tbp"), value(&args, "--bot")) {
+        (Some(command), _) => SimBot::Tbp(command.clone()),
+        (None, Some(level)) => SimBot::Ai(match level.as_str() {
+            "easy" => AiLevel::Easy,
+            "normal" => AiLevel::Normal,
+            "hard" => AiLevel::Hard,
+            _ => fail(format!("Unknown bot {}, one of: easy, normal, hard", level)),
+        }),
+        (None, None) => defaults.bot.clone(),
+    };
+    let randomizer = match value(&args, "--randomizer").map(String::as_str) {
+        None | Some("uniform") => Randomizer::Uniform,
+        Some("bag") => Randomizer::Bag,
+        Some(other) => fail(format!(
+            "Unknown randomizer {}, one of: uniform, bag",
+            other
+        )),
+    };
+    let config = SimConfig {
+        bot,
+        mode: value(&args, "--mode").map_or(defaults.mode, |name| parse_mode(name)),
+        randomizer,
+        games: parse(&args, "--games", defaults.games),
+        seed: parse(&args, "--seed", defaults.seed),
+        max_pieces: parse(&args, "--pieces", defaults.max_pieces),
+        threads: parse(&args, "--threads", defaults.threads),
+    };
+
+    eprintln!("Simulating {:?}", config);
+    let started = Instant::now();
+    match simulate(&config, &AiWeights::load()) {
+        Ok(games) => print!("{}", Summary::of(&games, started.elapsed())),
+        Err(err) => {
+            eprintln!("Simulation failed: {}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::{env, path::PathBuf, process, str::FromStr};
+
+use rs_tetris::{
+    ai::AiWeights,
+    tune::{TuneConfig, tune},
+};
+
+fn parse<T: FromStr>(args: &[String], flag: &str, default: T) -> T {
+    let Some(value) = args.iter().skip_while(|arg| *arg != flag).nth(1) else {
+        return default;
+    };
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("Bad value for {}: {}", flag, value);
+        process::exit(2);
+    })
+}
+
+/// Tunes the CPU's evaluator weights on seeded headless games and writes the
+/// best ones where the game loads them from, or to `--out`.
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let defaults = TuneConfig::default();
+    let config = TuneConfig {
+        seed: parse(&args, "--seed", defaults.seed),
+        generations: parse(&args, "--generations", defaults.generations),
+        population: parse(&args, "--population", defaults.population),
+        elite: parse(&args, "--elite", defaults.elite),
+        games: parse(&args, "--games", defaults.games),
+        pieces: parse(&args, "--pieces", defaults.pieces),
+        threads: parse(&args, "--threads", defaults.threads),
+    };
+    let Some(out) = args
+        .iter()
+        .skip_while(|arg| *arg != "--out")
+        .nth(1)
+        .map(PathBuf::from)
+        .or_else(AiWeights::path)
+    else {
+        eprintln!("No user data directory, pass --out");
+        process::exit(1);
+    };
+    eprintln!("Tuning with {:?}", config);
+    let best = tune(&config, |generation| {
+        eprintln!(
+            "generation {}: best {:.1} lines, mean {:.1}, weights {:?}",
+            generation.index + 1,
+            generation.best_lines,
+            generation.mean_lines,
+            generation.best.to_array()
+        );
+        // written every generation so a stopped run keeps its progress
+        if let Err(err) = generation.best.save(&out) {
+            eprintln!("Failed to write {}: {}", out.display(), err);
+        }
+    });
+    println!("{}", serde_json::to_string_pretty(&best).unwrap());
+    eprintln!("Best weights written to {}", out.display());
+}
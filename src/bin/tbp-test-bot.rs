@@ -0,0 +1,17 @@
+use std::{
+    io::{self, BufWriter},
+    process,
+};
+
+use rs_tetris::tbp::run_test_bot;
+
+/// A minimal Tetris Bot Protocol engine on stdin and stdout, for trying out
+/// `--bot`.
+fn main() {
+    let stdin = io::stdin().lock();
+    let stdout = BufWriter::new(io::stdout().lock());
+    if let Err(err) = run_test_bot(stdin, stdout) {
+        eprintln!("Test bot stopped: {}", err);
+        process::exit(1);
+    }
+}
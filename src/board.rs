@@ -1,22 +1,21 @@
-use std::time::Duration;
-
 use bevy::{
     asset::{Assets, Handle},
     color::{Color, Srgba},
     ecs::{
         bundle::Bundle,
+        change_detection::{DetectChanges, Ref},
         component::Component,
         entity::Entity,
-        query::{With, Without},
-        system::{Commands, ParamSet, Query, Res, ResMut, Single},
+        query::{AnyOf, Or, With, Without},
+        system::{Commands, Query, Res, ResMut},
     },
-    hierarchy::{BuildChildren, ChildBuild, ChildBuilder, Children, DespawnRecursiveExt},
+    hierarchy::{BuildChildren, ChildBuild, ChildBuilder, Children, DespawnRecursiveExt, Parent},
     math::{UVec2, Vec2},
     render::view::Visibility,
     sprite::{Anchor, Sprite, TextureAtlas, TextureAtlasLayout},
-    state::state::NextState,
+    state::state::State,
     text::{Font, FontSmoothing, Text2d, TextColor, TextFont},
-    time::Time,
+    time::{Fixed, Time},
     transform::components::Transform,
     utils::default,
 };
@@ -29,20 +28,28 @@ use bevy_prototype_lyon::{
 use chrono::{Local, Timelike};
 
 use crate::{
-    GameAssets,
-    brick::{Brick, BrickNode, BrickShape, get_brick_node_position},
-    constants::TIMER_FALLING_SECS,
-    game_data::EraseAnimationStep,
+    GameAssets, TetrisConfig, TetrisEntity,
+    brick::{Brick, BrickNode, get_brick_node_position},
+    constants::{LINES_PER_LEVEL, MAX_LEVEL, TIMER_FALLING_SECS},
+    events::BoardEvent,
+    game_data::{Board, EraseAnimationStep, InputBuffer},
+    mode::format_countdown,
     state::GameSate,
 };
-use crate::{
-    constants::{
-        BOARD_BRICK_NODE_COLS, BOARD_BRICK_NODE_ROWS, BRICK_NODE_WIDTH,
-        BRICKS_CONTAINER_BOUNDING_LEFT, BRICKS_CONTAINER_BOUNDING_TOP, BRICKS_CONTAINER_HEIGHT,
-        BRICKS_CONTAINER_WIDTH,
-    },
-    game_data::GameData,
-};
+use crate::{constants::BRICK_NODE_WIDTH, game_data::GameData};
+
+/// The board driven by the local player's input, menus and saved games.
+#[derive(Component)]
+pub struct PlayerBoard;
+
+/// Parent of a board's grid of cells.
+#[derive(Component)]
+pub struct BoardCells;
+
+/// The falling and next bricks of every board, found by the board they are
+/// parented to.
+pub type BoardBricks<'w, 's> =
+    Query<'w, 's, (Entity, &'static Parent), Or<(With<FallingBrick>, With<NextBrick>)>>;
 
 #[derive(Component)]
 pub struct ScoreLabel;
@@ -77,6 +84,9 @@ pub struct NextBrickBoard;
 #[derive(Component)]
 pub struct NextBrick;
 
+#[derive(Component)]
+pub struct NextBrickNode;
+
 #[derive(Component)]
 pub struct BoardBrickNode;
 
@@ -115,8 +125,8 @@ fn spawn_brick_node<F>(
             ShapeBundle {
                 path: GeometryBuilder::build_as(&shapes::Rectangle {
                     extents: Vec2 {
-                        x: BRICK_NODE_WIDTH as f32,
-                        y: BRICK_NODE_WIDTH as f32,
+                        x: BRICK_NODE_WIDTH,
+                        y: BRICK_NODE_WIDTH,
                     },
                     ..shapes::Rectangle::default()
                 }),
@@ -129,8 +139,8 @@ fn spawn_brick_node<F>(
             ShapeBundle {
                 path: GeometryBuilder::build_as(&shapes::Rectangle {
                     extents: Vec2 {
-                        x: BRICK_NODE_WIDTH - 2. as f32,
-                        y: BRICK_NODE_WIDTH - 2. as f32,
+                        x: BRICK_NODE_WIDTH - 2.,
+                        y: BRICK_NODE_WIDTH - 2.,
                     },
                     ..shapes::Rectangle::default()
                 }),
@@ -143,8 +153,8 @@ fn spawn_brick_node<F>(
             ShapeBundle {
                 path: GeometryBuilder::build_as(&shapes::Rectangle {
                     extents: Vec2 {
-                        x: BRICK_NODE_WIDTH - 6. as f32,
-                        y: BRICK_NODE_WIDTH - 6. as f32,
+                        x: BRICK_NODE_WIDTH - 6.,
+                        y: BRICK_NODE_WIDTH - 6.,
                     },
                     ..shapes::Rectangle::default()
                 }),
@@ -156,19 +166,26 @@ fn spawn_brick_node<F>(
         .insert_if(bundle, condition);
 }
 
-fn spawn_board(commands: &mut ChildBuilder) {
-    (0..BOARD_BRICK_NODE_COLS)
-        .flat_map(|i| return (0..BOARD_BRICK_NODE_ROWS).map(move |j| BrickNode(i as i8, j as i8)))
+/// Outline around a board of `cols` by `rows` cells.
+fn bricks_container_size(cols: usize, rows: usize) -> Vec2 {
+    Vec2::new(
+        cols as f32 * BRICK_NODE_WIDTH + 4.,
+        rows as f32 * BRICK_NODE_WIDTH + 4.,
+    )
+}
+
+fn spawn_board(commands: &mut ChildBuilder, cols: usize, rows: usize) {
+    (0..cols)
+        .flat_map(|i| (0..rows).map(move |j| BrickNode(i as i8, j as i8)))
         .for_each(|node| {
-            let x = BRICKS_CONTAINER_BOUNDING_LEFT + (node.0 as f32 + 0.5) * BRICK_NODE_WIDTH;
-            let y = BRICKS_CONTAINER_BOUNDING_TOP - (20. - node.1 as f32 - 0.5) * BRICK_NODE_WIDTH;
+            let pos = get_brick_node_position(&node, rows);
             spawn_brick_node(
                 commands,
-                x,
-                y,
+                pos.x,
+                pos.y,
                 "#9ead86",
                 "#879372",
-                (BoardBrickNode, node.clone()),
+                (BoardBrickNode, node),
                 || true,
             )
         });
@@ -196,35 +213,43 @@ fn spawn_text(text: String, x: f32, y: f32, font: Handle<Font>, font_size: f32)
             font_size,
             font_smoothing: FontSmoothing::AntiAliased,
             font,
-            ..TextFont::default()
         },
         Transform::from_xyz(x, y, 100.),
         Anchor::TopRight,
     )
 }
 
-pub fn spawn_next_brick(commands: &mut Commands, brick: Brick) {
+pub fn spawn_next_brick(commands: &mut Commands, brick: Brick) -> Entity {
     commands
-        .spawn((NextBrick, Sprite { ..default() }))
+        .spawn((NextBrick, Sprite::default()))
         .with_children(|child_builder| {
             brick.nodes.iter().for_each(|node| {
                 let x = 60.0 + (node.0 as f32 + 0.5) * BRICK_NODE_WIDTH;
                 let y = 43.0 - (node.1 as f32 + 0.5) * BRICK_NODE_WIDTH;
-                spawn_brick_node(child_builder, x, y, "#9ead86", "#000000", (), || false);
+                spawn_brick_node(
+                    child_builder,
+                    x,
+                    y,
+                    "#9ead86",
+                    "#000000",
+                    NextBrickNode,
+                    || true,
+                );
             });
-        });
+        })
+        .id()
 }
 
 pub fn spawn_falling_brick(
     commands: &mut Commands,
     mut brick: Brick,
     falling_brick_node: BrickNode,
-) {
+    rows: usize,
+) -> Entity {
     brick.nodes.iter_mut().for_each(|node| {
-        node.0 = falling_brick_node.0 + node.0;
+        node.0 += falling_brick_node.0;
         node.1 = falling_brick_node.1 - node.1;
     });
-    println!("spawn falling brick");
     commands
         .spawn((
             Sprite {
@@ -236,155 +261,49 @@ pub fn spawn_falling_brick(
         ))
         .with_children(|child_builder| {
             brick.nodes.iter().for_each(|node| {
-                let pos = get_brick_node_position(node);
+                let pos = get_brick_node_position(node, rows);
+                let visibility = if (node.1 as usize) < rows {
+                    Visibility::Visible
+                } else {
+                    Visibility::Hidden
+                };
                 spawn_brick_node(
                     child_builder,
                     pos.x,
                     pos.y,
                     "#9ead86",
                     "#000000",
-                    (FallingBrickNode, node.clone(), Visibility::Hidden),
+                    (FallingBrickNode, *node, visibility),
                     || true,
                 );
             });
-        });
+        })
+        .id()
 }
 
 pub fn board_setup(
     mut commands: Commands,
     game_assets: Res<GameAssets>,
+    config: Res<TetrisConfig>,
     mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
 ) {
-    commands
-        .spawn((
-            ShapeBundle {
-                path: GeometryBuilder::build_as(&shapes::Rectangle {
-                    extents: Vec2 { x: 248., y: 302. },
-                    radii: Some(BorderRadii::single(2.0)),
-                    ..shapes::Rectangle::default()
-                }),
-                transform: Transform::from_xyz(0.0, 90.0, 0.0),
-                ..default()
-            },
-            Fill::color(Srgba::hex("#fae36c").unwrap()),
-        ))
-        .with_children(|child_builder| {
-            child_builder.spawn((
-                ShapeBundle {
-                    path: GeometryBuilder::build_as(&shapes::Rectangle {
-                        extents: Vec2 { x: 246., y: 298. },
-                        radii: Some(BorderRadii::single(2.0)),
-                        ..shapes::Rectangle::default()
-                    }),
-                    transform: Transform::from_xyz(0.0, 0.0, 40.0),
-                    ..default()
-                },
-                Fill::color(Srgba::hex("#9ead86").unwrap()),
-            ));
-            // bricks container
-            child_builder.spawn((
-                ShapeBundle {
-                    path: GeometryBuilder::build_as(&shapes::Rectangle {
-                        extents: Vec2 {
-                            x: BRICKS_CONTAINER_WIDTH,
-                            y: BRICKS_CONTAINER_HEIGHT,
-                        },
-                        ..shapes::Rectangle::default()
-                    }),
-                    transform: Transform::from_xyz(-40.0, 0.0, 60.0),
-                    ..default()
-                },
-                Stroke::new(Color::BLACK, 2.0),
-            ));
-        });
+    let game_data = GameData {
+        board: Board::new(config.board_cols, config.board_rows),
+        mode: config.mode,
+        tracks_finesse: true,
+        ..Default::default()
+    };
+    let board = spawn_game_board(&mut commands, &game_assets, game_data, Vec2::ZERO);
+    commands.entity(board).insert(PlayerBoard);
 
-    commands
-        .spawn((
-            Sprite {
-                color: Color::NONE,
-                ..default()
-            },
-            Transform::from_xyz(0.0, 0.0, 40.0),
-        ))
-        .with_children(spawn_board);
-
-    // score
     let padding_x: f32 = 110.0;
-    commands
-        .spawn(spawn_label("Score".into(), padding_x, 220.))
-        .insert(ScoreLabel);
-
-    commands
-        .spawn(spawn_text(
-            "0".into(),
-            padding_x,
-            200.0,
-            game_assets.font.clone(),
-            20.0,
-        ))
-        .insert(ScoreText);
-
-    // Level
-    commands
-        .spawn(spawn_label("Level".into(), padding_x, 170.))
-        .insert(LevelLabel);
-
-    commands
-        .spawn(spawn_text(
-            "0".into(),
-            padding_x,
-            150.0,
-            game_assets.font.clone(),
-            20.0,
-        ))
-        .insert(LevelText);
-
-    // cleans
-    commands
-        .spawn(spawn_label("Cleans".into(), padding_x, 120.))
-        .insert(CleansLabel);
-
-    commands
-        .spawn(spawn_text(
-            "0".into(),
-            padding_x,
-            100.0,
-            game_assets.font.clone(),
-            20.0,
-        ))
-        .insert(CleansText);
-
-    // next
-    commands
-        .spawn(spawn_label("Next".into(), padding_x, 70.))
-        .insert(NextLabel);
-
-    // next brick board
-    commands
-        .spawn((NextBrickBoard, Sprite { ..default() }))
-        .with_children(|child_builder| {
-            spawn_next_brick_board(child_builder);
-        });
-
-    // time
-    let now = Local::now();
-    let hours = now.hour();
-    let minutes = now.minute();
-    commands
-        .spawn(spawn_text(
-            format!("{}:{}", hours, minutes),
-            padding_x + 10.,
-            -26.,
-            game_assets.font.clone(),
-            16.0,
-        ))
-        .insert(TimeText);
 
     // pause
     let pause = game_assets.pause.clone();
     let layout = TextureAtlasLayout::from_grid(UVec2::new(10, 12), 2, 1, None, None);
     let texture_atlas_layout = texture_atlas_layouts.add(layout);
     commands.spawn((
+        TetrisEntity,
         Sprite::from_atlas_image(
             pause,
             TextureAtlas {
@@ -401,6 +320,7 @@ pub fn board_setup(
     let layout = TextureAtlasLayout::from_grid(UVec2::new(46, 34), 2, 1, None, None);
     let texture_atlas_layout = texture_atlas_layouts.add(layout);
     commands.spawn((
+        TetrisEntity,
         Sprite {
             image: sound,
             texture_atlas: Some(TextureAtlas {
@@ -415,6 +335,156 @@ pub fn board_setup(
     ));
 }
 
+/// Spawns a board entity holding `game_data` at `position`, with its frame,
+/// cells, next brick preview and score texts as children.
+pub fn spawn_game_board(
+    commands: &mut Commands,
+    game_assets: &GameAssets,
+    game_data: GameData,
+    position: Vec2,
+) -> Entity {
+    let (cols, rows) = (game_data.board.cols, game_data.board.rows);
+    let container_size = bricks_container_size(cols, rows);
+    commands
+        .spawn((
+            TetrisEntity,
+            game_data,
+            InputBuffer::default(),
+            Transform::from_translation(position.extend(0.)),
+            Visibility::default(),
+        ))
+        .with_children(|child_builder| {
+            child_builder
+                .spawn((
+                    ShapeBundle {
+                        path: GeometryBuilder::build_as(&shapes::Rectangle {
+                            extents: Vec2 { x: 248., y: 302. },
+                            radii: Some(BorderRadii::single(2.0)),
+                            ..shapes::Rectangle::default()
+                        }),
+                        transform: Transform::from_xyz(0.0, 90.0, 0.0),
+                        ..default()
+                    },
+                    Fill::color(Srgba::hex("#fae36c").unwrap()),
+                ))
+                .with_children(|child_builder| {
+                    child_builder.spawn((
+                        ShapeBundle {
+                            path: GeometryBuilder::build_as(&shapes::Rectangle {
+                                extents: Vec2 { x: 246., y: 298. },
+                                radii: Some(BorderRadii::single(2.0)),
+                                ..shapes::Rectangle::default()
+                            }),
+                            transform: Transform::from_xyz(0.0, 0.0, 40.0),
+                            ..default()
+                        },
+                        Fill::color(Srgba::hex("#9ead86").unwrap()),
+                    ));
+                    // bricks container
+                    child_builder.spawn((
+                        ShapeBundle {
+                            path: GeometryBuilder::build_as(&shapes::Rectangle {
+                                extents: container_size,
+                                ..shapes::Rectangle::default()
+                            }),
+                            // keep the top left corner in place for other board sizes
+                            transform: Transform::from_xyz(
+                                -112.0 + container_size.x / 2.,
+                                142.0 - container_size.y / 2.,
+                                60.0,
+                            ),
+                            ..default()
+                        },
+                        Stroke::new(Color::BLACK, 2.0),
+                    ));
+                });
+
+            child_builder
+                .spawn((
+                    BoardCells,
+                    Sprite {
+                        color: Color::NONE,
+                        ..default()
+                    },
+                    Transform::from_xyz(0.0, 0.0, 40.0),
+                ))
+                .with_children(|child_builder| spawn_board(child_builder, cols, rows));
+
+            // score
+            let padding_x: f32 = 110.0;
+            child_builder
+                .spawn(spawn_label("Score".into(), padding_x, 220.))
+                .insert(ScoreLabel);
+
+            child_builder
+                .spawn(spawn_text(
+                    "0".into(),
+                    padding_x,
+                    200.0,
+                    game_assets.font.clone(),
+                    20.0,
+                ))
+                .insert(ScoreText);
+
+            // Level
+            child_builder
+                .spawn(spawn_label("Level".into(), padding_x, 170.))
+                .insert(LevelLabel);
+
+            child_builder
+                .spawn(spawn_text(
+                    "0".into(),
+                    padding_x,
+                    150.0,
+                    game_assets.font.clone(),
+                    20.0,
+                ))
+                .insert(LevelText);
+
+            // cleans
+            child_builder
+                .spawn(spawn_label("Cleans".into(), padding_x, 120.))
+                .insert(CleansLabel);
+
+            child_builder
+                .spawn(spawn_text(
+                    "0".into(),
+                    padding_x,
+                    100.0,
+                    game_assets.font.clone(),
+                    20.0,
+                ))
+                .insert(CleansText);
+
+            // next
+            child_builder
+                .spawn(spawn_label("Next".into(), padding_x, 70.))
+                .insert(NextLabel);
+
+            // next brick board
+            child_builder
+                .spawn((NextBrickBoard, Sprite { ..default() }))
+                .with_children(|child_builder| {
+                    spawn_next_brick_board(child_builder);
+                });
+
+            // time
+            let now = Local::now();
+            let hours = now.hour();
+            let minutes = now.minute();
+            child_builder
+                .spawn(spawn_text(
+                    format!("{}:{}", hours, minutes),
+                    padding_x + 10.,
+                    -26.,
+                    game_assets.font.clone(),
+                    16.0,
+                ))
+                .insert(TimeText);
+        })
+        .id()
+}
+
 pub fn get_speed(level: u32) -> f32 {
     TIMER_FALLING_SECS * (0.85_f32).powi(level as i32) + level as f32 / 1000.0
 }
@@ -426,314 +496,229 @@ pub fn get_score(level: u32, erase_lines: u32) -> u32 {
 }
 
 pub fn get_level(clean_lines: u32) -> u32 {
-    (clean_lines / 10).min(99)
+    (clean_lines / LINES_PER_LEVEL).min(MAX_LEVEL)
+}
+
+fn update_text(text: &mut Text2d, value: String) {
+    if **text != value {
+        **text = value;
+    }
 }
 
 pub fn clock_update_system(
     time: Res<Time>,
-    mut game_data: ResMut<GameData>,
-    time_text: Single<&mut Text2d, With<TimeText>>,
+    state: Res<State<GameSate>>,
+    mut boards: Query<&mut GameData>,
+    mut time_text_query: Query<(&Parent, &mut Text2d), With<TimeText>>,
 ) {
-    let ticked = game_data.clock_timer.tick(time.delta()).finished();
-    if ticked {
-        let now = Local::now();
-        let hours = now.hour();
-        let minutes = now.minute();
-        let mut text = time_text.into_inner();
-        text.clear();
-        text.push_str(format!("{}:{}", hours, minutes).as_str());
+    for (parent, mut text) in &mut time_text_query {
+        let Ok(mut game_data) = boards.get_mut(parent.get()) else {
+            continue;
+        };
+        if let (Some(clock), GameSate::Playing | GameSate::Results) =
+            (game_data.mode.clock(game_data.play_duration), state.get())
+        {
+            update_text(&mut text, format_countdown(clock));
+            continue;
+        }
+        let ticked = game_data.clock_timer.tick(time.delta()).finished();
+        if ticked || state.is_changed() {
+            let now = Local::now();
+            let hours = now.hour();
+            let minutes = now.minute();
+            text.clear();
+            text.push_str(format!("{}:{}", hours, minutes).as_str());
+        }
     }
 }
 
-pub fn spawn_new_falling_brick(commands: &mut Commands, game_data: &mut ResMut<GameData>) {
-    game_data.falling_brick_node = game_data.new_falling_brick_node();
-    game_data.falling_brick_shape = game_data.next_brick_shape;
-    game_data.next_brick_shape = BrickShape::next();
-    spawn_falling_brick(
+pub fn start_new_game(commands: &mut Commands, board: Entity, game_data: &mut GameData, seed: u64) {
+    game_data.start(seed);
+    spawn_bricks(commands, board, game_data);
+}
+
+/// Spawns the falling and next bricks of `board` for its current `GameData`.
+pub fn spawn_bricks(commands: &mut Commands, board: Entity, game_data: &GameData) {
+    let falling_brick = spawn_falling_brick(
         commands,
         game_data.falling_brick_shape.into(),
         game_data.falling_brick_node,
+        game_data.board.rows,
     );
-    spawn_next_brick(commands, game_data.next_brick_shape.into());
+    let next_brick = spawn_next_brick(commands, game_data.next_brick_shape.into());
+    commands
+        .entity(board)
+        .add_children(&[falling_brick, next_brick]);
+    commands.send_event(BoardEvent {
+        board,
+        event: game_data.spawned(),
+    });
 }
 
-pub fn clean_board_lines(
-    board_brick_nodes_query: &mut Query<
-        (&mut Children, &BrickNode),
+/// Colors each board's cells from `Board::occupied`, flashing rows being erased.
+#[allow(clippy::type_complexity)]
+pub fn render_board_system(
+    boards: Query<Ref<GameData>>,
+    cells_query: Query<(&Parent, &Children), With<BoardCells>>,
+    board_brick_nodes_query: Query<
+        (&Children, &BrickNode),
         (With<BoardBrickNode>, Without<FallingBrickNode>),
     >,
-    game_data: &mut ResMut<GameData>,
-    fill_query: &mut Query<&mut Fill>,
-    stroke_query: &mut Query<&mut Stroke>,
+    mut fill_query: Query<&mut Fill>,
+    mut stroke_query: Query<&mut Stroke>,
 ) {
-    let clean_lines = game_data.clean_lines;
-    let start = clean_lines.0 as i8;
-    let lines = clean_lines.1 as i8;
-    for (children, node) in board_brick_nodes_query {
-        if node.1 < start {
+    let occupied_color: Color = Srgba::hex("#000000").unwrap().into();
+    let empty_color: Color = Srgba::hex("#879372").unwrap().into();
+    for (parent, cells) in &cells_query {
+        let Ok(game_data) = boards.get(parent.get()) else {
             continue;
-        }
-        let is_occupied = if node.1 + lines >= BOARD_BRICK_NODE_ROWS as i8 {
-            false
-        } else {
-            game_data
-                .board
-                .is_brick_node_occupied(&BrickNode(node.0, node.1 + lines))
         };
-        for child in children.iter() {
-            if let Ok(mut fill) = fill_query.get_mut(*child) {
-                fill.color = if is_occupied {
-                    Srgba::hex("#000000").unwrap().into()
-                } else {
-                    Srgba::hex("#879372").unwrap().into()
+        if !game_data.is_changed() {
+            continue;
+        }
+        let (start, lines) = game_data.clean_lines;
+        for (children, node) in board_brick_nodes_query.iter_many(cells) {
+            let is_erasing = game_data.erase_animation_step == EraseAnimationStep::Playing
+                && node.1 >= start as i8
+                && node.1 < (start + lines) as i8;
+            let is_occupied = if is_erasing {
+                game_data.erase_animation_index % 2 == 0
+            } else {
+                game_data.board.is_brick_node_occupied(node)
+            };
+            let color = if is_occupied {
+                occupied_color
+            } else {
+                empty_color
+            };
+            for child in children.iter() {
+                if let Ok(mut fill) = fill_query.get_mut(*child)
+                    && fill.color != color
+                {
+                    fill.color = color;
                 }
-            }
-            if let Ok(mut stroke) = stroke_query.get_mut(*child) {
-                stroke.color = if is_occupied {
-                    Srgba::hex("#000000").unwrap().into()
-                } else {
-                    Srgba::hex("#879372").unwrap().into()
+                if let Ok(mut stroke) = stroke_query.get_mut(*child)
+                    && stroke.color != color
+                {
+                    stroke.color = color;
                 }
             }
         }
     }
-    game_data.board.clean(clean_lines);
-    game_data.cleans += clean_lines.1 as u32;
 }
 
-pub fn falling_brick_system(
-    mut commands: Commands,
-    time: Res<Time>,
-    mut game_data: ResMut<GameData>,
-    mut query: Query<
-        (Entity, &mut Transform, &mut BrickNode, &mut Visibility),
-        With<FallingBrickNode>,
-    >,
-    next_brick_entity: Single<Entity, With<NextBrick>>,
-    falling_brick_entity: Single<Entity, With<FallingBrick>>,
-    mut board_brick_nodes_query: Query<
-        (&mut Children, &BrickNode),
-        (With<BoardBrickNode>, Without<FallingBrickNode>),
+/// Moves each board's falling and next brick nodes to where its `GameData`
+/// has them. Places the falling brick, easing it from its previous cell over
+/// the time left until the next fixed tick.
+#[allow(clippy::type_complexity)]
+pub fn render_bricks_system(
+    boards: Query<Ref<GameData>>,
+    fixed_time: Res<Time<Fixed>>,
+    falling_bricks: Query<(&Parent, &Children), With<FallingBrick>>,
+    next_bricks: Query<(&Parent, &Children), With<NextBrick>>,
+    mut falling_brick_query: Query<
+        (&mut Transform, &mut BrickNode, &mut Visibility),
+        (With<FallingBrickNode>, Without<NextBrickNode>),
     >,
-    mut fill_query: Query<&mut Fill>,
-    mut stroke_query: Query<&mut Stroke>,
+    mut next_brick_query: Query<&mut Transform, (With<NextBrickNode>, Without<FallingBrickNode>)>,
 ) {
-    if game_data.paused || game_data.erase_animation_step == EraseAnimationStep::Playing {
-        return;
-    }
-    let falling_brick_shape = game_data.falling_brick_shape;
-    let mut falling_brick: Brick = falling_brick_shape.into();
-    falling_brick.nodes.iter_mut().for_each(|node| {
-        node.0 = game_data.falling_brick_node.0 + node.0;
-        node.1 = game_data.falling_brick_node.1 - node.1;
-    });
-    let ticked = game_data.falling_timer.tick(time.delta()).finished();
-    if ticked {
-        if game_data.freeze {
-            return;
-        }
-        let is_hit_bottom = query
-            .iter()
-            .any(|(_, __, node, ..)| game_data.board.is_move_to_bottom(node));
-        if is_hit_bottom {
-            game_data.freeze = true;
-            game_data.is_speed_up_falling = false;
-            game_data
-                .falling_timer
-                .set_duration(Duration::from_secs_f32(TIMER_FALLING_SECS));
-
-            let is_hit_top = query
-                .iter()
-                .any(|(_, __, node, ..)| game_data.board.is_move_to_top(node));
-
-            if is_hit_top {
-                game_data.is_game_over = true;
-                return;
-            }
-
-            game_data.board.update_occupied_by_brick(&falling_brick);
-
-            let clean_lines = game_data.board.get_clean_lines();
-
-            if clean_lines.1 > 0 {
-                if game_data.erase_animation_step == EraseAnimationStep::NotStart {
-                    game_data.erase_animation_step = EraseAnimationStep::Playing;
-                    game_data.erase_animation_duration = Duration::default();
-                    game_data.clean_lines = clean_lines;
-                    game_data.erase_animation_index = 0;
-                    // update board
-                    for (children, node) in &mut board_brick_nodes_query {
-                        if game_data.board.is_brick_node_occupied(node) {
-                            for child in children.iter() {
-                                if let Ok(mut fill) = fill_query.get_mut(*child) {
-                                    fill.color = Color::BLACK;
-                                }
-                                if let Ok(mut stroke) = stroke_query.get_mut(*child) {
-                                    stroke.color = Color::BLACK;
-                                }
-                            }
-                        }
-                    }
-                    commands
-                        .entity(falling_brick_entity.into_inner())
-                        .try_despawn_recursive();
-                    commands
-                        .entity(next_brick_entity.into_inner())
-                        .try_despawn_recursive();
-
-                    spawn_new_falling_brick(&mut commands, &mut game_data);
-                } else if game_data.erase_animation_step == EraseAnimationStep::End {
-                    game_data.erase_animation_step = EraseAnimationStep::NotStart;
-                    let start = clean_lines.0 as i8;
-                    let lines = clean_lines.1 as i8;
-                    println!("start lines {} {}", start, lines);
-                    clean_board_lines(
-                        &mut board_brick_nodes_query,
-                        &mut game_data,
-                        &mut fill_query,
-                        &mut stroke_query,
-                    );
-                }
-            } else {
-                // update board
-                for (children, node) in &mut board_brick_nodes_query {
-                    if game_data.board.is_brick_node_occupied(node) {
-                        for child in children.iter() {
-                            if let Ok(mut fill) = fill_query.get_mut(*child) {
-                                fill.color = Color::BLACK;
-                            }
-                            if let Ok(mut stroke) = stroke_query.get_mut(*child) {
-                                stroke.color = Color::BLACK;
-                            }
-                        }
-                    }
-                }
-                commands
-                    .entity(falling_brick_entity.into_inner())
-                    .try_despawn_recursive();
-                commands
-                    .entity(next_brick_entity.into_inner())
-                    .try_despawn_recursive();
-
-                spawn_new_falling_brick(&mut commands, &mut game_data);
-            }
+    for (parent, children) in &falling_bricks {
+        let Ok(game_data) = boards.get(parent.get()) else {
+            continue;
+        };
+        let from = game_data.previous_falling_brick_node;
+        let to = game_data.falling_brick_node;
+        // a new brick or a kick jumps straight to its cell
+        let offset = if (from.0 - to.0).abs() <= 1 && (from.1 - to.1).abs() <= 1 {
+            Vec2::new((from.0 - to.0) as f32, (from.1 - to.1) as f32)
+                * (1. - fixed_time.overstep_fraction())
+                * BRICK_NODE_WIDTH
         } else {
-            game_data.falling_brick_node.move_down();
-            for (_, mut transform, mut brick_node, mut visibility) in query.iter_mut() {
-                transform.translation.y -= BRICK_NODE_WIDTH;
-
-                brick_node.move_down();
+            Vec2::ZERO
+        };
+        let falling_brick = game_data.falling_brick();
+        let mut brick_nodes = falling_brick_query.iter_many_mut(children);
+        for node in falling_brick.nodes.iter() {
+            let Some((mut transform, mut brick_node, mut visibility)) = brick_nodes.fetch_next()
+            else {
+                break;
+            };
+            let pos = get_brick_node_position(node, game_data.board.rows) + offset;
+            transform.translation.x = pos.x;
+            transform.translation.y = pos.y;
+            *brick_node = *node;
+            *visibility = if game_data.board.is_brick_node_in_board(node) {
+                Visibility::Visible
+            } else {
+                Visibility::Hidden
+            };
+        }
+    }
 
-                *visibility = if game_data.board.is_brick_node_in_board(&brick_node) {
-                    Visibility::Visible
-                } else {
-                    Visibility::Hidden
-                };
-            }
+    for (parent, children) in &next_bricks {
+        let Ok(game_data) = boards.get(parent.get()) else {
+            continue;
+        };
+        if !game_data.is_changed() {
+            continue;
+        }
+        let next_brick: Brick = game_data.next_brick_shape.into();
+        let mut brick_nodes = next_brick_query.iter_many_mut(children);
+        for node in next_brick.nodes.iter() {
+            let Some(mut transform) = brick_nodes.fetch_next() else {
+                break;
+            };
+            transform.translation.x = 60.0 + (node.0 as f32 + 0.5) * BRICK_NODE_WIDTH;
+            transform.translation.y = 43.0 - (node.1 as f32 + 0.5) * BRICK_NODE_WIDTH;
         }
     }
 }
 
+#[allow(clippy::type_complexity)]
 pub fn score_board_system(
-    mut game_data: ResMut<GameData>,
-    mut next_state: ResMut<NextState<GameSate>>,
-    mut query: ParamSet<(
-        Single<&mut Text2d, With<LevelText>>,
-        Single<&mut Text2d, With<ScoreText>>,
-        Single<&mut Text2d, With<CleansText>>,
+    boards: Query<&GameData>,
+    mut text_query: Query<(
+        &Parent,
+        &mut Text2d,
+        AnyOf<(&LevelText, &ScoreText, &CleansText, &CleansLabel)>,
     )>,
 ) {
-    if game_data.paused
-        || game_data.erase_animation_step == EraseAnimationStep::Playing
-        || !game_data.freeze
-    {
-        return;
-    }
-    game_data.freeze = false;
-
-    let level = get_level(game_data.cleans);
-
-    if game_data.level != level {
-        let mut level_text = query.p0().into_inner();
-        level_text.clear();
-        level_text.push_str(format!("{}", level).as_str());
-        game_data
-            .falling_timer
-            .set_duration(Duration::from_secs_f32(get_speed(level)));
-    }
-    if game_data.cleans > 0 {
-        let score = get_score(level, game_data.cleans);
-        let mut score_text = query.p1().into_inner();
-        score_text.clear();
-        score_text.push_str(format!("{}", score).as_str());
-
-        let mut cleans_text = query.p2().into_inner();
-        cleans_text.clear();
-        cleans_text.push_str(format!("{}", game_data.cleans).as_str());
-    }
-
-    if game_data.is_game_over {
-        //
-        next_state.set(GameSate::GameOver);
+    for (parent, mut text, (level, score, cleans, _)) in &mut text_query {
+        let Ok(game_data) = boards.get(parent.get()) else {
+            continue;
+        };
+        let (cleans_label, cleans_value) = if game_data.mode.is_dig() {
+            ("Garbage", game_data.board.garbage_rows() as u32)
+        } else if let Some(goal) = game_data.mode.lines_goal() {
+            ("Lines", goal.saturating_sub(game_data.cleans))
+        } else {
+            ("Cleans", game_data.cleans)
+        };
+        let value = if level.is_some() {
+            game_data.level.to_string()
+        } else if score.is_some() {
+            game_data.score.to_string()
+        } else if cleans.is_some() {
+            cleans_value.to_string()
+        } else {
+            cleans_label.to_string()
+        };
+        update_text(&mut text, value);
     }
 }
 
+/// Despawns the falling and next bricks of `board` and clears its game, the
+/// cells are redrawn empty once `GameData` changes.
 pub fn reset_game(
     commands: &mut Commands,
-    falling_brick_entity: Single<Entity, With<FallingBrick>>,
-    next_brick_entity: Single<Entity, With<NextBrick>>,
-    mut board_brick_nodes_query: Query<
-        &mut Children,
-        (With<BoardBrickNode>, Without<FallingBrickNode>),
-    >,
-    mut fill_query: Query<&mut Fill>,
-    mut stroke_query: Query<&mut Stroke>,
-    game_data: &mut ResMut<GameData>,
+    board: Entity,
+    board_bricks: &BoardBricks,
+    game_data: &mut GameData,
 ) {
-    commands
-        .entity(falling_brick_entity.into_inner())
-        .try_despawn_recursive();
-    commands
-        .entity(next_brick_entity.into_inner())
-        .try_despawn_recursive();
-
-    // reset board
-    for children in &mut board_brick_nodes_query {
-        for child in children.iter() {
-            if let Ok(mut fill) = fill_query.get_mut(*child) {
-                fill.color = Srgba::hex("#879372").unwrap().into();
-            }
-            if let Ok(mut stroke) = stroke_query.get_mut(*child) {
-                stroke.color = Srgba::hex("#879372").unwrap().into();
-            }
+    for (entity, parent) in board_bricks {
+        if parent.get() == board {
+            commands.entity(entity).try_despawn_recursive();
         }
     }
-
     game_data.reset();
 }
-
-pub fn game_over_system(
-    mut commands: Commands,
-    mut game_data: ResMut<GameData>,
-    next_brick_entity: Single<Entity, With<NextBrick>>,
-    falling_brick_entity: Single<Entity, With<FallingBrick>>,
-    board_brick_nodes_query: Query<
-        &mut Children,
-        (With<BoardBrickNode>, Without<FallingBrickNode>),
-    >,
-    fill_query: Query<&mut Fill>,
-    stroke_query: Query<&mut Stroke>,
-    mut next_state: ResMut<NextState<GameSate>>,
-) {
-    println!("Game Over");
-    reset_game(
-        &mut commands,
-        falling_brick_entity,
-        next_brick_entity,
-        board_brick_nodes_query,
-        fill_query,
-        stroke_query,
-        &mut game_data,
-    );
-    next_state.set(GameSate::Ready);
-}
@@ -1,8 +1,9 @@
-use crate::constants::{BRICKS_CONTAINER_BOUNDING_LEFT, BRICKS_CONTAINER_BOUNDING_TOP, BRICKS_DICT, BRICK_NODE_WIDTH};
+use crate::constants::{BRICKS_CONTAINER_BOUNDING_LEFT, BRICKS_CONTAINER_BOUNDING_TOP, BRICKS_DICT, BRICK_MASKS, BRICK_NODE_WIDTH};
 use bevy::{ecs::component::Component, math::Vec2};
-use rand::{Rng, rng};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Default, Debug, Component, PartialEq, Eq)]
+#[derive(Clone, Copy, Default, Debug, Component, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct BrickNode(pub i8, pub i8);
 
 impl BrickNode {
@@ -36,32 +37,54 @@ pub struct Brick {
     pub nodes: [BrickNode; 4],
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
-pub struct BrickShape(usize, usize);
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct BrickShape(pub(crate) usize, pub(crate) usize);
 
 impl BrickShape {
-    pub fn rand() -> Self {
-        let index = rng().random_range(0..7);
+    pub fn rand(rng: &mut impl Rng) -> Self {
+        let index = rng.random_range(0..7);
         BrickShape(index, 0)
     }
     pub fn rotate(&self) -> Self {
         Self(self.0, (self.1 + 1) % BRICKS_DICT[self.0].len())
     }
-    pub fn next() -> Self {
-        Self::rand()
+    pub fn next(rng: &mut impl Rng) -> Self {
+        Self::rand(rng)
+    }
+    pub fn is_t(&self) -> bool {
+        self.0 == 6
+    }
+    /// Whether the shape and rotation exist, e.g. after loading a saved game.
+    pub fn is_valid(&self) -> bool {
+        self.0 < BRICKS_DICT.len() && self.1 < BRICKS_DICT[self.0].len()
+    }
+    /// The brick's cells as row masks, see `BRICK_MASKS`.
+    pub fn mask(&self) -> [u8; 4] {
+        BRICK_MASKS[self.0][self.1]
     }
 }
 
 impl From<BrickShape> for Brick {
     fn from(value: BrickShape) -> Self {
-        BRICKS_DICT[value.0][value.1].clone()
+        BRICKS_DICT[value.0][value.1]
     }
 }
 
-pub fn get_brick_node_position(node: &BrickNode) -> Vec2 {
+/// The cells of `shape` hanging down and right from `node` on the board.
+pub fn brick_at(shape: BrickShape, node: BrickNode) -> Brick {
+    let mut brick: Brick = shape.into();
+    brick.nodes.iter_mut().for_each(|brick_node| {
+        brick_node.0 += node.0;
+        brick_node.1 = node.1 - brick_node.1;
+    });
+    brick
+}
+
+/// Center of a cell on a board with `rows` rows, hanging from the top left.
+pub fn get_brick_node_position(node: &BrickNode, rows: usize) -> Vec2 {
     let x = BRICKS_CONTAINER_BOUNDING_LEFT + (node.0 as f32 + 0.5) * BRICK_NODE_WIDTH;
-    let y = BRICKS_CONTAINER_BOUNDING_TOP - (20. - node.1 as f32 - 0.5) * BRICK_NODE_WIDTH;
-    return Vec2 { x, y };
+    let y = BRICKS_CONTAINER_BOUNDING_TOP - (rows as f32 - node.1 as f32 - 0.5) * BRICK_NODE_WIDTH;
+    Vec2 { x, y }
 }
 
 #[cfg(test)]
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use bevy::math::Vec2;
 use lazy_static::lazy_static;
 
@@ -9,17 +11,76 @@ pub const BOARD_BRICK_NODE_ROWS: usize = 20;
 
 pub const BOARD_BRICK_NODE_COLS: usize = 10;
 
-pub const BOARD_BRICK_NODE_TOTAL: usize = 200;
-
 pub const BRICK_NODE_WIDTH: f32 = 14.;
 
 pub const BRICKS_CONTAINER_WIDTH: f32 = 144.0;
 
-pub const BRICKS_CONTAINER_HEIGHT: f32 = 284.0;
-
 pub const TIMER_FALLING_SECS: f32 = 0.725;
 
-pub const TIMER_FALLING_SPEED_UP_SECS: f32 = 1. / 60.;
+/// Length of one simulation tick, game rules advance at 60 Hz.
+pub const TICK_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// Lines that finish a Marathon, 0 for an endless game.
+pub const MARATHON_LINES: [u32; 3] = [0, 150, 200];
+
+pub const LINES_PER_LEVEL: u32 = 10;
+
+pub const MAX_LEVEL: u32 = 99;
+
+/// Lines per level under the variable goal, multiplied by the next level.
+pub const VARIABLE_GOAL_LINES: u32 = 5;
+
+pub const REPLAY_MIN_SPEED: f32 = 0.25;
+
+pub const REPLAY_MAX_SPEED: f32 = 8.;
+
+/// Ticks skipped by one seek step during replay playback, 5 seconds.
+pub const REPLAY_SEEK_TICKS: u64 = 300;
+
+pub const ULTRA_DURATION_SECS: [u64; 3] = [120, 180, 300];
+
+pub const DIG_GARBAGE_ROWS: [u32; 2] = [5, 10];
+
+pub const DIG_SURVIVAL_GARBAGE_ROWS: u32 = 4;
+
+pub const DIG_SURVIVAL_INTERVAL_SECS: f32 = 10.;
+
+/// Rows of scattered garbage for each B-Type height, as on the NES.
+pub const B_TYPE_HEIGHT_ROWS: [u32; 6] = [0, 3, 5, 8, 10, 12];
+
+pub const B_TYPE_LINES: u32 = 25;
+
+pub const B_TYPE_BONUS: u32 = 1000;
+
+pub const HIGH_SCORE_TABLE_SIZE: usize = 10;
+
+pub const HIGH_SCORE_CYCLE_SECS: f32 = 2.;
+
+/// Seconds without input on the Ready screen before the demo starts.
+pub const DEMO_IDLE_SECS: f32 = 15.;
+
+/// Length of the demo, one minute.
+pub const DEMO_TICKS: u64 = 3600;
+
+/// Ticks between the inputs of a bot's move.
+pub const TBP_MOVE_TICKS: u32 = 2;
+
+/// Where the relay server listens and clients connect by default.
+pub const DEFAULT_RELAY_ADDR: &str = "127.0.0.1:7878";
+
+/// Ticks between snapshots of a player's board sent to the opponent online,
+/// 2 seconds.
+pub const NET_SNAPSHOT_INTERVAL_TICKS: u64 = 120;
+
+/// Frames of the opponent's board left queued online, any more are played at
+/// once so their board catches up after a stall.
+pub const NET_MAX_LAG_TICKS: usize = 10;
+
+/// Versus boards are shrunk to fit side by side in the window.
+pub const VERSUS_BOARD_SCALE: f32 = 0.7;
+
+/// Distance of each versus board's center from the middle of the window.
+pub const VERSUS_BOARD_OFFSET_X: f32 = 90.;
 
 pub const BRICKS_CONTAINER_BOUNDING_LEFT: f32 = -109.;
 
@@ -193,4 +254,22 @@ lazy_static! {
             },
         ]
     ];
+    /// Each shape and rotation of `BRICKS_DICT` as one bit mask per row: bit
+    /// `dx` of row `dy` is the cell at `(node.x + dx, node.y - dy)`.
+    pub static ref BRICK_MASKS: Vec<Vec<[u8; 4]>> = BRICKS_DICT
+        .iter()
+        .map(|rotations| {
+            rotations
+                .iter()
+                .map(|brick| {
+                    let mut mask = [0; 4];
+                    brick
+                        .nodes
+                        .iter()
+                        .for_each(|node| mask[node.1 as usize] |= 1 << node.0);
+                    mask
+                })
+                .collect()
+        })
+        .collect();
 }
@@ -1,36 +1,30 @@
-use std::time::Duration;
-
+use crate::{
+    GameAssets, TetrisEntity,
+    board::{BoardBricks, PauseIcon, PlayerBoard, reset_game, start_new_game},
+    game_data::{GameAction, GameData, InputBuffer},
+    high_score::{HighScores, InitialsEntry, save_high_score},
+    net::{NetClient, RemoteBoard},
+    replay::save_replay,
+    save::{SaveSlot, autosave},
+    state::GameSate,
+};
 use bevy::{
     color::Color,
     ecs::{
         component::Component,
         entity::Entity,
-        query::{With, Without},
+        query::With,
         system::{Commands, ParamSet, Query, Res, ResMut, Single},
     },
-    hierarchy::{BuildChildren, Children},
-    input::{mouse::MouseButton, ButtonInput},
+    hierarchy::BuildChildren,
+    input::{ButtonInput, keyboard::KeyCode, mouse::MouseButton},
     math::{Vec2, Vec3},
-    render::view::Visibility,
     sprite::Sprite,
     state::state::NextState,
     text::{FontSmoothing, JustifyText, Text2d, TextColor, TextFont, TextLayout},
-    transform::components::Transform,
+    transform::components::{GlobalTransform, Transform},
     window::Window,
 };
-use bevy_prototype_lyon::draw::{Fill, Stroke};
-
-use crate::{
-    GameAssets,
-    board::{
-        BoardBrickNode, FallingBrick, FallingBrickNode, NextBrick, PauseIcon, reset_game,
-        spawn_new_falling_brick,
-    },
-    brick::{Brick, BrickNode, get_brick_node_position},
-    constants::{BOARD_BRICK_NODE_COLS, BRICK_NODE_WIDTH, TIMER_FALLING_SPEED_UP_SECS},
-    game_data::GameData,
-    state::GameSate,
-};
 
 #[derive(PartialEq, Eq)]
 pub enum ButtonName {
@@ -69,6 +63,7 @@ pub fn control_setup(mut commands: Commands, game_assets: Res<GameAssets>) {
     // rotate
     commands
         .spawn((
+            TetrisEntity,
             Sprite {
                 image: game_assets.move_button.clone(),
                 custom_size: Some(Vec2::new(50.0, 50.)),
@@ -94,6 +89,7 @@ pub fn control_setup(mut commands: Commands, game_assets: Res<GameAssets>) {
     // move to right
     commands
         .spawn((
+            TetrisEntity,
             Sprite {
                 image: game_assets.move_button.clone(),
                 custom_size: Some(Vec2::new(50.0, 50.)),
@@ -119,6 +115,7 @@ pub fn control_setup(mut commands: Commands, game_assets: Res<GameAssets>) {
     // move to down
     commands
         .spawn((
+            TetrisEntity,
             Sprite {
                 image: game_assets.move_button.clone(),
                 custom_size: Some(Vec2::new(50.0, 50.)),
@@ -144,6 +141,7 @@ pub fn control_setup(mut commands: Commands, game_assets: Res<GameAssets>) {
     // move to left
     commands
         .spawn((
+            TetrisEntity,
             Sprite {
                 image: game_assets.move_button.clone(),
                 custom_size: Some(Vec2::new(50.0, 50.)),
@@ -169,6 +167,7 @@ pub fn control_setup(mut commands: Commands, game_assets: Res<GameAssets>) {
     // drop
     commands
         .spawn((
+            TetrisEntity,
             Sprite {
                 image: game_assets.move_button.clone(),
                 custom_size: Some(Vec2::new(50.0, 50.0)),
@@ -194,6 +193,7 @@ pub fn control_setup(mut commands: Commands, game_assets: Res<GameAssets>) {
     // pause
     commands
         .spawn((
+            TetrisEntity,
             Sprite {
                 image: game_assets.effect_button.clone(),
                 custom_size: Some(Vec2::new(30.0, 30.0)),
@@ -224,6 +224,7 @@ pub fn control_setup(mut commands: Commands, game_assets: Res<GameAssets>) {
     // sound
     commands
         .spawn((
+            TetrisEntity,
             Sprite {
                 image: game_assets.effect_button.clone(),
                 custom_size: Some(Vec2::new(30.0, 30.0)),
@@ -253,6 +254,7 @@ pub fn control_setup(mut commands: Commands, game_assets: Res<GameAssets>) {
     // replay
     commands
         .spawn((
+            TetrisEntity,
             Sprite {
                 image: game_assets.replay_button.clone(),
                 custom_size: Some(Vec2::new(30.0, 30.0)),
@@ -283,7 +285,7 @@ pub fn control_setup(mut commands: Commands, game_assets: Res<GameAssets>) {
 }
 
 pub fn control_on_click(
-    mut query: Query<(&ControlButton, &mut Sprite, &Transform)>,
+    mut query: Query<(&ControlButton, &mut Sprite, &GlobalTransform)>,
     window: Single<&Window>,
     game_assets: Res<GameAssets>,
     mouse_button_input: Res<ButtonInput<MouseButton>>,
@@ -297,10 +299,7 @@ pub fn control_on_click(
         for (control_button, mut sprite, transform) in query.iter_mut() {
             let button_size = sprite.custom_size.unwrap();
             let is_hit = is_hit_button(
-                Vec2 {
-                    x: transform.translation.x,
-                    y: transform.translation.y,
-                },
+                transform.translation().truncate(),
                 mouse_world_pos,
                 button_size.x / 2.0,
             );
@@ -332,10 +331,7 @@ pub fn control_on_click(
         for (control_button, mut sprite, transform) in query.iter_mut() {
             let button_size = sprite.custom_size.unwrap();
             let is_hit = is_hit_button(
-                Vec2 {
-                    x: transform.translation.x,
-                    y: transform.translation.y,
-                },
+                transform.translation().truncate(),
                 mouse_world_pos,
                 button_size.x / 2.0,
             );
@@ -363,9 +359,10 @@ pub fn control_on_click(
 
 pub fn control_drop_to_start_game(
     mut next_state: ResMut<NextState<GameSate>>,
+    net_client: Option<Res<NetClient>>,
     window: Single<&Window>,
     mouse_button_input: Res<ButtonInput<MouseButton>>,
-    query: Query<(&Transform, &ControlButton, &Sprite)>,
+    query: Query<(&GlobalTransform, &ControlButton, &Sprite)>,
 ) {
     if mouse_button_input.just_pressed(MouseButton::Left) {
         for (transform, control_button, sprite) in query.iter() {
@@ -376,37 +373,157 @@ pub fn control_drop_to_start_game(
                     window.height() / 2.0 - mouse_pos.y,
                 );
                 if is_hit_button(
-                    Vec2 {
-                        x: transform.translation.x,
-                        y: transform.translation.y,
-                    },
+                    transform.translation().truncate(),
                     mouse_world_pos,
                     sprite.custom_size.unwrap().x / 2.0,
                 ) {
-                    next_state.set(GameSate::Playing);
+                    // online, the match starts once the relay sends the request back
+                    match &net_client {
+                        Some(net_client) => net_client.request_match(),
+                        None => next_state.set(GameSate::Playing),
+                    }
                 }
             }
         }
     }
 }
 
-fn falling_brick_nodes_any<F>(brick: &Brick, condition: F) -> bool
-where
-    F: FnMut(&BrickNode) -> bool,
-{
-    brick.nodes.iter().any(condition)
+pub fn control_resume_game(
+    mut next_state: ResMut<NextState<GameSate>>,
+    mut save_slot: ResMut<SaveSlot>,
+    window: Single<&Window>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    query: Query<(&GlobalTransform, &ControlButton, &Sprite)>,
+) {
+    if save_slot.save.is_none() || !mouse_button_input.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let mouse_world_pos = get_world_mouse_pos(
+        window.cursor_position().unwrap(),
+        window.width(),
+        window.height(),
+    );
+    let is_hit = query.iter().any(|(transform, control_button, sprite)| {
+        control_button.0 == ButtonName::Down
+            && is_hit_button(
+                transform.translation().truncate(),
+                mouse_world_pos,
+                sprite.custom_size.unwrap().x / 2.0,
+            )
+    });
+    if is_hit {
+        save_slot.resume = true;
+        next_state.set(GameSate::Playing);
+    }
+}
+
+pub fn control_select_mode(
+    window: Single<&Window>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    query: Query<(&GlobalTransform, &ControlButton, &Sprite)>,
+    mut game_data: Single<&mut GameData, With<PlayerBoard>>,
+) {
+    if mouse_button_input.just_pressed(MouseButton::Left) {
+        let mouse_world_pos = get_world_mouse_pos(
+            window.cursor_position().unwrap(),
+            window.width(),
+            window.height(),
+        );
+        for (transform, control_button, sprite) in query.iter() {
+            let is_hit = is_hit_button(
+                transform.translation().truncate(),
+                mouse_world_pos,
+                sprite.custom_size.unwrap().x / 2.0,
+            );
+            if !is_hit {
+                continue;
+            }
+            match control_button.0 {
+                ButtonName::Left => game_data.mode = game_data.mode.prev(),
+                ButtonName::Right => game_data.mode = game_data.mode.next(),
+                ButtonName::Rotate => game_data.mode = game_data.mode.next_option(),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn control_drop_to_close_results(
+    mut commands: Commands,
+    board_bricks: BoardBricks,
+    query: Query<(&GlobalTransform, &ControlButton, &Sprite)>,
+    window: Single<&Window>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    player_board: Single<(Entity, &mut GameData), With<PlayerBoard>>,
+    mut high_scores: ResMut<HighScores>,
+    mut initials_entry: ResMut<InitialsEntry>,
+    mut next_state: ResMut<NextState<GameSate>>,
+) {
+    if !mouse_button_input.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let mouse_world_pos = get_world_mouse_pos(
+        window.cursor_position().unwrap(),
+        window.width(),
+        window.height(),
+    );
+    let is_hit = query.iter().any(|(transform, control_button, sprite)| {
+        control_button.0 == ButtonName::Drop
+            && is_hit_button(
+                transform.translation().truncate(),
+                mouse_world_pos,
+                sprite.custom_size.unwrap().x / 2.0,
+            )
+    });
+    if is_hit {
+        let (board, mut game_data) = player_board.into_inner();
+        save_high_score(&game_data, &mut high_scores, &mut initials_entry);
+        reset_game(&mut commands, board, &board_bricks, &mut game_data);
+        next_state.set(GameSate::Ready);
+    }
+}
+
+pub fn control_initials_entry(
+    window: Single<&Window>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    query: Query<(&GlobalTransform, &ControlButton, &Sprite)>,
+    mut initials_entry: ResMut<InitialsEntry>,
+) {
+    if !initials_entry.active || !mouse_button_input.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let mouse_world_pos = get_world_mouse_pos(
+        window.cursor_position().unwrap(),
+        window.width(),
+        window.height(),
+    );
+    for (transform, control_button, sprite) in query.iter() {
+        let is_hit = is_hit_button(
+            transform.translation().truncate(),
+            mouse_world_pos,
+            sprite.custom_size.unwrap().x / 2.0,
+        );
+        if !is_hit {
+            continue;
+        }
+        match control_button.0 {
+            ButtonName::Rotate => initials_entry.next_letter(),
+            ButtonName::Down => initials_entry.prev_letter(),
+            ButtonName::Left => initials_entry.move_left(),
+            ButtonName::Right => initials_entry.move_right(),
+            _ => {}
+        }
+    }
 }
 
 pub fn control_direction_system(
-    mut query: Query<(&ControlButton, &Sprite, &Transform), Without<FallingBrickNode>>,
+    query: Query<(&ControlButton, &Sprite, &GlobalTransform)>,
     window: Single<&Window>,
     mouse_button_input: Res<ButtonInput<MouseButton>>,
-    mut game_data: ResMut<GameData>,
-    mut falling_brick_query: Query<
-        (&mut Transform, &mut BrickNode, &mut Visibility),
-        With<FallingBrickNode>,
-    >,
+    player_board: Single<(&GameData, &mut InputBuffer), With<PlayerBoard>>,
 ) {
+    let (game_data, mut input_buffer) = player_board.into_inner();
     if game_data.paused {
         return;
     }
@@ -416,162 +533,36 @@ pub fn control_direction_system(
             window.width(),
             window.height(),
         );
-        for (control_button, sprite, transform) in query.iter_mut() {
+        for (control_button, sprite, transform) in query.iter() {
             let button_size = sprite.custom_size.unwrap();
             let is_hit = is_hit_button(
-                Vec2 {
-                    x: transform.translation.x,
-                    y: transform.translation.y,
-                },
+                transform.translation().truncate(),
                 mouse_world_pos,
                 button_size.x / 2.0,
             );
             if !is_hit {
                 continue;
             }
-            let mut falling_brick: Brick = game_data.falling_brick_shape.into();
-            falling_brick.nodes.iter_mut().for_each(|node| {
-                node.0 = game_data.falling_brick_node.0 + node.0;
-                node.1 = game_data.falling_brick_node.1 - node.1;
-            });
-
-            match control_button.0 {
-                ButtonName::Down => {
-                    let disabled = falling_brick_nodes_any(&falling_brick, |node| {
-                        game_data.board.is_move_to_bottom(node)
-                    });
-                    if !disabled {
-                        game_data.falling_brick_node.move_down();
-                        for (mut transform, mut brick_node, mut visibility) in
-                            falling_brick_query.iter_mut()
-                        {
-                            brick_node.move_down();
-                            transform.translation.y -= BRICK_NODE_WIDTH;
-                            *visibility = if game_data.board.is_brick_node_in_board(&brick_node) {
-                                Visibility::Visible
-                            } else {
-                                Visibility::Hidden
-                            };
-                        }
-                    }
-                }
-                ButtonName::Right => {
-                    if game_data.paused {
-                        return;
-                    }
-                    let disabled = falling_brick_nodes_any(&falling_brick, |node| {
-                        game_data.board.is_move_to_right(node)
-                    });
-                    if !disabled {
-                        game_data.falling_brick_node.move_right();
-                        for (mut transform, mut brick_node, ..) in falling_brick_query.iter_mut() {
-                            brick_node.move_right();
-                            transform.translation.x += BRICK_NODE_WIDTH;
-                        }
-                    }
-                }
-                ButtonName::Left => {
-                    if game_data.paused {
-                        return;
-                    }
-                    let disabled = falling_brick_nodes_any(&falling_brick, |node| {
-                        game_data.board.is_move_to_left(node)
-                    });
-                    if !disabled {
-                        game_data.falling_brick_node.move_left();
-                        for (mut transform, mut brick_node, ..) in falling_brick_query.iter_mut() {
-                            brick_node.move_left();
-                            transform.translation.x -= BRICK_NODE_WIDTH;
-                        }
-                    }
-                }
-                ButtonName::Rotate => {
-                    if game_data.paused {
-                        return;
-                    }
-                    let rotated = game_data.falling_brick_shape.rotate();
-                    game_data.falling_brick_shape = rotated;
-                    let mut rotated_falling_brick: Brick = game_data.falling_brick_shape.into();
-                    let mut bounding = (0, 0, 0, 0);
-                    rotated_falling_brick.nodes.iter_mut().for_each(|node| {
-                        node.0 = game_data.falling_brick_node.0 + node.0;
-                        node.1 = game_data.falling_brick_node.1 - node.1;
-                        if node.0 < bounding.0 {
-                            bounding.0 = node.0;
-                        };
-                        if node.1 < bounding.3 {
-                            bounding.3 = node.1;
-                        };
-                        if node.0 > bounding.1 {
-                            bounding.1 = node.0;
-                        };
-                    });
-
-                    if bounding.0 < 0 {
-                        game_data.falling_brick_node.move_left_steps(-bounding.0);
-                    } else if (bounding.1 as usize) >= BOARD_BRICK_NODE_COLS {
-                        game_data
-                            .falling_brick_node
-                            .move_right_steps(BOARD_BRICK_NODE_COLS as i8 - bounding.1);
-                    }
-                    if bounding.3 < 0 {
-                        game_data.falling_brick_node.move_up_steps(-bounding.3);
-                    }
-
-                    let mut new_falling_brick: Brick = game_data.falling_brick_shape.into();
-                    new_falling_brick.nodes.iter_mut().for_each(|node| {
-                        node.0 = game_data.falling_brick_node.0 + node.0;
-                        node.1 = game_data.falling_brick_node.1 - node.1;
-                    });
-
-                    for (i, (mut transform, mut brick_node, mut visibility)) in
-                        falling_brick_query.iter_mut().enumerate()
-                    {
-                        let pos = get_brick_node_position(&new_falling_brick.nodes[i]);
-                        transform.translation.x = pos.x;
-                        transform.translation.y = pos.y;
-                        brick_node.0 = new_falling_brick.nodes[i].0;
-                        brick_node.1 = new_falling_brick.nodes[i].1;
-
-                        *visibility = if game_data.board.is_brick_node_in_board(&brick_node) {
-                            Visibility::Visible
-                        } else {
-                            Visibility::Hidden
-                        };
-                    }
-                }
-                ButtonName::Drop => {
-                    if game_data.paused {
-                        return;
-                    }
-                    if game_data.is_speed_up_falling {
-                        return;
-                    }
-                    game_data.is_speed_up_falling = true;
-                    game_data
-                        .falling_timer
-                        .set_duration(Duration::from_secs_f32(TIMER_FALLING_SPEED_UP_SECS));
-                }
-                _ => {}
-            }
+            let action = match control_button.0 {
+                ButtonName::Down => GameAction::Down,
+                ButtonName::Right => GameAction::Right,
+                ButtonName::Left => GameAction::Left,
+                ButtonName::Rotate => GameAction::Rotate,
+                ButtonName::Drop => GameAction::Drop,
+                _ => continue,
+            };
+            input_buffer.push(action);
         }
     }
 }
 
 pub fn replay_game_system(
     mut commands: Commands,
-    falling_brick_entity: Single<Entity, With<FallingBrick>>,
-    next_brick_entity: Single<Entity, With<NextBrick>>,
-    board_brick_nodes_query: Query<
-        &mut Children,
-        (With<BoardBrickNode>, Without<FallingBrickNode>),
-    >,
-    fill_query: Query<&mut Fill>,
-    stroke_query: Query<&mut Stroke>,
-    query: Single<(&Sprite, &Transform), With<ReplayButton>>,
+    board_bricks: BoardBricks,
+    query: Single<(&Sprite, &GlobalTransform), With<ReplayButton>>,
     window: Single<&Window>,
     mouse_button_input: Res<ButtonInput<MouseButton>>,
-    mut game_data: ResMut<GameData>,
+    player_board: Single<(Entity, &mut GameData), With<PlayerBoard>>,
 ) {
     if mouse_button_input.just_pressed(MouseButton::Left) {
         let mouse_world_pos = get_world_mouse_pos(
@@ -582,38 +573,36 @@ pub fn replay_game_system(
         let (sprite, transform) = query.into_inner();
         let button_size = sprite.custom_size.unwrap();
         let is_hit = is_hit_button(
-            Vec2 {
-                x: transform.translation.x,
-                y: transform.translation.y,
-            },
+            transform.translation().truncate(),
             mouse_world_pos,
             button_size.x / 2.0,
         );
-        if is_hit {
-            reset_game(
-                &mut commands,
-                falling_brick_entity,
-                next_brick_entity,
-                board_brick_nodes_query,
-                fill_query,
-                stroke_query,
-                &mut game_data,
-            );
+        let (board, mut game_data) = player_board.into_inner();
+        // restarting one side of a versus match would leave the other running
+        if is_hit && !game_data.mode.is_versus() {
+            save_replay(&game_data);
+            reset_game(&mut commands, board, &board_bricks, &mut game_data);
 
-            spawn_new_falling_brick(&mut commands, &mut game_data);
+            start_new_game(&mut commands, board, &mut game_data, rand::random());
         }
     }
 }
 
+#[allow(clippy::type_complexity)]
 pub fn pause_game_system(
     window: Single<&Window>,
     mouse_button_input: Res<ButtonInput<MouseButton>>,
-    mut game_data: ResMut<GameData>,
+    mut game_data: Single<&mut GameData, With<PlayerBoard>>,
+    remote_boards: Query<(), With<RemoteBoard>>,
     mut query: ParamSet<(
-        Single<(&Sprite, &Transform), With<PauseButton>>,
-        Single<&mut Sprite, With<PauseIcon>>
+        Single<(&Sprite, &GlobalTransform), With<PauseButton>>,
+        Single<&mut Sprite, With<PauseIcon>>,
     )>,
 ) {
+    // an online opponent can't be paused
+    if !remote_boards.is_empty() {
+        return;
+    }
     if mouse_button_input.just_pressed(MouseButton::Left) {
         let mouse_world_pos = get_world_mouse_pos(
             window.cursor_position().unwrap(),
@@ -623,15 +612,15 @@ pub fn pause_game_system(
         let (sprite, transform) = query.p0().into_inner();
         let button_size = sprite.custom_size.unwrap();
         let is_hit = is_hit_button(
-            Vec2 {
-                x: transform.translation.x,
-                y: transform.translation.y,
-            },
+            transform.translation().truncate(),
             mouse_world_pos,
             button_size.x / 2.0,
         );
         if is_hit {
             game_data.paused = !game_data.paused;
+            if game_data.paused {
+                autosave(&game_data);
+            }
 
             let mut sprite = query.p1().into_inner();
             if let Some(atlas) = &mut sprite.texture_atlas {
@@ -640,3 +629,27 @@ pub fn pause_game_system(
         }
     }
 }
+
+/// Zen practice hotkeys: R restarts with the same seed, Z undoes and Y redoes a placement.
+pub fn zen_hotkeys_system(
+    mut commands: Commands,
+    board_bricks: BoardBricks,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    player_board: Single<(Entity, &mut GameData, &mut InputBuffer), With<PlayerBoard>>,
+) {
+    let (board, mut game_data, mut input_buffer) = player_board.into_inner();
+    if !game_data.mode.has_undo() {
+        return;
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyR) {
+        let seed = game_data.seed;
+        save_replay(&game_data);
+        reset_game(&mut commands, board, &board_bricks, &mut game_data);
+        input_buffer.clear();
+        start_new_game(&mut commands, board, &mut game_data, seed);
+    } else if keyboard_input.just_pressed(KeyCode::KeyZ) {
+        input_buffer.push(GameAction::Undo);
+    } else if keyboard_input.just_pressed(KeyCode::KeyY) {
+        input_buffer.push(GameAction::Redo);
+    }
+}
@@ -6,12 +6,9 @@ use bevy::transform::components::Transform;
 use bevy::utils::default;
 use bevy::window::Window;
 use bevy::{color::Srgba, ecs::system::Single};
-use bevy_prototype_lyon::{
-    prelude::*,
-    shapes::{BorderRadii},
-};
+use bevy_prototype_lyon::{prelude::*, shapes::BorderRadii};
 
-use crate::constants::DESIGN_SIZE;
+use crate::{TetrisEntity, constants::DESIGN_SIZE};
 
 #[derive(Component)]
 pub struct Decorate;
@@ -19,7 +16,7 @@ pub struct Decorate;
 fn calc_decorate_size(design_size: &Vec2, window_size: &Vec2) -> Vec2 {
     let rx: f32 = window_size.x / design_size.x;
     let ry = window_size.y / design_size.y;
-    let size = if rx > ry {
+    if rx > ry {
         Vec2 {
             x: ry * design_size.x,
             y: window_size.y,
@@ -29,8 +26,7 @@ fn calc_decorate_size(design_size: &Vec2, window_size: &Vec2) -> Vec2 {
             x: window_size.x,
             y: rx * design_size.y,
         }
-    };
-    size
+    }
 }
 
 pub fn decorate_setup(mut commands: Commands, window: Single<&Window>) {
@@ -41,6 +37,7 @@ pub fn decorate_setup(mut commands: Commands, window: Single<&Window>) {
         ..shapes::Rectangle::default()
     };
     commands.spawn((
+        TetrisEntity,
         ShapeBundle {
             path: GeometryBuilder::build_as(&rect),
             ..default()
@@ -55,6 +52,7 @@ pub fn decorate_setup(mut commands: Commands, window: Single<&Window>) {
     };
 
     commands.spawn((
+        TetrisEntity,
         ShapeBundle {
             path: GeometryBuilder::build_as(&board_border_rect),
             transform: Transform::from_xyz(0.0, 90.0, 0.0),
@@ -62,4 +60,4 @@ pub fn decorate_setup(mut commands: Commands, window: Single<&Window>) {
         },
         Stroke::new(Color::BLACK, 4.0),
     ));
-}
\ No newline at end of file
+}
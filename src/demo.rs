@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use bevy::{
+    ecs::{
+        entity::Entity,
+        query::With,
+        system::{Commands, Query, Res, ResMut, Resource, Single},
+    },
+    input::{ButtonInput, gamepad::Gamepad, keyboard::KeyCode, mouse::MouseButton},
+    state::state::{NextState, State},
+    time::Time,
+};
+
+use crate::{
+    ai::{AiLevel, AiPlayer, AiWeights},
+    board::{BoardBricks, PlayerBoard, reset_game},
+    constants::{DEMO_IDLE_SECS, DEMO_TICKS},
+    game_data::{Board, GameData},
+    mode::GameMode,
+    replay::{Replay, ReplayPlayer},
+    state::GameSate,
+};
+
+/// The attract mode: left alone on the Ready screen, the board plays a game
+/// the CPU recorded. It goes through replay playback, so nothing it does is
+/// ranked or saved.
+#[derive(Debug, Default, Resource)]
+pub struct Demo {
+    /// Time on the Ready screen since the last input.
+    pub idle: Duration,
+    pub active: bool,
+    /// The mode selected before the demo, put back after it.
+    pub mode: GameMode,
+}
+
+pub fn is_demo(demo: Res<Demo>) -> bool {
+    demo.active
+}
+
+/// Has the CPU play a game on an empty board like `board` and returns its
+/// replay.
+pub fn record_demo(board: &Board, seed: u64, weights: &AiWeights) -> Replay {
+    let mut game_data = GameData {
+        board: board.cleared(),
+        ..Default::default()
+    };
+    game_data.start(seed);
+    let mut ai_player = AiPlayer::new(AiLevel::Normal, seed);
+    while game_data.tick < DEMO_TICKS && game_data.ended.is_none() {
+        let action = ai_player.act(&game_data, weights);
+        game_data.tick_with(action);
+    }
+    game_data.replay
+}
+
+fn any_input(
+    keyboard_input: &ButtonInput<KeyCode>,
+    mouse_button_input: &ButtonInput<MouseButton>,
+    gamepads: &Query<&Gamepad>,
+) -> bool {
+    keyboard_input.get_just_pressed().next().is_some()
+        || mouse_button_input.get_just_pressed().next().is_some()
+        || gamepads
+            .iter()
+            .any(|gamepad| gamepad.get_just_pressed().next().is_some())
+}
+
+/// Starts the demo once the Ready screen has been left alone long enough.
+#[allow(clippy::too_many_arguments)]
+pub fn start_demo_system(
+    time: Res<Time>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    gamepads: Query<&Gamepad>,
+    weights: Res<AiWeights>,
+    game_data: Single<&GameData, With<PlayerBoard>>,
+    mut demo: ResMut<Demo>,
+    mut replay_player: ResMut<ReplayPlayer>,
+    mut next_state: ResMut<NextState<GameSate>>,
+) {
+    if any_input(&keyboard_input, &mouse_button_input, &gamepads) {
+        demo.idle = Duration::default();
+        return;
+    }
+    demo.idle += time.delta();
+    if demo.idle.as_secs_f32() < DEMO_IDLE_SECS {
+        return;
+    }
+    demo.idle = Duration::default();
+    demo.active = true;
+    demo.mode = game_data.mode;
+    replay_player.replay = Some(record_demo(&game_data.board, rand::random(), &weights));
+    replay_player.cursor = 0;
+    next_state.set(GameSate::Playing);
+}
+
+/// Goes back to the Ready screen on any input or once the demo is over. Runs
+/// after the game's own state changes so a top-out skips the game over
+/// screen.
+pub fn exit_demo_system(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    gamepads: Query<&Gamepad>,
+    state: Res<State<GameSate>>,
+    game_data: Single<&GameData, With<PlayerBoard>>,
+    mut next_state: ResMut<NextState<GameSate>>,
+) {
+    if *state.get() == GameSate::Ready {
+        return;
+    }
+    if any_input(&keyboard_input, &mouse_button_input, &gamepads)
+        || *state.get() != GameSate::Playing
+        || game_data.tick >= DEMO_TICKS
+        || game_data.ended.is_some()
+    {
+        next_state.set(GameSate::Ready);
+    }
+}
+
+/// Clears the demo's game off the board and puts the selected mode back.
+pub fn end_demo(
+    mut commands: Commands,
+    board_bricks: BoardBricks,
+    player_board: Single<(Entity, &mut GameData), With<PlayerBoard>>,
+    mut demo: ResMut<Demo>,
+) {
+    demo.idle = Duration::default();
+    if !demo.active {
+        return;
+    }
+    demo.active = false;
+    let (board, mut game_data) = player_board.into_inner();
+    reset_game(&mut commands, board, &board_bricks, &mut game_data);
+    game_data.mode = demo.mode;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::record_demo;
+
+    use crate::{
+        ai::AiWeights,
+        constants::DEMO_TICKS,
+        game_data::{Board, GameData},
+        replay::ReplayPlayer,
+    };
+
+    #[test]
+    fn test_demo_replay_plays_back() {
+        let replay = record_demo(&Board::default(), 8, &AiWeights::default());
+        assert!(!replay.actions.is_empty());
+        let mut replay_player = ReplayPlayer {
+            replay: Some(replay),
+            cursor: 0,
+        };
+        let mut game_data = GameData::default();
+        replay_player.seek(&mut game_data, DEMO_TICKS);
+        assert!(game_data.cleans > 0);
+    }
+}
@@ -0,0 +1,103 @@
+use bevy::ecs::{entity::Entity, event::Event};
+
+use crate::brick::{BrickNode, BrickShape};
+
+/// Something that happened on one board, `board` is the entity holding its
+/// `GameData`. The payloads below are always sent wrapped in it.
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoardEvent<E: Send + Sync + 'static> {
+    pub board: Entity,
+    pub event: E,
+}
+
+/// A new falling brick appeared at the top of the board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PieceSpawned {
+    pub shape: BrickShape,
+    pub node: BrickNode,
+}
+
+/// The falling brick moved by input or gravity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PieceMoved {
+    pub from: BrickNode,
+    pub to: BrickNode,
+}
+
+/// The falling brick rotated, `kicked` when it had to be pushed back inside
+/// the walls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PieceRotated {
+    pub shape: BrickShape,
+    pub kicked: bool,
+}
+
+/// A drop was accepted, gravity pulls the brick down every tick until it locks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DropStarted;
+
+/// The falling brick came to rest, `lines` are the rows it completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PieceLocked {
+    pub shape: BrickShape,
+    pub node: BrickNode,
+    pub lines: (usize, usize),
+    /// A T brick rotated into a slot with three of its corners filled.
+    pub t_spin: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClearKind {
+    Single,
+    Double,
+    Triple,
+    Tetris,
+}
+
+impl ClearKind {
+    pub fn from_lines(lines: usize) -> Self {
+        match lines {
+            1 => ClearKind::Single,
+            2 => ClearKind::Double,
+            3 => ClearKind::Triple,
+            _ => ClearKind::Tetris,
+        }
+    }
+
+    pub fn lines(&self) -> u32 {
+        match self {
+            ClearKind::Single => 1,
+            ClearKind::Double => 2,
+            ClearKind::Triple => 3,
+            ClearKind::Tetris => 4,
+        }
+    }
+}
+
+/// Completed rows were erased after their flash animation. `rows` is the
+/// first row and the count, as in `Board::get_clean_lines`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinesCleared {
+    pub rows: (usize, usize),
+    pub kind: ClearKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelUp {
+    pub level: u32,
+}
+
+/// Garbage rows a versus player sent to the opponent, after cancelling
+/// their own incoming garbage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GarbageSent {
+    pub rows: u32,
+}
+
+/// The stack reached the top and the game is lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TopOut;
+
+/// The mode's goal or time limit was reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameFinished;
@@ -0,0 +1,208 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::{
+    brick::{BrickNode, BrickShape, brick_at},
+    game_data::{Board, GameAction},
+};
+
+/// A place the falling brick can lock, with the fewest moves and rotations
+/// that get it there from where it spawned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub shape: BrickShape,
+    pub node: BrickNode,
+    /// Ends with the drop that locks the brick.
+    pub inputs: Vec<GameAction>,
+}
+
+type State = (BrickShape, BrickNode);
+
+/// Every searched state fits, so a move is possible exactly when the moved
+/// brick fits too, the same as gameplay's checks. Gameplay doesn't check the
+/// cells a kick lands on, so a rotation can leave the brick inside the stack
+/// or past the right wall. Those states aren't searched.
+fn next_state(board: &Board, (shape, mut node): State, action: GameAction) -> Option<State> {
+    let shape = match action {
+        GameAction::Rotate => {
+            let shape = shape.rotate();
+            node = board.kick(shape, node);
+            shape
+        }
+        GameAction::Left => {
+            node.move_left();
+            shape
+        }
+        GameAction::Right => {
+            node.move_right();
+            shape
+        }
+        GameAction::Down => {
+            node.move_down();
+            shape
+        }
+        _ => return None,
+    };
+    board.fits(shape, node).then_some((shape, node))
+}
+
+fn below(mut node: BrickNode) -> BrickNode {
+    node.move_down();
+    node
+}
+
+/// Where the brick locks when dropped from `state`, or `None` if it would
+/// top out there.
+fn landing(board: &Board, state: State) -> Option<State> {
+    let (shape, mut node) = state;
+    while board.fits(shape, below(node)) {
+        node.move_down();
+    }
+    let brick = brick_at(shape, node);
+    (!brick.nodes.iter().any(|node| board.is_move_to_top(node))).then_some((shape, node))
+}
+
+/// Searches every position and rotation the brick can be moved to from
+/// `spawn` and returns each place it can lock, reached with as few moves and
+/// rotations as possible. Soft drops are free: they let the brick tuck under
+/// overhangs and rotate into slots a straight drop can't reach. Inputs are
+/// taken to happen before gravity pulls the brick down any further.
+pub fn find_routes(board: &Board, shape: BrickShape, spawn: BrickNode) -> Vec<Route> {
+    let start = (shape, spawn);
+    if !board.fits(shape, spawn) {
+        return vec![];
+    }
+    let mut parents: HashMap<State, Option<(State, GameAction)>> = HashMap::from([(start, None)]);
+    let mut costs: HashMap<State, u32> = HashMap::from([(start, 0)]);
+    let mut searched = HashSet::new();
+    // soft drops cost nothing and go to the front, so states come off the
+    // queue cheapest first
+    let mut queue = VecDeque::from([start]);
+    let mut landings: HashMap<State, State> = HashMap::new();
+    let mut order = vec![];
+    while let Some(state) = queue.pop_front() {
+        if !searched.insert(state) {
+            continue;
+        }
+        if let Some(landing) = landing(board, state)
+            && !landings.contains_key(&landing)
+        {
+            landings.insert(landing, state);
+            order.push(landing);
+        }
+        for action in [
+            GameAction::Left,
+            GameAction::Right,
+            GameAction::Rotate,
+            GameAction::Down,
+        ] {
+            let Some(next) = next_state(board, state, action) else {
+                continue;
+            };
+            let is_free = action == GameAction::Down;
+            let cost = costs[&state] + u32::from(!is_free);
+            if costs.get(&next).is_some_and(|known| *known <= cost) {
+                continue;
+            }
+            costs.insert(next, cost);
+            parents.insert(next, Some((state, action)));
+            if is_free {
+                queue.push_front(next);
+            } else {
+                queue.push_back(next);
+            }
+        }
+    }
+
+    order
+        .into_iter()
+        .map(|(shape, node)| {
+            let mut inputs = vec![GameAction::Drop];
+            let mut state = landings[&(shape, node)];
+            while let Some((previous, action)) = parents[&state] {
+                inputs.push(action);
+                state = previous;
+            }
+            inputs.reverse();
+            Route {
+                shape,
+                node,
+                inputs,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Route, find_routes};
+
+    use crate::{
+        brick::{BrickNode, BrickShape, brick_at},
+        game_data::{ActionOutcome, Board, Fall, GameAction, GameData},
+    };
+
+    fn spawn(board: &Board) -> BrickNode {
+        BrickNode(board.cols as i8 / 2, board.rows as i8 + 3)
+    }
+
+    /// Plays the route in a game and returns where the brick locked.
+    fn play(board: &Board, shape: BrickShape, route: &Route) -> (BrickShape, BrickNode) {
+        let mut game_data = GameData {
+            board: board.clone(),
+            falling_brick_shape: shape,
+            ..Default::default()
+        };
+        game_data.falling_brick_node = spawn(board);
+        for action in &route.inputs {
+            if let Some(ActionOutcome::DropStarted(_)) = game_data.apply_action(*action) {
+                game_data.start_drop();
+            }
+        }
+        loop {
+            if let Some(Fall::Locked(locked, _)) = game_data.update_gravity() {
+                return (locked.shape, locked.node);
+            }
+        }
+    }
+
+    #[test]
+    fn test_routes_on_empty_board() {
+        let board = Board::default();
+        let routes = find_routes(&board, BrickShape(0, 0), spawn(&board));
+        assert_eq!(routes.len(), board.cols - 1);
+        assert_eq!(routes[0].inputs, vec![GameAction::Drop]);
+        for route in &routes {
+            assert_eq!(
+                play(&board, BrickShape(0, 0), route),
+                (route.shape, route.node)
+            );
+        }
+    }
+
+    #[test]
+    fn test_tuck_under_overhang() {
+        let mut board = Board::default();
+        (0..4).for_each(|x| board.set_occupied(x, 1, true));
+        let shape = BrickShape(1, 0);
+        let routes = find_routes(&board, shape, spawn(&board));
+        let tuck = routes
+            .iter()
+            .find(|route| {
+                brick_at(route.shape, route.node)
+                    .nodes
+                    .iter()
+                    .all(|node| node.1 == 0 && node.0 < 4)
+            })
+            .expect("the I brick slides under the roof");
+        let last_down = tuck
+            .inputs
+            .iter()
+            .rposition(|action| *action == GameAction::Down)
+            .unwrap();
+        assert!(tuck.inputs[last_down..].contains(&GameAction::Left));
+        assert_eq!(play(&board, shape, tuck), (tuck.shape, tuck.node));
+        for route in &routes {
+            assert_eq!(play(&board, shape, route), (route.shape, route.node));
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use bevy::{
+    color::{Color, Srgba},
+    ecs::{
+        component::Component,
+        entity::Entity,
+        query::With,
+        system::{Commands, Res, Single},
+    },
+    text::{FontSmoothing, JustifyText, Text2d, TextColor, TextFont, TextLayout},
+    transform::components::Transform,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    GameAssets, TetrisEntity,
+    board::PlayerBoard,
+    brick::{BrickNode, BrickShape},
+    finder::find_routes,
+    game_data::{Board, GameAction, GameData},
+};
+
+/// How economically the bricks of a game were placed: each placement's moves
+/// and rotations against the fewest that reach the same spot. Soft drops
+/// aren't counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Finesse {
+    /// Moves and rotations pressed for the falling brick so far.
+    pub inputs: u32,
+    /// Inputs over the fewest needed, for the whole game.
+    pub faults: u32,
+    /// Inputs over the fewest needed for the last placed brick.
+    pub last_faults: u32,
+    /// Where the finesse trainer wants the falling brick to go.
+    pub target: Option<(BrickShape, BrickNode)>,
+    /// Trainer bricks placed anywhere but the target.
+    pub misses: u32,
+}
+
+/// Whether `action` counts against finesse, soft and hard drops don't.
+pub fn is_finesse_input(action: GameAction) -> bool {
+    matches!(
+        action,
+        GameAction::Left | GameAction::Right | GameAction::Rotate
+    )
+}
+
+/// The fewest moves and rotations that take the brick from `spawn` to lock at
+/// `lock` on `board`, `None` if the finder can't get there.
+pub fn minimal_inputs(
+    board: &Board,
+    spawn: (BrickShape, BrickNode),
+    lock: (BrickShape, BrickNode),
+) -> Option<u32> {
+    find_routes(board, spawn.0, spawn.1)
+        .into_iter()
+        .find(|route| (route.shape, route.node) == lock)
+        .map(|route| {
+            route
+                .inputs
+                .into_iter()
+                .filter(|action| is_finesse_input(*action))
+                .count() as u32
+        })
+}
+
+impl Finesse {
+    /// Scores the brick that spawned at `spawn` and is locking at `lock`,
+    /// before it's added to `board`, and starts counting for the next one.
+    pub fn record(
+        &mut self,
+        board: &Board,
+        spawn: (BrickShape, BrickNode),
+        lock: (BrickShape, BrickNode),
+    ) {
+        self.last_faults = minimal_inputs(board, spawn, lock)
+            .map_or(0, |minimal| self.inputs.saturating_sub(minimal));
+        self.faults += self.last_faults;
+        if self.target.is_some_and(|target| target != lock) {
+            self.misses += 1;
+        }
+        self.inputs = 0;
+    }
+}
+
+#[derive(Component)]
+pub struct FinesseText;
+
+pub fn spawn_finesse_text(mut commands: Commands, game_assets: Res<GameAssets>) {
+    commands.spawn((
+        TetrisEntity,
+        Text2d::default(),
+        TextColor(Color::BLACK),
+        TextLayout::new_with_justify(JustifyText::Center),
+        TextFont {
+            font: game_assets.font.clone(),
+            font_size: 12.0,
+            font_smoothing: FontSmoothing::AntiAliased,
+        },
+        Transform::from_xyz(110., 242., 300.),
+        FinesseText,
+    ));
+}
+
+pub fn despawn_finesse_text(
+    mut commands: Commands,
+    finesse_text: Option<Single<Entity, With<FinesseText>>>,
+) {
+    if let Some(finesse_text) = finesse_text {
+        commands.entity(finesse_text.into_inner()).despawn();
+    }
+}
+
+/// Shows the player's finesse faults, in red right after a wasteful
+/// placement.
+pub fn finesse_text_system(
+    game_data: Single<&GameData, With<PlayerBoard>>,
+    finesse_text: Single<(&mut Text2d, &mut TextColor), With<FinesseText>>,
+) {
+    let (mut text, mut color) = finesse_text.into_inner();
+    let finesse = game_data.finesse;
+    let value = if game_data.mode.is_finesse_training() {
+        format!("Faults {} Miss {}", finesse.faults, finesse.misses)
+    } else {
+        format!("Faults {}", finesse.faults)
+    };
+    if **text != value {
+        **text = value;
+    }
+    let value = if finesse.last_faults > 0 {
+        Srgba::hex("#a02818").unwrap().into()
+    } else {
+        Color::BLACK
+    };
+    if color.0 != value {
+        color.0 = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::minimal_inputs;
+
+    use crate::{
+        brick::{BrickNode, BrickShape},
+        finder::find_routes,
+        game_data::{GameAction, GameData},
+        mode::GameMode,
+    };
+
+    #[test]
+    fn test_minimal_inputs() {
+        let game_data = GameData::default();
+        let spawn = (BrickShape(0, 0), BrickNode(5, 23));
+        let board = &game_data.board;
+        assert_eq!(
+            minimal_inputs(board, spawn, (BrickShape(0, 0), BrickNode(5, 2))),
+            Some(0)
+        );
+        assert_eq!(
+            minimal_inputs(board, spawn, (BrickShape(0, 0), BrickNode(2, 2))),
+            Some(3)
+        );
+        assert_eq!(
+            minimal_inputs(board, spawn, (BrickShape(0, 0), BrickNode(5, 5))),
+            None
+        );
+
+        // the soft drops under the roof don't count, only the moves left
+        let mut board = board.clone();
+        (0..4).for_each(|x| board.set_occupied(x, 1, true));
+        let spawn = (BrickShape(1, 0), BrickNode(5, 23));
+        assert_eq!(
+            minimal_inputs(&board, spawn, (BrickShape(1, 0), BrickNode(0, 1))),
+            Some(5)
+        );
+    }
+
+    #[test]
+    fn test_faults_are_counted() {
+        let play = |tracks_finesse| {
+            let mut game_data = GameData {
+                tracks_finesse,
+                ..Default::default()
+            };
+            game_data.start(3);
+            // there and back again before dropping straight down
+            for action in [GameAction::Left, GameAction::Right, GameAction::Drop] {
+                game_data.tick_with([action]);
+            }
+            assert_eq!(game_data.finesse.inputs, 2);
+            for _ in 0..60 {
+                game_data.tick_with([]);
+            }
+            assert_eq!(game_data.finesse.inputs, 0);
+            game_data.finesse
+        };
+        let finesse = play(true);
+        assert_eq!(finesse.faults, 2);
+        assert_eq!(finesse.last_faults, 2);
+        // boards nobody watches aren't scored
+        assert_eq!(play(false).faults, 0);
+    }
+
+    #[test]
+    fn test_trainer_target() {
+        let mut game_data = GameData {
+            mode: GameMode::Finesse,
+            ..Default::default()
+        };
+        game_data.start(5);
+        let (shape, node) = game_data.finesse.target.unwrap();
+        let route = find_routes(
+            &game_data.board,
+            game_data.falling_brick_shape,
+            game_data.falling_brick_node,
+        )
+        .into_iter()
+        .find(|route| (route.shape, route.node) == (shape, node))
+        .unwrap();
+        for action in route.inputs {
+            game_data.tick_with([action]);
+        }
+        for _ in 0..60 {
+            game_data.tick_with([]);
+        }
+        assert!(game_data.board.occupied().all(|cell| !cell));
+        assert_eq!(game_data.finesse.faults, 0);
+        assert_eq!(game_data.finesse.misses, 0);
+        assert!(game_data.finesse.target.is_some());
+    }
+}
@@ -1,56 +1,197 @@
 use crate::{
-    brick::{Brick, BrickNode, BrickShape},
-    constants::{
-        BOARD_BRICK_NODE_COLS, BOARD_BRICK_NODE_ROWS, BOARD_BRICK_NODE_TOTAL, TIMER_FALLING_SECS,
+    board::{get_score, get_speed},
+    brick::{Brick, BrickNode, BrickShape, brick_at},
+    constants::{BOARD_BRICK_NODE_COLS, BOARD_BRICK_NODE_ROWS, TICK_DURATION, TIMER_FALLING_SECS},
+    events::{
+        ClearKind, DropStarted, LevelUp, LinesCleared, PieceLocked, PieceMoved, PieceRotated,
+        PieceSpawned,
     },
+    finder::find_routes,
+    finesse::{Finesse, is_finesse_input},
+    mode::{GameMode, update_mode_rules},
+    replay::Replay,
+    rng::GameRng,
 };
 use bevy::{
-    ecs::system::Resource,
+    ecs::component::Component,
     time::{Timer, TimerMode},
 };
+use rand::{Rng, seq::IndexedRandom};
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
-#[derive(Debug, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum EraseAnimationStep {
     #[default]
     NotStart,
     Playing,
-    End,
 }
 
-#[derive(Debug, Clone)]
+/// Inputs that drive a game, recorded in replays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameAction {
+    Left,
+    Right,
+    Down,
+    Rotate,
+    Drop,
+    Undo,
+    Redo,
+}
+
+/// Widest board the row masks hold.
+pub const MAX_BOARD_COLS: usize = 32;
+
+/// The stack, one bit mask per row with bit `x` for column `x`, so collision
+/// and line checks stay cheap and bots can clone boards freely. It is saved
+/// as the list of cells, see `occupied`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "BoardCells", try_from = "BoardCells")]
 pub struct Board {
-    pub occupied: Vec<bool>,
+    pub cols: usize,
+    pub rows: usize,
+    /// One flag per row, set for rows that were pushed in as garbage.
+    pub garbage: Vec<bool>,
+    masks: Vec<u32>,
+}
+
+/// A board as saved, every cell `x + y * cols`.
+#[derive(Serialize, Deserialize)]
+struct BoardCells {
+    cols: usize,
+    rows: usize,
+    occupied: Vec<bool>,
+    garbage: Vec<bool>,
+}
+
+impl From<Board> for BoardCells {
+    fn from(board: Board) -> Self {
+        BoardCells {
+            cols: board.cols,
+            rows: board.rows,
+            occupied: board.occupied().collect(),
+            garbage: board.garbage,
+        }
+    }
+}
+
+impl TryFrom<BoardCells> for Board {
+    type Error = String;
+
+    fn try_from(cells: BoardCells) -> Result<Self, Self::Error> {
+        if !(1..=MAX_BOARD_COLS).contains(&cells.cols)
+            || cells.occupied.len() != cells.cols * cells.rows
+        {
+            return Err(format!(
+                "{} cells don't make a board of {} by {}",
+                cells.occupied.len(),
+                cells.cols,
+                cells.rows
+            ));
+        }
+        Ok(Board {
+            cols: cells.cols,
+            rows: cells.rows,
+            garbage: cells.garbage,
+            masks: cells.occupied.chunks(cells.cols).map(row_mask).collect(),
+        })
+    }
+}
+
+fn row_mask(cells: &[bool]) -> u32 {
+    cells
+        .iter()
+        .enumerate()
+        .filter(|(_, cell)| **cell)
+        .fold(0, |mask, (x, _)| mask | 1 << x)
 }
 
 impl Default for Board {
     fn default() -> Self {
-        let mut occupied = vec![];
-        (0..BOARD_BRICK_NODE_ROWS).for_each(|_| {
-            (0..BOARD_BRICK_NODE_COLS).for_each(|_| {
-                occupied.push(false);
-            });
-        });
-        Self { occupied }
+        Self::new(BOARD_BRICK_NODE_COLS, BOARD_BRICK_NODE_ROWS)
     }
 }
 
 impl Board {
-    pub fn is_brick_node_occupied(&self, brick_node: &BrickNode) -> bool {
-        let index = (brick_node.0 as usize) + (brick_node.1 as usize) * BOARD_BRICK_NODE_COLS;
-        if index >= self.occupied.len() {
-            return false;
+    pub fn new(cols: usize, rows: usize) -> Self {
+        assert!(
+            (1..=MAX_BOARD_COLS).contains(&cols),
+            "boards are 1 to 32 columns wide"
+        );
+        Self {
+            cols,
+            rows,
+            garbage: vec![false; rows],
+            masks: vec![0; rows],
         }
-        assert!(index >= 0);
-        assert!(index < BOARD_BRICK_NODE_TOTAL);
-        self.occupied[index]
+    }
+
+    /// An empty board of the same size.
+    pub fn cleared(&self) -> Self {
+        Self::new(self.cols, self.rows)
+    }
+
+    /// The filled cells of row `y`, bit `x` for column `x`.
+    pub fn row_mask(&self, y: usize) -> u32 {
+        self.masks[y]
+    }
+
+    fn full_mask(&self) -> u32 {
+        (u64::MAX >> (64 - self.cols)) as u32
+    }
+
+    pub fn is_occupied(&self, x: usize, y: usize) -> bool {
+        self.masks[y] & 1 << x != 0
+    }
+
+    /// Every cell, `x + y * cols`, for drawing and saving.
+    pub fn occupied(&self) -> impl Iterator<Item = bool> + '_ {
+        self.masks
+            .iter()
+            .flat_map(|mask| (0..self.cols).map(move |x| mask & 1 << x != 0))
+    }
+
+    pub fn set_occupied(&mut self, x: usize, y: usize, occupied: bool) {
+        assert!(x < self.cols);
+        if occupied {
+            self.masks[y] |= 1 << x;
+        } else {
+            self.masks[y] &= !(1 << x);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.masks.iter().all(|mask| *mask == 0)
+    }
+
+    /// Whether the brick of `shape` at `node` is inside the walls and above
+    /// the floor without overlapping the stack. Cells above the board are free.
+    pub fn fits(&self, shape: BrickShape, node: BrickNode) -> bool {
+        shape.mask().iter().enumerate().all(|(dy, bits)| {
+            if *bits == 0 {
+                return true;
+            }
+            let (x, y) = (node.0 as i32, node.1 as i32 - dy as i32);
+            let row = if x >= 0 {
+                (*bits as u64) << x.min(MAX_BOARD_COLS as i32)
+            } else if x > -8 && bits.trailing_zeros() as i32 >= -x {
+                (*bits >> -x) as u64
+            } else {
+                return false;
+            };
+            y >= 0
+                && row >> self.cols == 0
+                && (y as usize >= self.rows || self.masks[y as usize] as u64 & row == 0)
+        })
+    }
+
+    pub fn is_brick_node_occupied(&self, brick_node: &BrickNode) -> bool {
+        self.is_brick_node_in_board(brick_node)
+            && self.is_occupied(brick_node.0 as usize, brick_node.1 as usize)
     }
 
     pub fn is_brick_node_in_board(&self, node: &BrickNode) -> bool {
-        node.0 >= 0
-            && (node.0 as usize) < BOARD_BRICK_NODE_COLS
-            && node.1 >= 0
-            && (node.1 as usize) < BOARD_BRICK_NODE_ROWS
+        node.0 >= 0 && (node.0 as usize) < self.cols && node.1 >= 0 && (node.1 as usize) < self.rows
     }
 
     pub fn is_move_to_left(&self, node: &BrickNode) -> bool {
@@ -58,7 +199,7 @@ impl Board {
     }
 
     pub fn is_move_to_right(&self, node: &BrickNode) -> bool {
-        node.0 as usize >= BOARD_BRICK_NODE_COLS - 1
+        node.0 as usize >= self.cols - 1
             || self.is_brick_hit_to_occupied(&BrickNode(node.0 + 1, node.1))
     }
 
@@ -67,64 +208,159 @@ impl Board {
     }
 
     pub fn is_move_to_top(&self, node: &BrickNode) -> bool {
-        (node.1 as usize) >= BOARD_BRICK_NODE_ROWS - 1
+        (node.1 as usize) >= self.rows - 1
     }
 
     pub fn is_brick_hit_to_occupied(&self, node: &BrickNode) -> bool {
         self.is_brick_node_occupied(node)
     }
 
+    /// Where a brick just rotated to `shape` at `node` ends up after the wall
+    /// and floor kicks. Cells it lands on are not checked.
+    pub fn kick(&self, shape: BrickShape, node: BrickNode) -> BrickNode {
+        let mut node = node;
+        let mut bounding = (0, 0, 0, 0);
+        brick_at(shape, node).nodes.iter().for_each(|node| {
+            if node.0 < bounding.0 {
+                bounding.0 = node.0;
+            };
+            if node.1 < bounding.3 {
+                bounding.3 = node.1;
+            };
+            if node.0 > bounding.1 {
+                bounding.1 = node.0;
+            };
+        });
+
+        if bounding.0 < 0 {
+            node.move_left_steps(-bounding.0);
+        } else if (bounding.1 as usize) >= self.cols {
+            node.move_right_steps(self.cols as i8 - bounding.1);
+        }
+        if bounding.3 < 0 {
+            node.move_up_steps(-bounding.3);
+        }
+        node
+    }
+
     pub fn update_occupied_by_brick(&mut self, brick: &Brick) {
         brick.nodes.iter().for_each(|node| {
-            let index = (node.0 as usize) + (node.1 as usize) * BOARD_BRICK_NODE_COLS;
-            assert!(index >= 0);
-            assert!(index < BOARD_BRICK_NODE_TOTAL);
-            self.occupied[index] = true;
+            assert!(self.is_brick_node_in_board(node));
+            self.masks[node.1 as usize] |= 1 << node.0;
         });
     }
 
     pub fn get_clean_lines(&self) -> (usize, usize) {
-        let mut start: i8 = -1;
-        let mut lines: i8 = 0;
-
-        for (i, _) in (0..BOARD_BRICK_NODE_ROWS).into_iter().rev().enumerate() {
-            let is_full = (&self.occupied
-                [(BOARD_BRICK_NODE_COLS * i)..(BOARD_BRICK_NODE_COLS * (i + 1))])
-                .iter()
-                .all(|v| *v);
-            if is_full {
-                if start < 0 {
-                    start = i as i8;
-                }
-                lines += 1;
-            } else if start >= 0 {
-                break;
-            }
-        }
-
-        if start >= 0 {
-            (start as usize, lines as usize)
-        } else {
-            (0, 0)
-        }
+        let full = self.full_mask();
+        let Some(start) = self.masks.iter().position(|mask| *mask == full) else {
+            return (0, 0);
+        };
+        let lines = self.masks[start..]
+            .iter()
+            .take_while(|mask| **mask == full)
+            .count();
+        (start, lines)
     }
 
     pub fn clean(&mut self, range: (usize, usize)) {
-        (range.0..BOARD_BRICK_NODE_ROWS).for_each(|i| {
-            (0..BOARD_BRICK_NODE_COLS).for_each(|j| {
-                let index = i * BOARD_BRICK_NODE_COLS + j;
-                let above_index = (i + 1) * BOARD_BRICK_NODE_COLS + j;
-                self.occupied[index] = if above_index >= BOARD_BRICK_NODE_TOTAL {
-                    false
-                } else {
-                    self.occupied[above_index]
-                }
-            });
+        let (start, lines) = range;
+        (start..self.rows).for_each(|i| {
+            let is_above = i + lines < self.rows;
+            self.masks[i] = if is_above { self.masks[i + lines] } else { 0 };
+            self.garbage[i] = is_above && self.garbage[i + lines];
         });
     }
+
+    /// Shifts the stack up one row and fills the bottom row, leaving a hole at
+    /// `hole_col`. Returns true when blocks were pushed out of the top (top-out).
+    pub fn push_garbage_row(&mut self, hole_col: usize) -> bool {
+        let mut cells = vec![true; self.cols];
+        cells[hole_col] = false;
+        self.push_garbage_cells(&cells)
+    }
+
+    /// Like `push_garbage_row`, with the bottom row filled from `cells`.
+    pub fn push_garbage_cells(&mut self, cells: &[bool]) -> bool {
+        assert_eq!(cells.len(), self.cols);
+        let is_topped_out = self.masks[self.rows - 1] != 0;
+
+        self.masks.rotate_right(1);
+        self.masks[0] = row_mask(cells);
+        self.garbage.rotate_right(1);
+        self.garbage[0] = true;
+
+        is_topped_out
+    }
+
+    pub fn garbage_rows(&self) -> usize {
+        self.garbage.iter().filter(|v| **v).count()
+    }
+}
+
+/// Inputs gathered between fixed ticks, applied in order at the start of the
+/// next one so the outcome doesn't depend on the frame rate.
+#[derive(Debug, Default, Component)]
+pub struct InputBuffer(pub Vec<GameAction>);
+
+impl InputBuffer {
+    pub fn push(&mut self, action: GameAction) {
+        self.0.push(action);
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// Why a game stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameEnd {
+    TopOut,
+    Finished,
+}
+
+/// What an input did to the falling brick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActionOutcome {
+    Moved(PieceMoved),
+    Rotated(PieceRotated),
+    /// Takes effect once gravity runs.
+    DropStarted(DropStarted),
+    /// Undo and redo bring back the brick of the restored placement.
+    Spawned(PieceSpawned),
+}
+
+/// What gravity did to the falling brick in a tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fall {
+    Moved(PieceMoved),
+    Locked(PieceLocked, PieceSpawned),
+    TopOut,
+}
+
+/// Everything that happened in one fixed tick.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TickOutcome {
+    pub fall: Option<Fall>,
+    pub cleared: Option<LinesCleared>,
+    pub level_up: Option<LevelUp>,
+    pub ended: Option<GameEnd>,
+}
+
+/// State restored by undo/redo, recorded after each lock.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub board: Board,
+    pub falling_brick_shape: BrickShape,
+    pub next_brick_shape: BrickShape,
+    pub rng: GameRng,
+    pub level: u32,
+    pub score: u32,
+    pub cleans: u32,
 }
 
-#[derive(Default, Debug, Resource)]
+/// One board's game, kept on the board entity.
+#[derive(Debug, Clone, Component)]
 pub struct GameData {
     pub board: Board,
     pub level: u32,
@@ -137,22 +373,43 @@ pub struct GameData {
     pub ready_animation_duration: Duration,
     pub is_playing_dino_running_animation: bool,
     pub falling_brick_node: BrickNode,
-    pub freeze: bool,
-    pub is_game_over: bool,
-    pub is_speed_up_falling: bool,
+    /// Where the falling brick was before the last tick, drawing eases from it.
+    pub previous_falling_brick_node: BrickNode,
+    /// Whether the falling brick's last move was a rotation, for T-spins.
+    pub last_action_rotated: bool,
     pub paused: bool,
     pub erase_animation_step: EraseAnimationStep,
     pub erase_animation_duration: Duration,
     pub erase_animation_timer: Timer,
     pub erase_animation_index: i8,
-    pub clean_lines: (usize, usize)
+    pub clean_lines: (usize, usize),
+    pub mode: GameMode,
+    pub play_duration: Duration,
+    pub seed: u64,
+    pub rng: GameRng,
+    pub garbage_timer: Timer,
+    pub history: Vec<Snapshot>,
+    pub history_index: usize,
+    /// Fixed ticks simulated since the game started.
+    pub tick: u64,
+    /// The tick the falling brick spawned on, a new brick is up once it changes.
+    pub spawn_tick: u64,
+    /// Why the game stopped, `None` while it runs.
+    pub ended: Option<GameEnd>,
+    /// Whether placement hints were shown at any point of the game.
+    pub hints_used: bool,
+    pub finesse: Finesse,
+    /// Whether placements are scored for finesse, which takes a route search
+    /// per brick. Only the player's board shows it.
+    pub tracks_finesse: bool,
+    pub replay: Replay,
 }
 
-impl GameData {
-    pub fn default() -> Self {
+impl Default for GameData {
+    fn default() -> Self {
         GameData {
             board: Board::default(),
-            level: 1,
+            level: 0,
             score: 0,
             next_brick_shape: BrickShape::default(),
             falling_brick_shape: BrickShape::default(),
@@ -162,48 +419,522 @@ impl GameData {
             ready_animation_duration: Duration::default(),
             is_playing_dino_running_animation: true,
             falling_brick_node: BrickNode(5, 23),
-            freeze: false,
-            is_game_over: false,
-            is_speed_up_falling: false,
+            previous_falling_brick_node: BrickNode(5, 23),
+            last_action_rotated: false,
             paused: false,
             erase_animation_step: EraseAnimationStep::NotStart,
             erase_animation_duration: Duration::default(),
             erase_animation_timer: Timer::from_seconds(0.5, TimerMode::Repeating),
             erase_animation_index: 0,
-            clean_lines: (0, 0)
+            clean_lines: (0, 0),
+            mode: GameMode::default(),
+            play_duration: Duration::default(),
+            seed: 0,
+            rng: GameRng::default(),
+            garbage_timer: Timer::default(),
+            history: vec![],
+            history_index: 0,
+            tick: 0,
+            spawn_tick: 0,
+            ended: None,
+            hints_used: false,
+            finesse: Finesse::default(),
+            tracks_finesse: false,
+            replay: Replay::default(),
         }
     }
+}
 
+impl GameData {
     pub fn new_falling_brick_node(&mut self) -> BrickNode {
-        BrickNode(5, 23)
+        let col = (self.board.cols / 2) as i8;
+        if self.mode.has_gravity() {
+            BrickNode(col, self.board.rows as i8 + 3)
+        } else {
+            // nothing pulls the brick into view, so spawn it inside the board
+            BrickNode(col, self.board.rows as i8 - 1)
+        }
     }
 
     pub fn reset(&mut self) {
-        self.board = Board::default();
-        self.level = 1;
+        self.board = self.board.cleared();
+        self.level = 0;
         self.score = 0;
         self.next_brick_shape = BrickShape::default();
         self.falling_brick_shape = BrickShape::default();
         self.cleans = 0;
-        self.falling_brick_node = BrickNode(5, 23);
-        self.freeze = false;
-        self.is_game_over = false;
+        self.falling_brick_node = self.new_falling_brick_node();
+        self.previous_falling_brick_node = self.falling_brick_node;
+        self.last_action_rotated = false;
         self.is_playing_dino_running_animation = true;
         self.falling_timer = Timer::from_seconds(TIMER_FALLING_SECS, TimerMode::Repeating);
-        self.is_speed_up_falling = false;
         self.paused = false;
         self.erase_animation_step = EraseAnimationStep::NotStart;
         self.clean_lines = (0, 0);
-        self.erase_animation_index = 0
+        self.erase_animation_index = 0;
+        self.play_duration = Duration::default();
+        self.history.clear();
+        self.history_index = 0;
+        self.tick = 0;
+        self.spawn_tick = 0;
+        self.ended = None;
+        self.hints_used = false;
+        self.finesse = Finesse::default();
+    }
+
+    /// Prepares a fresh game for the selected mode, drawing the pieces and any
+    /// starting garbage from `seed`.
+    pub fn start(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = GameRng::new(seed);
+        (0..self.mode.garbage_rows()).for_each(|_| {
+            self.push_garbage_row();
+        });
+        (0..self.mode.scattered_garbage_rows()).for_each(|_| {
+            self.push_scattered_garbage_row();
+        });
+        if let Some(interval) = self.mode.garbage_interval() {
+            self.garbage_timer = Timer::new(interval, TimerMode::Repeating);
+        }
+        self.falling_brick_shape = BrickShape::rand(&mut self.rng);
+        self.next_brick_shape = BrickShape::rand(&mut self.rng);
+        self.falling_brick_node = self.new_falling_brick_node();
+        self.previous_falling_brick_node = self.falling_brick_node;
+        self.pick_finesse_target();
+        self.tick = 0;
+        self.spawn_tick = 0;
+        self.replay = Replay::new(seed, self.mode, &self.board);
+        if self.mode.has_undo() {
+            self.push_history();
+        }
+    }
+
+    /// The falling brick at its position on the board.
+    pub fn falling_brick(&self) -> Brick {
+        brick_at(self.falling_brick_shape, self.falling_brick_node)
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.paused && self.ended.is_none()
+    }
+
+    pub fn spawned(&self) -> PieceSpawned {
+        PieceSpawned {
+            shape: self.falling_brick_shape,
+            node: self.falling_brick_node,
+        }
+    }
+
+    /// Applies a player input between two ticks and records it in the replay.
+    pub fn apply_action(&mut self, action: GameAction) -> Option<ActionOutcome> {
+        if !self.is_running() {
+            return None;
+        }
+        self.replay.actions.push((self.tick, action));
+        if is_finesse_input(action) {
+            self.finesse.inputs += 1;
+        }
+        let brick = self.falling_brick();
+        let from = self.falling_brick_node;
+        match action {
+            GameAction::Down => {
+                if !brick
+                    .nodes
+                    .iter()
+                    .any(|node| self.board.is_move_to_bottom(node))
+                {
+                    self.falling_brick_node.move_down();
+                }
+            }
+            GameAction::Right => {
+                if !brick
+                    .nodes
+                    .iter()
+                    .any(|node| self.board.is_move_to_right(node))
+                {
+                    self.falling_brick_node.move_right();
+                }
+            }
+            GameAction::Left => {
+                if !brick
+                    .nodes
+                    .iter()
+                    .any(|node| self.board.is_move_to_left(node))
+                {
+                    self.falling_brick_node.move_left();
+                }
+            }
+            GameAction::Rotate => {
+                self.falling_brick_shape = self.falling_brick_shape.rotate();
+                self.falling_brick_node = self
+                    .board
+                    .kick(self.falling_brick_shape, self.falling_brick_node);
+                self.last_action_rotated = true;
+                return Some(ActionOutcome::Rotated(PieceRotated {
+                    shape: self.falling_brick_shape,
+                    kicked: self.falling_brick_node != from,
+                }));
+            }
+            GameAction::Drop => return Some(ActionOutcome::DropStarted(DropStarted)),
+            GameAction::Undo | GameAction::Redo => {
+                if !self.mode.has_undo() || self.erase_animation_step == EraseAnimationStep::Playing
+                {
+                    return None;
+                }
+                let is_restored = if action == GameAction::Undo {
+                    self.undo()
+                } else {
+                    self.redo()
+                };
+                return is_restored.then(|| ActionOutcome::Spawned(self.spawned()));
+            }
+        }
+        if self.falling_brick_node == from {
+            return None;
+        }
+        self.last_action_rotated = false;
+        Some(ActionOutcome::Moved(PieceMoved {
+            from,
+            to: self.falling_brick_node,
+        }))
+    }
+
+    /// Runs one fixed tick, applying the inputs given since the last one first.
+    pub fn tick_with(&mut self, actions: impl IntoIterator<Item = GameAction>) -> TickOutcome {
+        self.previous_falling_brick_node = self.falling_brick_node;
+        for action in actions {
+            if let Some(ActionOutcome::DropStarted(_)) = self.apply_action(action) {
+                self.start_drop();
+            }
+        }
+        self.step()
+    }
+
+    /// Advances the game by one fixed tick, running the same phases as the
+    /// gameplay system sets in order.
+    pub fn step(&mut self) -> TickOutcome {
+        let mut outcome = TickOutcome::default();
+        if !self.advance_tick() {
+            return outcome;
+        }
+        outcome.fall = self.update_gravity();
+        let locked = match outcome.fall {
+            Some(Fall::Locked(locked, _)) => Some(locked),
+            Some(Fall::TopOut) => return outcome,
+            _ => None,
+        };
+        outcome.cleared = self.update_line_clear(locked);
+        (outcome.level_up, outcome.ended) = self.update_scoring(locked, outcome.cleared);
+        outcome
+    }
+
+    /// Counts the tick, returns false when the game is paused or over.
+    pub fn advance_tick(&mut self) -> bool {
+        if !self.is_running() {
+            return false;
+        }
+        self.tick += 1;
+        self.play_duration = self.play_duration.saturating_add(TICK_DURATION);
+        true
+    }
+
+    /// Pulls the brick down every tick until it locks.
+    pub fn start_drop(&mut self) {
+        self.falling_timer.set_duration(TICK_DURATION);
+    }
+
+    /// No level falls as fast as one row per tick, so that speed marks a drop.
+    pub fn is_dropping(&self) -> bool {
+        self.falling_timer.duration() == TICK_DURATION
+    }
+
+    fn stop_drop(&mut self) {
+        self.falling_timer
+            .set_duration(Duration::from_secs_f32(get_speed(self.level)));
+    }
+
+    /// Moves the falling brick down when its timer is up, locking it once it
+    /// can't fall any further and spawning the next one.
+    pub fn update_gravity(&mut self) -> Option<Fall> {
+        if self.erase_animation_step == EraseAnimationStep::Playing
+            || !self.falling_timer.tick(TICK_DURATION).finished()
+        {
+            return None;
+        }
+        if !self.mode.has_gravity() && !self.is_dropping() {
+            return None;
+        }
+        let brick = self.falling_brick();
+        let is_hit_bottom = brick
+            .nodes
+            .iter()
+            .any(|node| self.board.is_move_to_bottom(node));
+        if !is_hit_bottom {
+            let from = self.falling_brick_node;
+            self.falling_brick_node.move_down();
+            self.last_action_rotated = false;
+            return Some(Fall::Moved(PieceMoved {
+                from,
+                to: self.falling_brick_node,
+            }));
+        }
+
+        self.stop_drop();
+        let t_spin = self.is_t_spin();
+
+        // a brick spawned into the stack (possible without gravity) also tops out
+        let is_hit_top = brick.nodes.iter().any(|node| {
+            self.board.is_move_to_top(node)
+                || (self.board.is_brick_node_in_board(node)
+                    && self.board.is_brick_node_occupied(node))
+        });
+        if self.tracks_finesse || self.mode.is_finesse_training() {
+            let spawn = (
+                BrickShape(self.falling_brick_shape.0, 0),
+                self.new_falling_brick_node(),
+            );
+            self.finesse.record(
+                &self.board,
+                spawn,
+                (self.falling_brick_shape, self.falling_brick_node),
+            );
+        } else {
+            self.finesse.inputs = 0;
+        }
+        if !is_hit_top {
+            // the trainer only ever shows the brick being placed
+            if !self.mode.is_finesse_training() {
+                self.board.update_occupied_by_brick(&brick);
+            }
+        } else if self.mode.can_top_out() {
+            self.ended = Some(GameEnd::TopOut);
+            return Some(Fall::TopOut);
+        } else {
+            // no top-out: drop the brick and start over on an empty board
+            self.board = self.board.cleared();
+        }
+
+        let locked = PieceLocked {
+            shape: self.falling_brick_shape,
+            node: self.falling_brick_node,
+            lines: self.board.get_clean_lines(),
+            t_spin,
+        };
+        self.falling_brick_node = self.new_falling_brick_node();
+        self.last_action_rotated = false;
+        self.falling_brick_shape = self.next_brick_shape;
+        self.next_brick_shape = BrickShape::next(&mut self.rng);
+        self.spawn_tick = self.tick;
+        self.pick_finesse_target();
+        Some(Fall::Locked(locked, self.spawned()))
+    }
+
+    /// Gives the falling brick a random place to reach in the finesse
+    /// trainer.
+    fn pick_finesse_target(&mut self) {
+        if !self.mode.is_finesse_training() {
+            return;
+        }
+        let routes = find_routes(
+            &self.board,
+            self.falling_brick_shape,
+            self.falling_brick_node,
+        );
+        self.finesse.target = routes
+            .choose(&mut self.rng)
+            .map(|route| (route.shape, route.node));
+    }
+
+    /// A T brick rotated into place with at least three of the four cells
+    /// diagonal to its center filled, walls and floor included.
+    fn is_t_spin(&self) -> bool {
+        if !self.falling_brick_shape.is_t() || !self.last_action_rotated {
+            return false;
+        }
+        let BrickNode(x, y) = self.falling_brick_node;
+        [(x, y), (x + 2, y), (x, y - 2), (x + 2, y - 2)]
+            .iter()
+            .filter(|(x, y)| {
+                *x < 0
+                    || *x as usize >= self.board.cols
+                    || *y < 0
+                    || self.board.is_brick_node_occupied(&BrickNode(*x, *y))
+            })
+            .count()
+            >= 3
+    }
+
+    /// Plays the flash of completed rows and erases them once it is over,
+    /// then starts the flash for rows completed by `locked`.
+    pub fn update_line_clear(&mut self, locked: Option<PieceLocked>) -> Option<LinesCleared> {
+        let mut cleared = None;
+        if self.erase_animation_step == EraseAnimationStep::Playing {
+            self.erase_animation_duration =
+                self.erase_animation_duration.saturating_add(TICK_DURATION);
+            if self
+                .erase_animation_timer
+                .tick(TICK_DURATION)
+                .just_finished()
+            {
+                self.erase_animation_index += 1;
+            }
+            if self.erase_animation_duration.as_secs() >= 2 {
+                self.board.clean(self.clean_lines);
+                self.erase_animation_step = EraseAnimationStep::NotStart;
+                cleared = Some(LinesCleared {
+                    rows: self.clean_lines,
+                    kind: ClearKind::from_lines(self.clean_lines.1),
+                });
+            }
+        }
+        if let Some(locked) = locked
+            && locked.lines.1 > 0
+        {
+            self.erase_animation_step = EraseAnimationStep::Playing;
+            self.erase_animation_duration = Duration::default();
+            self.erase_animation_timer.reset();
+            self.clean_lines = locked.lines;
+            self.erase_animation_index = 0;
+        }
+        cleared
+    }
+
+    /// Scores cleared lines and picks up the level once a placement is done,
+    /// then applies the mode's rules.
+    pub fn update_scoring(
+        &mut self,
+        locked: Option<PieceLocked>,
+        cleared: Option<LinesCleared>,
+    ) -> (Option<LevelUp>, Option<GameEnd>) {
+        if let Some(cleared) = cleared {
+            let lines = cleared.kind.lines();
+            self.score += get_score(self.level, lines);
+            self.cleans += lines;
+        }
+        let mut level_up = None;
+        let is_placed = cleared.is_some() || locked.is_some_and(|locked| locked.lines.1 == 0);
+        if is_placed {
+            if self.mode.has_undo() {
+                self.push_history();
+            }
+            let level = self.mode.level(self.cleans);
+            if self.level != level {
+                level_up = (level > self.level).then_some(LevelUp { level });
+                self.level = level;
+                self.falling_timer
+                    .set_duration(Duration::from_secs_f32(get_speed(level)));
+            }
+        }
+        (level_up, update_mode_rules(self))
+    }
+
+    /// Records the current placement, dropping anything that was undone.
+    pub fn push_history(&mut self) {
+        if !self.history.is_empty() {
+            self.history.truncate(self.history_index + 1);
+        }
+        self.history.push(Snapshot {
+            board: self.board.clone(),
+            falling_brick_shape: self.falling_brick_shape,
+            next_brick_shape: self.next_brick_shape,
+            rng: self.rng,
+            level: self.level,
+            score: self.score,
+            cleans: self.cleans,
+        });
+        self.history_index = self.history.len() - 1;
+    }
+
+    /// Steps back to the previous placement, returns false when there is none.
+    pub fn undo(&mut self) -> bool {
+        if self.history_index == 0 || self.history.is_empty() {
+            return false;
+        }
+        self.history_index -= 1;
+        self.restore(self.history[self.history_index].clone());
+        true
+    }
+
+    /// Reapplies an undone placement, returns false when there is none.
+    pub fn redo(&mut self) -> bool {
+        if self.history_index + 1 >= self.history.len() {
+            return false;
+        }
+        self.history_index += 1;
+        self.restore(self.history[self.history_index].clone());
+        true
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.board = snapshot.board;
+        self.falling_brick_shape = snapshot.falling_brick_shape;
+        self.next_brick_shape = snapshot.next_brick_shape;
+        self.rng = snapshot.rng;
+        self.level = snapshot.level;
+        self.score = snapshot.score;
+        self.cleans = snapshot.cleans;
+        self.falling_brick_node = self.new_falling_brick_node();
+        self.spawn_tick = self.tick;
+        self.last_action_rotated = false;
+        self.finesse.inputs = 0;
+        self.stop_drop();
+    }
+
+    /// Adds a garbage row with a seeded hole column, returns true on top-out.
+    pub fn push_garbage_row(&mut self) -> bool {
+        let hole_col = self.rng.random_range(0..self.board.cols);
+        self.board.push_garbage_row(hole_col)
+    }
+
+    /// Adds `rows` garbage rows sharing one hole column, returns true on top-out.
+    pub fn push_garbage_rows(&mut self, rows: u32, hole_col: usize) -> bool {
+        for _ in 0..rows {
+            if self.board.push_garbage_row(hole_col) {
+                return true;
+            }
+        }
+        self.lift_falling_brick();
+        false
+    }
+
+    /// Pushes the falling brick up until the rising stack no longer reaches it.
+    pub fn lift_falling_brick(&mut self) {
+        while self
+            .falling_brick()
+            .nodes
+            .iter()
+            .any(|node| self.board.is_brick_node_occupied(node))
+        {
+            self.falling_brick_node.move_up_steps(1);
+        }
+    }
+
+    /// Adds a row of randomly scattered blocks that is never already full.
+    pub fn push_scattered_garbage_row(&mut self) -> bool {
+        let mut cells = vec![false; self.board.cols];
+        cells
+            .iter_mut()
+            .for_each(|cell| *cell = self.rng.random_bool(0.5));
+        let hole_col = self.rng.random_range(0..self.board.cols);
+        cells[hole_col] = false;
+        self.board.push_garbage_cells(&cells)
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use super::Board;
+    use std::time::Duration;
 
-    use crate::brick::{Brick, BrickNode};
+    use super::{ActionOutcome, Board, Fall, GameAction, GameData};
+
+    use crate::{
+        board::get_speed,
+        brick::{Brick, BrickNode, BrickShape, brick_at},
+        constants::BRICKS_DICT,
+        events::{DropStarted, PieceMoved},
+        mode::GameMode,
+    };
 
     #[test]
     fn test_board_update_occupied() {
@@ -218,7 +949,7 @@ mod tests {
             ],
         });
 
-        assert!(!&board.occupied[0..4].iter().any(|b| { !b }));
+        assert!(board.occupied().take(4).all(|b| b));
     }
 
     #[test]
@@ -256,12 +987,270 @@ mod tests {
 
         board.clean((0, 1));
 
-        let has_occupied = board.occupied.iter().any(|v| *v);
+        let has_occupied = board.occupied().any(|v| v);
         assert!(has_occupied);
 
         board.clean((0, 1));
 
-        let has_occupied = board.occupied.iter().any(|v| *v);
+        let has_occupied = board.occupied().any(|v| v);
         assert!(!has_occupied);
     }
+
+    #[test]
+    fn test_board_saves_flat_cells() {
+        let mut board = Board::new(4, 4);
+        board.set_occupied(1, 0, true);
+        board.set_occupied(3, 2, true);
+        let json = serde_json::to_value(&board).unwrap();
+        let occupied: Vec<bool> = serde_json::from_value(json["occupied"].clone()).unwrap();
+        assert!(board.occupied().eq(occupied.iter().copied()));
+        assert!(occupied[1] && occupied[3 + 2 * 4]);
+        assert_eq!(serde_json::from_value::<Board>(json).unwrap(), board);
+    }
+
+    #[test]
+    fn test_board_fits_matches_cells() {
+        let mut board = Board::default();
+        (0..board.rows / 2).for_each(|y| {
+            (0..board.cols)
+                .filter(|x| (x * 7 + y * 3) % 5 != 0)
+                .for_each(|x| board.set_occupied(x, y, true))
+        });
+        for (kind, rotations) in BRICKS_DICT.iter().enumerate() {
+            for rotation in 0..rotations.len() {
+                let shape = BrickShape(kind, rotation);
+                for x in -4..board.cols as i8 + 2 {
+                    for y in -2..board.rows as i8 + 4 {
+                        let expected = brick_at(shape, BrickNode(x, y)).nodes.iter().all(|node| {
+                            node.0 >= 0
+                                && (node.0 as usize) < board.cols
+                                && node.1 >= 0
+                                && !board.is_brick_node_occupied(node)
+                        });
+                        assert_eq!(board.fits(shape, BrickNode(x, y)), expected);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_board_clean_multiple_lines() {
+        let mut board: Board = Board::default();
+        board.push_garbage_row(0);
+        board.push_garbage_row(0);
+        board.update_occupied_by_brick(&Brick {
+            nodes: [
+                BrickNode(0, 0),
+                BrickNode(0, 1),
+                BrickNode(0, 2),
+                BrickNode(0, 3),
+            ],
+        });
+
+        assert_eq!(board.get_clean_lines(), (0, 2));
+        board.clean((0, 2));
+
+        assert!(board.is_brick_node_occupied(&BrickNode(0, 0)));
+        assert!(board.is_brick_node_occupied(&BrickNode(0, 1)));
+        assert!(!board.is_brick_node_occupied(&BrickNode(1, 0)));
+        assert_eq!(board.garbage_rows(), 0);
+    }
+
+    fn run_inputs(seed: u64, ticks: u64) -> GameData {
+        let mut game_data = GameData::default();
+        game_data.start(seed);
+        for tick in 0..ticks {
+            let actions = match tick % 45 {
+                0 => vec![GameAction::Rotate, GameAction::Left],
+                15 => vec![GameAction::Right, GameAction::Right],
+                30 => vec![GameAction::Drop],
+                _ => vec![],
+            };
+            game_data.tick_with(actions);
+        }
+        game_data
+    }
+
+    #[test]
+    fn test_same_inputs_give_identical_boards() {
+        let first = run_inputs(99, 5000);
+        let second = run_inputs(99, 5000);
+        assert!(first.cleans > 0 || first.ended.is_some());
+        assert_eq!(
+            serde_json::to_vec(&first.board).unwrap(),
+            serde_json::to_vec(&second.board).unwrap()
+        );
+        assert_eq!(first.tick, second.tick);
+        assert_eq!(first.score, second.score);
+        assert_eq!(first.falling_brick_node, second.falling_brick_node);
+        assert_eq!(first.rng, second.rng);
+    }
+
+    #[test]
+    fn test_rule_outcomes() {
+        let mut game_data = GameData::default();
+        game_data.start(3);
+        let from = game_data.falling_brick_node;
+        assert_eq!(
+            game_data.apply_action(GameAction::Left),
+            Some(ActionOutcome::Moved(PieceMoved {
+                from,
+                to: BrickNode(from.0 - 1, from.1),
+            }))
+        );
+        assert_eq!(
+            game_data.apply_action(GameAction::Drop),
+            Some(ActionOutcome::DropStarted(DropStarted))
+        );
+        game_data.start_drop();
+        let next_shape = game_data.next_brick_shape;
+        let fall = loop {
+            assert!(game_data.advance_tick());
+            if let Some(fall @ (Fall::Locked(..) | Fall::TopOut)) = game_data.update_gravity() {
+                break fall;
+            }
+        };
+        let Fall::Locked(locked, spawned) = fall else {
+            panic!("the first brick can't top out");
+        };
+        assert_eq!(locked.lines, (0, 0));
+        assert_eq!(spawned.shape, next_shape);
+        assert!(!game_data.is_dropping());
+    }
+
+    #[test]
+    fn test_lock_keeps_level() {
+        let mut game_data = GameData::default();
+        game_data.start(3);
+        let level = game_data.level;
+        let speed = game_data.falling_timer.duration();
+        assert_eq!(speed, Duration::from_secs_f32(get_speed(level)));
+        let outcome = loop {
+            let outcome = game_data.tick_with([GameAction::Drop]);
+            if matches!(outcome.fall, Some(Fall::Locked(..))) {
+                break outcome;
+            }
+        };
+        assert_eq!(outcome.cleared, None);
+        assert_eq!(outcome.level_up, None);
+        assert_eq!(game_data.level, level);
+        assert_eq!(game_data.falling_timer.duration(), speed);
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut game_data = GameData {
+            mode: GameMode::Zen,
+            ..Default::default()
+        };
+        game_data.start(7);
+        assert!(!game_data.undo());
+
+        let brick = Brick {
+            nodes: [
+                BrickNode(0, 0),
+                BrickNode(1, 0),
+                BrickNode(2, 0),
+                BrickNode(3, 0),
+            ],
+        };
+        game_data.board.update_occupied_by_brick(&brick);
+        game_data.score = 10;
+        game_data.push_history();
+
+        assert!(game_data.undo());
+        assert!(!game_data.board.occupied().any(|v| v));
+        assert_eq!(game_data.score, 0);
+        assert!(game_data.redo());
+        assert!(game_data.board.is_brick_node_occupied(&BrickNode(3, 0)));
+        assert_eq!(game_data.score, 10);
+        assert!(!game_data.redo());
+
+        // a new placement after undo drops the redo branch
+        game_data.undo();
+        game_data.push_history();
+        assert!(!game_data.redo());
+        assert_eq!(game_data.history.len(), 2);
+    }
+
+    /// Drops a T brick pointing down into a one cell hole with the cell
+    /// above its left arm filled, rotating it into place first if `rotate`.
+    fn lock_t_into_slot(rotate: bool) -> (usize, usize, bool) {
+        let mut game_data = GameData::default();
+        game_data.start(3);
+        let cols = game_data.board.cols;
+        (0..cols)
+            .filter(|x| *x != 4)
+            .for_each(|x| game_data.board.set_occupied(x, 0, true));
+        game_data.board.set_occupied(3, 2, true);
+        if rotate {
+            game_data.falling_brick_shape = BrickShape(6, 1);
+            game_data.falling_brick_node = BrickNode(3, 2);
+            game_data.apply_action(GameAction::Rotate);
+        } else {
+            game_data.falling_brick_shape = BrickShape(6, 2);
+            game_data.falling_brick_node = BrickNode(3, 2);
+        }
+        game_data.start_drop();
+        match game_data.update_gravity() {
+            Some(Fall::Locked(locked, _)) => (locked.lines.0, locked.lines.1, locked.t_spin),
+            fall => panic!("expected a lock, got {:?}", fall),
+        }
+    }
+
+    #[test]
+    fn test_t_spin() {
+        assert_eq!(lock_t_into_slot(true), (0, 1, true));
+        assert_eq!(lock_t_into_slot(false), (0, 1, false));
+    }
+
+    #[test]
+    fn test_custom_board_size() {
+        let mut game_data = GameData {
+            board: Board::new(6, 12),
+            ..Default::default()
+        };
+        game_data.start(11);
+        assert_eq!(game_data.falling_brick_node, BrickNode(3, 15));
+        assert_eq!(game_data.replay.board_size, (6, 12));
+
+        game_data.board.push_garbage_row(2);
+        game_data.board.update_occupied_by_brick(&Brick {
+            nodes: [
+                BrickNode(2, 0),
+                BrickNode(2, 1),
+                BrickNode(2, 2),
+                BrickNode(2, 3),
+            ],
+        });
+        assert_eq!(game_data.board.get_clean_lines(), (0, 1));
+        assert!(!game_data.board.is_brick_node_in_board(&BrickNode(6, 0)));
+        assert!(game_data.board.is_move_to_right(&BrickNode(5, 4)));
+    }
+
+    #[test]
+    fn test_board_push_garbage_row() {
+        let mut board: Board = Board::default();
+        board.update_occupied_by_brick(&Brick {
+            nodes: [
+                BrickNode(4, 0),
+                BrickNode(5, 0),
+                BrickNode(6, 0),
+                BrickNode(5, 1),
+            ],
+        });
+
+        assert!(!board.push_garbage_row(3));
+        assert!(!board.is_brick_node_occupied(&BrickNode(3, 0)));
+        assert!(board.is_brick_node_occupied(&BrickNode(2, 0)));
+        assert!(board.is_brick_node_occupied(&BrickNode(5, 1)));
+        assert!(board.is_brick_node_occupied(&BrickNode(5, 2)));
+        assert_eq!(board.garbage_rows(), 1);
+
+        (0..17).for_each(|_| {
+            assert!(!board.push_garbage_row(0));
+        });
+        assert!(board.push_garbage_row(0));
+    }
 }
@@ -0,0 +1,327 @@
+use std::io::{self, BufRead, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    brick::BrickNode,
+    game_data::{ActionOutcome, Board, Fall, GameAction, GameData, GameEnd, TickOutcome},
+    mode::GameMode,
+    tbp::{Piece, write_message},
+};
+
+/// A line sent to `--headless`, e.g. `{"action":"tick","n":10}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Request {
+    Left,
+    Right,
+    RotateCw,
+    SoftDrop,
+    /// Drops the brick and runs ticks until it locks.
+    HardDrop,
+    /// Not part of these rules, always answered with an error.
+    Hold,
+    Tick {
+        #[serde(default = "one")]
+        n: u32,
+    },
+    /// Answers with the state and nothing else.
+    State,
+    /// Starts a new game, with the current mode unless one is given.
+    Reset {
+        #[serde(default)]
+        seed: Option<u64>,
+        #[serde(default)]
+        mode: Option<GameMode>,
+    },
+}
+
+fn one() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Moved {
+        from: BrickNode,
+        to: BrickNode,
+    },
+    Rotated {
+        kicked: bool,
+    },
+    Spawned {
+        piece: Piece,
+        node: BrickNode,
+    },
+    Locked {
+        piece: Piece,
+        node: BrickNode,
+        lines: usize,
+        t_spin: bool,
+    },
+    LinesCleared {
+        lines: u32,
+    },
+    LevelUp {
+        level: u32,
+    },
+    TopOut,
+    Finished,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FallingPiece {
+    pub piece: Piece,
+    pub node: BrickNode,
+    pub cells: Vec<BrickNode>,
+}
+
+/// The game as scripts see it, `board.occupied` is row by row from the
+/// bottom, `cols` cells each.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct State<'a> {
+    pub tick: u64,
+    pub board: &'a Board,
+    /// `None` once the game is over.
+    pub piece: Option<FallingPiece>,
+    pub queue: Vec<Piece>,
+    pub score: u32,
+    pub level: u32,
+    pub lines: u32,
+    pub ended: Option<GameEnd>,
+}
+
+impl<'a> State<'a> {
+    pub fn of(game_data: &'a GameData) -> Self {
+        let piece = game_data.ended.is_none().then(|| FallingPiece {
+            piece: Piece::of(game_data.falling_brick_shape),
+            node: game_data.falling_brick_node,
+            cells: game_data.falling_brick().nodes.to_vec(),
+        });
+        Self {
+            tick: game_data.tick,
+            board: &game_data.board,
+            piece,
+            queue: vec![Piece::of(game_data.next_brick_shape)],
+            score: game_data.score,
+            level: game_data.level,
+            lines: game_data.cleans,
+            ended: game_data.ended,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Response<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub events: Vec<Event>,
+    pub state: State<'a>,
+}
+
+fn tick_events(outcome: TickOutcome, events: &mut Vec<Event>) {
+    match outcome.fall {
+        Some(Fall::Moved(moved)) => events.push(Event::Moved {
+            from: moved.from,
+            to: moved.to,
+        }),
+        Some(Fall::Locked(locked, spawned)) => {
+            events.push(Event::Locked {
+                piece: Piece::of(locked.shape),
+                node: locked.node,
+                lines: locked.lines.1,
+                t_spin: locked.t_spin,
+            });
+            events.push(Event::Spawned {
+                piece: Piece::of(spawned.shape),
+                node: spawned.node,
+            });
+        }
+        Some(Fall::TopOut) => events.push(Event::TopOut),
+        None => {}
+    }
+    if let Some(cleared) = outcome.cleared {
+        events.push(Event::LinesCleared {
+            lines: cleared.kind.lines(),
+        });
+    }
+    if let Some(level_up) = outcome.level_up {
+        events.push(Event::LevelUp {
+            level: level_up.level,
+        });
+    }
+    match outcome.ended {
+        Some(GameEnd::Finished) => events.push(Event::Finished),
+        // rising garbage, a lock above the board was reported with the fall
+        Some(GameEnd::TopOut) if outcome.fall != Some(Fall::TopOut) => events.push(Event::TopOut),
+        _ => {}
+    }
+}
+
+/// Applies one request to the game, returning what happened or why it
+/// couldn't be done.
+pub fn handle(game_data: &mut GameData, request: Request) -> Result<Vec<Event>, String> {
+    let mut events = vec![];
+    let action = match request {
+        Request::Left => GameAction::Left,
+        Request::Right => GameAction::Right,
+        Request::RotateCw => GameAction::Rotate,
+        Request::SoftDrop => GameAction::Down,
+        Request::HardDrop => GameAction::Drop,
+        Request::Hold => return Err("hold is not part of these rules".into()),
+        Request::Tick { n } => {
+            for _ in 0..n {
+                tick_events(game_data.step(), &mut events);
+            }
+            return Ok(events);
+        }
+        Request::State => return Ok(events),
+        Request::Reset { seed, mode } => {
+            if let Some(mode) = mode {
+                game_data.mode = mode;
+            }
+            game_data.reset();
+            game_data.start(seed.unwrap_or_else(rand::random));
+            return Ok(events);
+        }
+    };
+    if !game_data.is_running() {
+        return Err("the game is over".into());
+    }
+    match game_data.apply_action(action) {
+        Some(ActionOutcome::Moved(moved)) => events.push(Event::Moved {
+            from: moved.from,
+            to: moved.to,
+        }),
+        Some(ActionOutcome::Rotated(rotated)) => events.push(Event::Rotated {
+            kicked: rotated.kicked,
+        }),
+        Some(ActionOutcome::DropStarted(_)) => {
+            game_data.start_drop();
+            while game_data.is_running() {
+                let outcome = game_data.step();
+                tick_events(outcome, &mut events);
+                if matches!(outcome.fall, Some(Fall::Locked(..))) {
+                    break;
+                }
+            }
+        }
+        Some(ActionOutcome::Spawned(_)) | None => {}
+    }
+    Ok(events)
+}
+
+/// Plays a game driven by JSON lines on `reader`, answering each with a
+/// `Response` line on `writer`. The first line written is the state of a
+/// fresh game. Returns once `reader` is closed.
+pub fn run(reader: impl BufRead, mut writer: impl Write) -> io::Result<()> {
+    let mut game_data = GameData::default();
+    game_data.start(rand::random());
+    write_message(
+        &mut writer,
+        &Response {
+            error: None,
+            events: vec![],
+            state: State::of(&game_data),
+        },
+    )?;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (error, events) = match serde_json::from_str(&line)
+            .map_err(|error| error.to_string())
+            .and_then(|request| handle(&mut game_data, request))
+        {
+            Ok(events) => (None, events),
+            Err(error) => (Some(error), vec![]),
+        };
+        write_message(
+            &mut writer,
+            &Response {
+                error,
+                events,
+                state: State::of(&game_data),
+            },
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::{Event, Request, handle, run};
+
+    use crate::{
+        game_data::{GameData, GameEnd},
+        mode::GameMode,
+    };
+
+    #[test]
+    fn test_scripted_game() {
+        let input = [
+            r#"{"action":"reset","seed":3}"#,
+            r#"{"action":"left"}"#,
+            r#"{"action":"rotate_cw"}"#,
+            r#"{"action":"tick","n":5}"#,
+            r#"{"action":"hold"}"#,
+            r#"{"action":"hard_drop"}"#,
+            "tick 5",
+        ]
+        .join("\n");
+        let mut output = vec![];
+        run(input.as_bytes(), &mut output).unwrap();
+        let responses: Vec<Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(responses.len(), 8);
+
+        let state = &responses[1]["state"];
+        assert_eq!(state["tick"], 0);
+        assert_eq!(state["piece"]["cells"].as_array().unwrap().len(), 4);
+        assert_eq!(state["queue"].as_array().unwrap().len(), 1);
+        assert_eq!(responses[2]["events"][0]["type"], "moved");
+        assert_eq!(responses[4]["state"]["tick"], 5);
+        assert!(responses[5]["error"].is_string());
+
+        let events = responses[6]["events"].as_array().unwrap();
+        let locked = events.iter().position(|event| event["type"] == "locked");
+        assert!(locked.is_some_and(|index| events[index + 1]["type"] == "spawned"));
+        let board = &responses[6]["state"]["board"];
+        let filled = board["occupied"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|cell| cell.as_bool().unwrap())
+            .count();
+        assert_eq!(filled, 4);
+
+        assert!(responses[7]["error"].is_string());
+    }
+
+    #[test]
+    fn test_garbage_top_out_is_sent() {
+        let mut game_data = GameData {
+            mode: GameMode::DigSurvival,
+            ..Default::default()
+        };
+        game_data.start(2);
+        let rows = game_data.board.rows;
+        game_data.board.set_occupied(0, rows - 1, true);
+        let events = handle(&mut game_data, Request::Tick { n: 1200 }).unwrap();
+        assert_eq!(game_data.ended, Some(GameEnd::TopOut));
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| **event == Event::TopOut)
+                .count(),
+            1
+        );
+    }
+}
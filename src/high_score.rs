@@ -0,0 +1,407 @@
+use std::{collections::HashMap, fs, io, path::PathBuf, time::Duration};
+
+use bevy::{
+    color::Color,
+    ecs::{
+        component::Component,
+        entity::Entity,
+        query::{Has, With},
+        system::{Commands, Query, Res, ResMut, Resource, Single},
+    },
+    log::warn,
+    text::{FontSmoothing, JustifyText, Text2d, TextColor, TextFont, TextLayout},
+    time::{Time, Timer, TimerMode},
+    transform::components::Transform,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    GameAssets, TetrisEntity,
+    ai::AiPlayer,
+    board::PlayerBoard,
+    constants::{HIGH_SCORE_CYCLE_SECS, HIGH_SCORE_TABLE_SIZE},
+    game_data::GameData,
+    mode::{GameMode, ResultsText, format_countdown, spawn_results_panel},
+    replay::ReplayPlayer,
+    tbp::TbpBot,
+};
+
+const HIGH_SCORE_FILE: &str = "high_scores.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HighScoreEntry {
+    pub initials: String,
+    /// The score, or the time in milliseconds for modes that rank by time.
+    pub value: u32,
+    pub lines: u32,
+    pub level: u32,
+    pub date: String,
+    /// Placement hints were on during the game, shown next to the entry.
+    #[serde(default)]
+    pub hints: bool,
+}
+
+/// Top results of each mode, keyed by the mode name and saved in the user's
+/// data directory.
+#[derive(Debug, Default, Resource, Serialize, Deserialize)]
+pub struct HighScores {
+    tables: HashMap<String, Vec<HighScoreEntry>>,
+}
+
+impl HighScores {
+    pub fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("rs-tetris").join(HIGH_SCORE_FILE))
+    }
+
+    /// Loads the saved table, starting empty when there is none or it can't be read.
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+        match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|err| {
+                warn!("Ignoring high scores in {}: {}", path.display(), err);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no user data directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn table(&self, mode: GameMode) -> &[HighScoreEntry] {
+        self.tables
+            .get(&mode.name())
+            .map(|table| table.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Position `value` would take in the table of `mode`, if it makes it in.
+    pub fn rank(&self, mode: GameMode, value: u32) -> Option<usize> {
+        if !mode.ranks_by_time() && value == 0 {
+            return None;
+        }
+        let table = self.table(mode);
+        let rank = table
+            .iter()
+            .position(|entry| {
+                if mode.ranks_by_time() {
+                    value < entry.value
+                } else {
+                    value > entry.value
+                }
+            })
+            .unwrap_or(table.len());
+        (rank < HIGH_SCORE_TABLE_SIZE).then_some(rank)
+    }
+
+    pub fn insert(&mut self, mode: GameMode, entry: HighScoreEntry) -> Option<usize> {
+        let rank = self.rank(mode, entry.value)?;
+        let table = self.tables.entry(mode.name()).or_default();
+        table.insert(rank, entry);
+        table.truncate(HIGH_SCORE_TABLE_SIZE);
+        Some(rank)
+    }
+}
+
+/// Three initials picked letter by letter with the arrow buttons.
+#[derive(Debug, Resource)]
+pub struct InitialsEntry {
+    pub active: bool,
+    pub letters: [u8; 3],
+    pub cursor: usize,
+}
+
+impl Default for InitialsEntry {
+    fn default() -> Self {
+        InitialsEntry {
+            active: false,
+            letters: *b"AAA",
+            cursor: 0,
+        }
+    }
+}
+
+impl InitialsEntry {
+    /// Starts a new entry, keeping the last initials like the handheld does.
+    pub fn start(&mut self) {
+        self.active = true;
+        self.cursor = 0;
+    }
+
+    pub fn next_letter(&mut self) {
+        let letter = &mut self.letters[self.cursor];
+        *letter = if *letter == b'Z' { b'A' } else { *letter + 1 };
+    }
+
+    pub fn prev_letter(&mut self) {
+        let letter = &mut self.letters[self.cursor];
+        *letter = if *letter == b'A' { b'Z' } else { *letter - 1 };
+    }
+
+    pub fn move_left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn move_right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.letters.len() - 1);
+    }
+
+    pub fn initials(&self) -> String {
+        String::from_utf8_lossy(&self.letters).into()
+    }
+
+    fn text(&self) -> String {
+        self.letters
+            .iter()
+            .enumerate()
+            .map(|(i, letter)| {
+                if i == self.cursor {
+                    format!("[{}]", *letter as char)
+                } else {
+                    format!(" {} ", *letter as char)
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Component)]
+pub struct HighScoreText {
+    timer: Timer,
+    index: usize,
+    mode: GameMode,
+}
+
+/// The value a finished game is ranked by in its mode.
+pub fn record_value(game_data: &GameData) -> u32 {
+    if game_data.mode.ranks_by_time() {
+        game_data.play_duration.as_millis() as u32
+    } else {
+        game_data.score
+    }
+}
+
+pub fn format_value(mode: GameMode, value: u32) -> String {
+    if mode.ranks_by_time() {
+        format_countdown(Duration::from_millis(value as u64))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Marks the results of a game played with placement hints.
+pub fn hints_note(game_data: &GameData) -> &'static str {
+    if game_data.hints_used {
+        "\nHints used"
+    } else {
+        ""
+    }
+}
+
+/// Saves the entered initials with the result of the finished game.
+pub fn save_high_score(
+    game_data: &GameData,
+    high_scores: &mut HighScores,
+    initials_entry: &mut InitialsEntry,
+) {
+    if !initials_entry.active {
+        return;
+    }
+    initials_entry.active = false;
+    high_scores.insert(
+        game_data.mode,
+        HighScoreEntry {
+            initials: initials_entry.initials(),
+            value: record_value(game_data),
+            lines: game_data.cleans,
+            level: game_data.level,
+            date: chrono::Local::now().format("%Y-%m-%d").to_string(),
+            hints: game_data.hints_used,
+        },
+    );
+    if let Err(err) = high_scores.save() {
+        warn!("Failed to save high scores: {}", err);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn show_game_over(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    player_board: Single<(&GameData, Has<AiPlayer>, Has<TbpBot>), With<PlayerBoard>>,
+    high_scores: Res<HighScores>,
+    mut initials_entry: ResMut<InitialsEntry>,
+    replay_player: Res<ReplayPlayer>,
+) {
+    let (game_data, is_cpu, is_bot) = *player_board;
+    let mode = game_data.mode;
+    // a time only counts when the goal was reached
+    if !mode.ranks_by_time()
+        && !replay_player.is_active()
+        && !is_cpu
+        && !is_bot
+        && high_scores.rank(mode, game_data.score).is_some()
+    {
+        initials_entry.start();
+    }
+    let summary = format!(
+        "GAME OVER\n\nScore {}\nLines {}\nLevel {}\nTime {}{}",
+        game_data.score,
+        game_data.cleans,
+        game_data.level,
+        format_countdown(game_data.play_duration),
+        hints_note(game_data),
+    );
+    spawn_results_panel(&mut commands, &game_assets, summary);
+}
+
+pub fn results_text_system(
+    initials_entry: Res<InitialsEntry>,
+    mut query: Query<(&ResultsText, &mut Text2d)>,
+) {
+    for (results_text, mut text) in query.iter_mut() {
+        let value = if initials_entry.active {
+            format!(
+                "{}\n\nName {}\nDrop to save",
+                results_text.0,
+                initials_entry.text()
+            )
+        } else {
+            format!("{}\n\nDrop to continue", results_text.0)
+        };
+        if **text != value {
+            **text = value;
+        }
+    }
+}
+
+pub fn spawn_high_score_text(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    game_data: Single<&GameData, With<PlayerBoard>>,
+) {
+    commands.spawn((
+        TetrisEntity,
+        Text2d::default(),
+        TextColor(Color::BLACK),
+        TextLayout::new_with_justify(JustifyText::Center),
+        TextFont {
+            font: game_assets.font.clone(),
+            font_size: 14.0,
+            font_smoothing: FontSmoothing::AntiAliased,
+        },
+        Transform::from_xyz(-40., 18., 300.),
+        HighScoreText {
+            timer: Timer::from_seconds(HIGH_SCORE_CYCLE_SECS, TimerMode::Repeating),
+            index: 0,
+            mode: game_data.mode,
+        },
+    ));
+}
+
+pub fn despawn_high_score_text(
+    mut commands: Commands,
+    high_score_text: Single<Entity, With<HighScoreText>>,
+) {
+    commands.entity(high_score_text.into_inner()).despawn();
+}
+
+/// Cycles through the table of the selected mode on the Ready screen.
+pub fn high_score_text_system(
+    time: Res<Time>,
+    game_data: Single<&GameData, With<PlayerBoard>>,
+    high_scores: Res<HighScores>,
+    high_score_text: Single<(&mut HighScoreText, &mut Text2d)>,
+) {
+    let (mut high_score_text, mut text) = high_score_text.into_inner();
+    if high_score_text.mode != game_data.mode {
+        high_score_text.mode = game_data.mode;
+        high_score_text.index = 0;
+        high_score_text.timer.reset();
+    } else if high_score_text.timer.tick(time.delta()).just_finished() {
+        high_score_text.index += 1;
+    }
+
+    let table = high_scores.table(game_data.mode);
+    let value = if table.is_empty() {
+        "No records".to_string()
+    } else {
+        let index = high_score_text.index % table.len();
+        let entry = &table[index];
+        format!(
+            "{:>2} {} {}{}",
+            index + 1,
+            entry.initials,
+            format_value(game_data.mode, entry.value),
+            if entry.hints { "*" } else { "" }
+        )
+    };
+    if **text != value {
+        **text = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HighScoreEntry, HighScores, InitialsEntry};
+
+    use crate::{constants::HIGH_SCORE_TABLE_SIZE, mode::GameMode};
+
+    fn entry(value: u32) -> HighScoreEntry {
+        HighScoreEntry {
+            initials: "AAA".into(),
+            value,
+            lines: 0,
+            level: 0,
+            date: String::new(),
+            hints: false,
+        }
+    }
+
+    #[test]
+    fn test_insert_keeps_top_scores() {
+        let mode = GameMode::default();
+        let mut high_scores = HighScores::default();
+        assert_eq!(high_scores.rank(mode, 0), None);
+        for value in 1..=12 {
+            high_scores.insert(mode, entry(value * 100));
+        }
+        let table = high_scores.table(mode);
+        assert_eq!(table.len(), HIGH_SCORE_TABLE_SIZE);
+        assert_eq!(table[0].value, 1200);
+        assert_eq!(table[HIGH_SCORE_TABLE_SIZE - 1].value, 300);
+        assert_eq!(high_scores.rank(mode, 300), None);
+        assert_eq!(high_scores.rank(mode, 301), Some(9));
+        assert!(high_scores.table(GameMode::Zen).is_empty());
+    }
+
+    #[test]
+    fn test_insert_ranks_by_time() {
+        let mode = GameMode::Dig(5);
+        let mut high_scores = HighScores::default();
+        high_scores.insert(mode, entry(60_000));
+        assert_eq!(high_scores.insert(mode, entry(30_000)), Some(0));
+        assert_eq!(high_scores.insert(mode, entry(90_000)), Some(2));
+    }
+
+    #[test]
+    fn test_initials_entry() {
+        let mut initials_entry = InitialsEntry::default();
+        initials_entry.prev_letter();
+        initials_entry.move_right();
+        initials_entry.next_letter();
+        initials_entry.move_right();
+        initials_entry.move_right();
+        initials_entry.next_letter();
+        initials_entry.next_letter();
+        assert_eq!(initials_entry.initials(), "ZBC");
+    }
+}
@@ -0,0 +1,166 @@
+use bevy::{
+    color::{Color, Srgba},
+    ecs::{
+        component::Component,
+        entity::Entity,
+        query::With,
+        system::{Commands, Query, Res, ResMut, Resource, Single},
+    },
+    hierarchy::{BuildChildren, ChildBuild, Children},
+    input::{ButtonInput, keyboard::KeyCode},
+    math::Vec2,
+    render::view::Visibility,
+    sprite::Sprite,
+    transform::components::Transform,
+    utils::default,
+};
+use bevy_prototype_lyon::{draw::Stroke, entity::ShapeBundle, prelude::GeometryBuilder, shapes};
+
+use crate::{
+    ai::{AiWeights, best_route},
+    board::PlayerBoard,
+    brick::{BrickNode, BrickShape, brick_at, get_brick_node_position},
+    constants::BRICK_NODE_WIDTH,
+    game_data::{Board, GameData},
+    replay::ReplayPlayer,
+};
+
+/// Whether placement hints are on, toggled with H.
+#[derive(Debug, Default, Resource)]
+pub struct Hints {
+    pub enabled: bool,
+}
+
+/// Faint outline of where the evaluator would lock the falling brick of the
+/// player's board, or of the finesse trainer's target.
+#[derive(Debug, Default, Component)]
+pub struct HintBrick {
+    target: Option<(BrickShape, BrickNode)>,
+    /// The last search, so a brick is only searched again when the board
+    /// changes under it.
+    searched: Option<HintSearch>,
+}
+
+/// The brick, by its spawn tick, and the board the evaluator was asked
+/// about, with the placement it found.
+#[derive(Debug)]
+struct HintSearch {
+    spawn_tick: u64,
+    board: Board,
+    target: Option<(BrickShape, BrickNode)>,
+}
+
+#[derive(Component)]
+pub struct HintBrickNode;
+
+pub fn hint_setup(mut commands: Commands, player_board: Single<Entity, With<PlayerBoard>>) {
+    let hint_brick = commands
+        .spawn((
+            Sprite {
+                color: Color::NONE,
+                ..default()
+            },
+            Transform::from_xyz(0., 0., 300.),
+            Visibility::Hidden,
+            HintBrick::default(),
+        ))
+        .with_children(|child_builder| {
+            (0..4).for_each(|_| {
+                child_builder.spawn((
+                    ShapeBundle {
+                        path: GeometryBuilder::build_as(&shapes::Rectangle {
+                            extents: Vec2 {
+                                x: BRICK_NODE_WIDTH - 2.,
+                                y: BRICK_NODE_WIDTH - 2.,
+                            },
+                            ..shapes::Rectangle::default()
+                        }),
+                        ..default()
+                    },
+                    Stroke::new(Srgba::hex("#00000060").unwrap(), 1.),
+                    HintBrickNode,
+                ));
+            });
+        })
+        .id();
+    commands
+        .entity(player_board.into_inner())
+        .add_child(hint_brick);
+}
+
+pub fn toggle_hints_system(keyboard_input: Res<ButtonInput<KeyCode>>, mut hints: ResMut<Hints>) {
+    if keyboard_input.just_pressed(KeyCode::KeyH) {
+        hints.enabled = !hints.enabled;
+    }
+}
+
+/// Looks for the best placement of each new brick of the player's board,
+/// counting the next brick too, and outlines it. A game that shows a hint is
+/// marked on its results. The finesse trainer's target is outlined instead
+/// whenever there is one.
+pub fn hint_system(
+    hints: Res<Hints>,
+    weights: Res<AiWeights>,
+    replay_player: Res<ReplayPlayer>,
+    mut game_data: Single<&mut GameData, With<PlayerBoard>>,
+    hint_brick: Single<(&mut HintBrick, &mut Visibility, &Children)>,
+    mut hint_brick_nodes: Query<&mut Transform, With<HintBrickNode>>,
+) {
+    let (mut hint_brick, mut visibility, children) = hint_brick.into_inner();
+    if let Some(target) = game_data.finesse.target {
+        hint_brick.target = Some(target);
+    } else if !hints.enabled || replay_player.is_active() {
+        hint_brick.target = None;
+        *visibility = Visibility::Hidden;
+        return;
+    } else {
+        let is_searched = hint_brick.searched.as_ref().is_some_and(|searched| {
+            searched.spawn_tick == game_data.spawn_tick && searched.board == game_data.board
+        });
+        if !is_searched {
+            let target = best_route(
+                &game_data.board,
+                game_data.falling_brick_shape,
+                game_data.falling_brick_node,
+                game_data.next_brick_shape,
+                &weights,
+            )
+            .map(|route| (route.shape, route.node));
+            hint_brick.searched = Some(HintSearch {
+                spawn_tick: game_data.spawn_tick,
+                board: game_data.board.clone(),
+                target,
+            });
+        }
+        hint_brick.target = hint_brick
+            .searched
+            .as_ref()
+            .and_then(|searched| searched.target);
+    }
+    let Some((shape, node)) = hint_brick.target else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+    if game_data.finesse.target.is_none() && !game_data.hints_used {
+        game_data.hints_used = true;
+    }
+    *visibility = Visibility::Inherited;
+    let brick = brick_at(shape, node);
+    let mut nodes = hint_brick_nodes.iter_many_mut(children);
+    for node in brick.nodes.iter() {
+        let Some(mut transform) = nodes.fetch_next() else {
+            break;
+        };
+        let pos = get_brick_node_position(node, game_data.board.rows);
+        transform.translation.x = pos.x;
+        transform.translation.y = pos.y;
+    }
+}
+
+/// Takes the outline off the board between games.
+pub fn hide_hint(hint_brick: Single<(&mut HintBrick, &mut Visibility)>) {
+    let (mut hint_brick, mut visibility) = hint_brick.into_inner();
+    hint_brick.target = None;
+    hint_brick.searched = None;
+    *visibility = Visibility::Hidden;
+}
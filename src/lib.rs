@@ -0,0 +1,509 @@
+pub mod ai;
+pub mod animation;
+pub mod board;
+pub mod brick;
+pub mod constants;
+pub mod control;
+pub mod decorate;
+pub mod demo;
+pub mod events;
+pub mod finder;
+pub mod finesse;
+pub mod game_data;
+pub mod headless;
+pub mod high_score;
+pub mod hint;
+pub mod mode;
+pub mod net;
+pub mod replay;
+pub mod rng;
+pub mod save;
+pub mod schedule;
+pub mod sim;
+pub mod state;
+pub mod tbp;
+pub mod tune;
+pub mod versus;
+
+use bevy::prelude::*;
+use bevy_prototype_lyon::plugin::ShapePlugin;
+use game_data::{GameData, InputBuffer};
+use state::GameSate;
+use std::time::Duration;
+
+use crate::{
+    ai::{
+        AiPlayer, AiSettings, AiWeights, ai_input_system, ai_text_system, despawn_ai_text, end_ai,
+        spawn_ai_text, toggle_ai_system,
+    },
+    animation::{
+        AnimationIndices, AnimationTimer, despawn_victory_animation, play_ready_animation,
+        play_victory_animation, spawn_victory_animation,
+    },
+    board::{
+        PlayerBoard, board_setup, clock_update_system, render_board_system, render_bricks_system,
+        score_board_system, spawn_bricks, start_new_game,
+    },
+    constants::{BOARD_BRICK_NODE_COLS, BOARD_BRICK_NODE_ROWS, TICK_DURATION},
+    control::{
+        control_direction_system, control_drop_to_close_results, control_drop_to_start_game,
+        control_initials_entry, control_on_click, control_resume_game, control_select_mode,
+        control_setup, pause_game_system, replay_game_system, zen_hotkeys_system,
+    },
+    decorate::decorate_setup,
+    demo::{Demo, end_demo, exit_demo_system, is_demo, start_demo_system},
+    events::{
+        BoardEvent, DropStarted, GameFinished, GarbageSent, LevelUp, LinesCleared, PieceLocked,
+        PieceMoved, PieceRotated, PieceSpawned, TopOut,
+    },
+    finesse::{despawn_finesse_text, finesse_text_system, spawn_finesse_text},
+    high_score::{
+        HighScores, InitialsEntry, despawn_high_score_text, high_score_text_system,
+        results_text_system, show_game_over, spawn_high_score_text,
+    },
+    hint::{Hints, hide_hint, hint_setup, hint_system, toggle_hints_system},
+    mode::{
+        GameMode, despawn_mode_text, hide_results, mode_text_system, show_results, spawn_mode_text,
+    },
+    net::{
+        NetClient, connect_relay, net_lobby_system, net_match_over_system, net_receive_system,
+        net_sync_system, start_online_match,
+    },
+    replay::{
+        ReplayPlayer, control_replay_playback, control_start_replay, is_live_game, is_replaying,
+        replay_text_system, save_replay_system, spawn_replay_text, stop_replay,
+    },
+    save::{
+        SaveGame, SaveSlot, autosave_on_exit_system, despawn_resume_text, load_save_slot,
+        spawn_resume_text,
+    },
+    schedule::{
+        GameSet, feed_inputs_system, game_end_system, gravity_lock_system, line_clear_system,
+        movement_system, scoring_system,
+    },
+    tbp::{TbpBot, end_tbp, tbp_input_system},
+    versus::{
+        AttackTable, end_versus, garbage_exchange_system, garbage_meter_system,
+        show_versus_results, start_versus, versus_end_system, versus_input_system,
+        versus_pause_system,
+    },
+};
+
+/// Options for embedding the game in another app.
+#[derive(Debug, Clone, Resource)]
+pub struct TetrisConfig {
+    pub board_cols: usize,
+    pub board_rows: usize,
+    /// The mode selected when the app starts.
+    pub mode: GameMode,
+    /// Where the game is drawn, relative to the world origin.
+    pub offset: Vec2,
+    /// Set to false when the host app brings its own camera.
+    pub spawn_camera: bool,
+    /// Garbage sent by clears in versus.
+    pub attack_table: AttackTable,
+    /// Address of the relay server to play versus online through.
+    pub relay: Option<String>,
+    /// How the CPU player rates placements.
+    pub ai_weights: AiWeights,
+    /// Command running a Tetris Bot Protocol engine to play instead of the
+    /// built-in CPU.
+    pub bot: Option<String>,
+}
+
+impl Default for TetrisConfig {
+    fn default() -> Self {
+        TetrisConfig {
+            board_cols: BOARD_BRICK_NODE_COLS,
+            board_rows: BOARD_BRICK_NODE_ROWS,
+            mode: GameMode::default(),
+            offset: Vec2::ZERO,
+            spawn_camera: true,
+            attack_table: AttackTable::default(),
+            relay: None,
+            ai_weights: AiWeights::default(),
+            bot: None,
+        }
+    }
+}
+
+/// Parent of everything the game draws, placed at `TetrisConfig::offset`.
+#[derive(Component)]
+pub struct TetrisRoot;
+
+/// Marks the game's top-level entities so they get moved under `TetrisRoot`.
+#[derive(Component, Default)]
+pub struct TetrisEntity;
+
+fn scene_setup(mut commands: Commands, config: Res<TetrisConfig>) {
+    if config.spawn_camera {
+        commands.spawn(Camera2d);
+    }
+    commands.spawn((
+        TetrisRoot,
+        Transform::from_translation(config.offset.extend(0.)),
+        Visibility::default(),
+    ));
+}
+
+/// Moves newly spawned game entities under the root before transforms are
+/// propagated, so they are drawn at the configured offset.
+fn adopt_entities_system(
+    mut commands: Commands,
+    root: Single<Entity, With<TetrisRoot>>,
+    query: Query<Entity, (Added<TetrisEntity>, Without<Parent>)>,
+) {
+    let root = root.into_inner();
+    for entity in &query {
+        commands.entity(entity).set_parent(root);
+    }
+}
+
+#[derive(Resource)]
+pub struct GameAssets {
+    move_button: Handle<Image>,
+    move_button_pressed: Handle<Image>,
+    replay_button: Handle<Image>,
+    replay_button_pressed: Handle<Image>,
+    effect_button: Handle<Image>,
+    effect_button_pressed: Handle<Image>,
+    sound: Handle<Image>,
+    pause: Handle<Image>,
+    dino: Handle<Image>,
+    font: Handle<Font>,
+}
+
+fn load_assets(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut next_state: ResMut<NextState<GameSate>>,
+) {
+    commands.insert_resource(GameAssets {
+        move_button: asset_server.load("move_button.png"),
+        move_button_pressed: asset_server.load("move_button_pressed.png"),
+        replay_button: asset_server.load("replay_button.png"),
+        replay_button_pressed: asset_server.load("replay_button_pressed.png"),
+        effect_button: asset_server.load("effect_button.png"),
+        effect_button_pressed: asset_server.load("effect_button_pressed.png"),
+        pause: asset_server.load("pause.png"),
+        sound: asset_server.load("sound.png"),
+        dino: asset_server.load("dino.png"),
+        font: asset_server.load("digital7mono.ttf"),
+    });
+
+    next_state.set(GameSate::Ready);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn start_game(
+    mut commands: Commands,
+    query: Single<Entity, (With<Sprite>, With<AnimationIndices>)>,
+    player_board: Single<(Entity, &mut GameData, &mut InputBuffer), With<PlayerBoard>>,
+    mut save_slot: ResMut<SaveSlot>,
+    replay_player: Res<ReplayPlayer>,
+    net_client: Option<ResMut<NetClient>>,
+    ai_settings: Res<AiSettings>,
+    config: Res<TetrisConfig>,
+    game_assets: Res<GameAssets>,
+) {
+    let (board, mut game_data, mut input_buffer) = player_board.into_inner();
+    let ready_animation_entity = query.into_inner();
+    commands.entity(ready_animation_entity).despawn();
+    input_buffer.clear();
+    if let Some(mut net_client) = net_client
+        && let Some(seed) = net_client.pending_start.take()
+    {
+        start_online_match(
+            &mut commands,
+            &game_assets,
+            board,
+            &mut game_data,
+            &mut net_client,
+            seed,
+        );
+        return;
+    }
+    if let Some(replay) = &replay_player.replay {
+        replay.begin(&mut game_data);
+        spawn_bricks(&mut commands, board, &game_data);
+        spawn_replay_text(&mut commands, &game_assets);
+        return;
+    }
+    // the saved game is used up either way, a new one is written on pause or exit
+    let save_slot = std::mem::take(&mut *save_slot);
+    SaveGame::remove();
+    let mut ai_board = board;
+    match save_slot.save {
+        Some(save) if save_slot.resume => {
+            save.restore(&mut game_data);
+            spawn_bricks(&mut commands, board, &game_data);
+        }
+        _ => {
+            start_new_game(&mut commands, board, &mut game_data, rand::random());
+            if game_data.mode.is_versus() {
+                ai_board = start_versus(&mut commands, &game_assets, board, &game_data, 0);
+            }
+        }
+    }
+    if let Some(command) = &config.bot {
+        match TbpBot::spawn(command) {
+            Ok(bot) => {
+                commands.entity(ai_board).insert(bot);
+                return;
+            }
+            Err(err) => warn!("Failed to run the bot {}: {}", command, err),
+        }
+    }
+    if let Some(level) = ai_settings.level {
+        commands
+            .entity(ai_board)
+            .insert(AiPlayer::new(level, game_data.seed));
+    }
+}
+
+fn ready_game_system(mut game_data: Single<&mut GameData, With<PlayerBoard>>) {
+    game_data.ready_animation_duration = Duration::default();
+    game_data.is_playing_dino_running_animation = true;
+}
+
+fn spawn_ready_animation_sprite(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
+) {
+    let texture = game_assets.dino.clone();
+    let layout = TextureAtlasLayout::from_grid(UVec2::new(80, 86), 4, 1, None, None);
+    let texture_atlas_layout = texture_atlas_layouts.add(layout);
+    let animation_indices = AnimationIndices { first: 2, last: 3 };
+    commands.spawn((
+        TetrisEntity,
+        Sprite::from_atlas_image(
+            texture,
+            TextureAtlas {
+                layout: texture_atlas_layout,
+                index: animation_indices.first,
+            },
+        ),
+        Transform::from_xyz(-40., 120., 300.),
+        animation_indices,
+        AnimationTimer(Timer::from_seconds(0.1, TimerMode::Repeating)),
+    ));
+}
+
+/// The whole game: states, resources, assets and systems.
+#[derive(Default)]
+pub struct TetrisPlugin {
+    pub config: TetrisConfig,
+}
+
+impl Plugin for TetrisPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ShapePlugin>() {
+            app.add_plugins(ShapePlugin);
+        }
+        app.insert_resource(self.config.clone())
+            .insert_resource(self.config.attack_table.clone())
+            .insert_resource(self.config.ai_weights)
+            .init_resource::<AiSettings>()
+            .init_resource::<Demo>()
+            .init_resource::<Hints>()
+            .init_state::<GameSate>()
+            .insert_resource(HighScores::load())
+            .init_resource::<InitialsEntry>()
+            .init_resource::<SaveSlot>()
+            .init_resource::<ReplayPlayer>()
+            .insert_resource(Time::<Fixed>::from_duration(TICK_DURATION))
+            .add_event::<BoardEvent<PieceSpawned>>()
+            .add_event::<BoardEvent<PieceMoved>>()
+            .add_event::<BoardEvent<PieceRotated>>()
+            .add_event::<BoardEvent<DropStarted>>()
+            .add_event::<BoardEvent<PieceLocked>>()
+            .add_event::<BoardEvent<LevelUp>>()
+            .add_event::<BoardEvent<LinesCleared>>()
+            .add_event::<BoardEvent<TopOut>>()
+            .add_event::<BoardEvent<GameFinished>>()
+            .add_event::<BoardEvent<GarbageSent>>()
+            .configure_sets(
+                FixedUpdate,
+                (
+                    GameSet::Input,
+                    GameSet::Movement,
+                    GameSet::GravityLock,
+                    GameSet::LineClear,
+                    GameSet::Scoring,
+                )
+                    .chain()
+                    .run_if(in_state(GameSate::Playing)),
+            )
+            .configure_sets(
+                Update,
+                (GameSet::Input, GameSet::Presentation)
+                    .chain()
+                    .run_if(in_state(GameSate::Playing)),
+            )
+            .add_systems(PreStartup, load_assets)
+            .add_systems(
+                PostUpdate,
+                adopt_entities_system.before(TransformSystem::TransformPropagate),
+            )
+            .add_systems(
+                Startup,
+                (
+                    (
+                        scene_setup,
+                        decorate_setup,
+                        control_setup,
+                        board_setup,
+                        hint_setup,
+                    )
+                        .chain(),
+                    connect_relay,
+                ),
+            )
+            .add_systems(
+                OnEnter(GameSate::Ready),
+                (
+                    ready_game_system,
+                    spawn_ready_animation_sprite,
+                    spawn_mode_text,
+                    spawn_ai_text,
+                    spawn_high_score_text,
+                    (load_save_slot, spawn_resume_text).chain(),
+                    stop_replay,
+                    end_versus,
+                    end_ai,
+                    end_tbp,
+                    end_demo,
+                    hide_hint,
+                    despawn_finesse_text,
+                ),
+            )
+            .add_systems(
+                OnExit(GameSate::Ready),
+                (
+                    despawn_mode_text,
+                    despawn_ai_text,
+                    despawn_high_score_text,
+                    despawn_resume_text,
+                ),
+            )
+            .add_systems(OnEnter(GameSate::Playing), (start_game, spawn_finesse_text))
+            .add_systems(Update, control_on_click)
+            .add_systems(
+                FixedUpdate,
+                (
+                    feed_inputs_system.in_set(GameSet::Input),
+                    ai_input_system.in_set(GameSet::Input),
+                    tbp_input_system.in_set(GameSet::Input),
+                    movement_system.in_set(GameSet::Movement),
+                    gravity_lock_system.in_set(GameSet::GravityLock),
+                    line_clear_system.in_set(GameSet::LineClear),
+                    scoring_system.in_set(GameSet::Scoring),
+                    garbage_exchange_system
+                        .in_set(GameSet::Scoring)
+                        .after(scoring_system),
+                    (
+                        net_receive_system.in_set(GameSet::Input),
+                        net_sync_system
+                            .in_set(GameSet::Scoring)
+                            .after(garbage_exchange_system),
+                    )
+                        .run_if(resource_exists::<NetClient>),
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    (
+                        control_direction_system,
+                        replay_game_system,
+                        pause_game_system,
+                        zen_hotkeys_system,
+                        versus_input_system,
+                    )
+                        .run_if(is_live_game),
+                    control_replay_playback.run_if(is_replaying),
+                )
+                    .in_set(GameSet::Input),
+            )
+            .add_systems(
+                Update,
+                (
+                    replay_text_system.run_if(is_replaying),
+                    score_board_system,
+                    game_end_system,
+                    render_board_system,
+                    render_bricks_system,
+                    versus_pause_system,
+                    garbage_meter_system,
+                    versus_end_system,
+                    hint_system,
+                    finesse_text_system,
+                )
+                    .in_set(GameSet::Presentation),
+            )
+            .add_systems(Update, (clock_update_system, toggle_hints_system))
+            .add_systems(
+                Update,
+                exit_demo_system
+                    .after(GameSet::Presentation)
+                    .run_if(is_demo),
+            )
+            .add_systems(
+                Update,
+                (
+                    play_ready_animation,
+                    control_drop_to_start_game,
+                    control_select_mode,
+                    control_resume_game,
+                    control_start_replay,
+                    mode_text_system,
+                    ai_text_system,
+                    toggle_ai_system,
+                    high_score_text_system,
+                    start_demo_system,
+                    net_lobby_system.run_if(resource_exists::<NetClient>),
+                )
+                    .run_if(in_state(GameSate::Ready)),
+            )
+            .add_systems(
+                OnEnter(GameSate::Results),
+                (
+                    show_results,
+                    show_versus_results,
+                    spawn_victory_animation,
+                    save_replay_system,
+                    net_match_over_system.run_if(resource_exists::<NetClient>),
+                ),
+            )
+            .add_systems(
+                OnExit(GameSate::Results),
+                (hide_results, despawn_victory_animation),
+            )
+            .add_systems(
+                Update,
+                (
+                    control_drop_to_close_results,
+                    control_initials_entry,
+                    results_text_system,
+                    play_victory_animation,
+                )
+                    .run_if(in_state(GameSate::Results)),
+            )
+            .add_systems(Last, autosave_on_exit_system)
+            .add_systems(
+                OnEnter(GameSate::GameOver),
+                (show_game_over, save_replay_system),
+            )
+            .add_systems(OnExit(GameSate::GameOver), hide_results)
+            .add_systems(
+                Update,
+                (
+                    control_drop_to_close_results,
+                    control_initials_entry,
+                    results_text_system,
+                )
+                    .run_if(in_state(GameSate::GameOver)),
+            );
+    }
+}
@@ -0,0 +1,570 @@
+use std::time::Duration;
+
+use bevy::{
+    color::{Color, Srgba},
+    ecs::{
+        component::Component,
+        entity::Entity,
+        query::{Has, With},
+        system::{Commands, Res, ResMut, Single},
+    },
+    hierarchy::{BuildChildren, DespawnRecursiveExt},
+    math::Vec2,
+    text::{FontSmoothing, JustifyText, Text2d, TextColor, TextFont, TextLayout},
+    transform::components::Transform,
+    utils::default,
+};
+use bevy_prototype_lyon::{
+    draw::{Fill, Stroke},
+    entity::ShapeBundle,
+    prelude::GeometryBuilder,
+    shapes,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    GameAssets, TetrisEntity,
+    ai::AiPlayer,
+    board::{PlayerBoard, get_level},
+    constants::{
+        B_TYPE_BONUS, B_TYPE_HEIGHT_ROWS, B_TYPE_LINES, DIG_GARBAGE_ROWS,
+        DIG_SURVIVAL_GARBAGE_ROWS, DIG_SURVIVAL_INTERVAL_SECS, MARATHON_LINES, MAX_LEVEL,
+        TICK_DURATION, ULTRA_DURATION_SECS, VARIABLE_GOAL_LINES,
+    },
+    game_data::{EraseAnimationStep, GameData, GameEnd},
+    high_score::{HighScores, InitialsEntry, format_value, hints_note, record_value},
+    replay::ReplayPlayer,
+    tbp::TbpBot,
+};
+
+/// How many lines it takes to advance a level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LevelGoal {
+    /// Every 10 lines.
+    #[default]
+    Fixed,
+    /// 5 lines times the next level, so later levels take longer.
+    Variable,
+}
+
+impl LevelGoal {
+    pub fn level(&self, clean_lines: u32) -> u32 {
+        match self {
+            LevelGoal::Fixed => get_level(clean_lines),
+            LevelGoal::Variable => {
+                let mut level = 0;
+                let mut goal = VARIABLE_GOAL_LINES;
+                while clean_lines >= goal && level < MAX_LEVEL {
+                    level += 1;
+                    goal += VARIABLE_GOAL_LINES * (level + 1);
+                }
+                level
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GameMode {
+    /// Ends after the given number of lines, or only by top-out when 0.
+    Marathon(u32, LevelGoal),
+    /// Score attack within the given number of seconds.
+    Ultra(u64),
+    /// Race to clear the given number of garbage rows.
+    Dig(u32),
+    /// Garbage keeps rising from below until top-out.
+    DigSurvival,
+    /// Clear 25 lines starting over scattered garbage of the given height.
+    BType(u32),
+    /// Practice without gravity or top-out, with undo/redo of placements.
+    Zen,
+    /// Single bricks on an empty board, each to be placed on a target with
+    /// as few inputs as possible.
+    Finesse,
+    /// Two players side by side sending garbage to each other, the last one
+    /// standing wins.
+    Versus,
+}
+
+impl Default for GameMode {
+    fn default() -> Self {
+        GameMode::Marathon(0, LevelGoal::Fixed)
+    }
+}
+
+impl GameMode {
+    /// Modes offered on the Ready screen, in selection order.
+    pub fn all() -> Vec<GameMode> {
+        let mut modes = vec![GameMode::default()];
+        modes.extend(
+            ULTRA_DURATION_SECS
+                .iter()
+                .map(|secs| GameMode::Ultra(*secs)),
+        );
+        modes.extend(DIG_GARBAGE_ROWS.iter().map(|rows| GameMode::Dig(*rows)));
+        modes.push(GameMode::DigSurvival);
+        modes.push(GameMode::BType(0));
+        modes.push(GameMode::Zen);
+        modes.push(GameMode::Finesse);
+        modes.push(GameMode::Versus);
+        modes
+    }
+
+    fn index(&self, modes: &[GameMode]) -> usize {
+        modes
+            .iter()
+            .position(|mode| match (mode, self) {
+                (GameMode::Marathon(..), GameMode::Marathon(..)) => true,
+                (GameMode::BType(_), GameMode::BType(_)) => true,
+                _ => mode == self,
+            })
+            .unwrap_or(0)
+    }
+
+    pub fn next(&self) -> Self {
+        let modes = Self::all();
+        modes[(self.index(&modes) + 1) % modes.len()]
+    }
+
+    pub fn prev(&self) -> Self {
+        let modes = Self::all();
+        modes[(self.index(&modes) + modes.len() - 1) % modes.len()]
+    }
+
+    /// Cycles the secondary setting of the mode: the Marathon length and
+    /// level goal, or the B-Type height.
+    pub fn next_option(&self) -> Self {
+        match self {
+            GameMode::Marathon(lines, goal) => {
+                let index = MARATHON_LINES.iter().position(|l| l == lines).unwrap_or(0);
+                let index = (index + 1) % MARATHON_LINES.len();
+                let goal = match (index, goal) {
+                    (0, LevelGoal::Fixed) => LevelGoal::Variable,
+                    (0, LevelGoal::Variable) => LevelGoal::Fixed,
+                    (_, goal) => *goal,
+                };
+                GameMode::Marathon(MARATHON_LINES[index], goal)
+            }
+            GameMode::BType(height) => {
+                GameMode::BType((height + 1) % B_TYPE_HEIGHT_ROWS.len() as u32)
+            }
+            mode => *mode,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            GameMode::Marathon(lines, goal) => {
+                let lines = if *lines == 0 {
+                    "".to_string()
+                } else {
+                    format!(" {}", lines)
+                };
+                let goal = match goal {
+                    LevelGoal::Fixed => "",
+                    LevelGoal::Variable => " V",
+                };
+                format!("Marathon{}{}", lines, goal)
+            }
+            GameMode::Ultra(secs) => {
+                format!("Ultra {}", format_countdown(Duration::from_secs(*secs)))
+            }
+            GameMode::Dig(rows) => format!("Dig {}", rows),
+            GameMode::DigSurvival => "Dig Survival".into(),
+            GameMode::BType(height) => format!("B-Type H{}", height),
+            GameMode::Zen => "Zen".into(),
+            GameMode::Finesse => "Finesse".into(),
+            GameMode::Versus => "Versus".into(),
+        }
+    }
+
+    pub fn time_limit(&self) -> Option<Duration> {
+        match self {
+            GameMode::Ultra(secs) => Some(Duration::from_secs(*secs)),
+            _ => None,
+        }
+    }
+
+    /// What the clock shows while playing, if the mode is timed.
+    pub fn clock(&self, play_duration: Duration) -> Option<Duration> {
+        match self {
+            GameMode::Ultra(secs) => Some(Duration::from_secs(*secs).saturating_sub(play_duration)),
+            GameMode::Dig(_) | GameMode::Versus => Some(play_duration),
+            _ => None,
+        }
+    }
+
+    /// Garbage rows on the board when the game starts.
+    pub fn garbage_rows(&self) -> u32 {
+        match self {
+            GameMode::Dig(rows) => *rows,
+            GameMode::DigSurvival => DIG_SURVIVAL_GARBAGE_ROWS,
+            _ => 0,
+        }
+    }
+
+    /// Rows of randomly scattered garbage on the board when the game starts.
+    pub fn scattered_garbage_rows(&self) -> u32 {
+        match self {
+            GameMode::BType(height) => B_TYPE_HEIGHT_ROWS[*height as usize],
+            _ => 0,
+        }
+    }
+
+    /// Lines to clear to finish the game.
+    pub fn lines_goal(&self) -> Option<u32> {
+        match self {
+            GameMode::Marathon(lines, _) if *lines > 0 => Some(*lines),
+            GameMode::BType(_) => Some(B_TYPE_LINES),
+            _ => None,
+        }
+    }
+
+    /// Whether the mode's settings are ones the game offers, a saved mode
+    /// may not be.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            GameMode::BType(height) => (*height as usize) < B_TYPE_HEIGHT_ROWS.len(),
+            _ => true,
+        }
+    }
+
+    /// Score added when the goal is reached at `level`.
+    pub fn clear_bonus(&self, level: u32) -> u32 {
+        match self {
+            GameMode::BType(height) => B_TYPE_BONUS * (level + height),
+            _ => 0,
+        }
+    }
+
+    /// Modes that can be won, and so end with the victory animation.
+    pub fn has_goal(&self) -> bool {
+        self.lines_goal().is_some() || matches!(self, GameMode::Dig(_))
+    }
+
+    /// Level reached after clearing `clean_lines`.
+    pub fn level(&self, clean_lines: u32) -> u32 {
+        match self {
+            GameMode::Marathon(_, goal) => goal.level(clean_lines),
+            _ => get_level(clean_lines),
+        }
+    }
+
+    /// How often a new garbage row rises while playing.
+    pub fn garbage_interval(&self) -> Option<Duration> {
+        match self {
+            GameMode::DigSurvival => Some(Duration::from_secs_f32(DIG_SURVIVAL_INTERVAL_SECS)),
+            _ => None,
+        }
+    }
+
+    /// Whether the falling brick moves down on its own.
+    pub fn has_gravity(&self) -> bool {
+        !matches!(self, GameMode::Zen | GameMode::Finesse)
+    }
+
+    /// Whether locking above the board ends the game.
+    pub fn can_top_out(&self) -> bool {
+        !matches!(self, GameMode::Zen | GameMode::Finesse)
+    }
+
+    /// Whether placements are recorded for undo/redo.
+    pub fn has_undo(&self) -> bool {
+        *self == GameMode::Zen
+    }
+
+    pub fn is_versus(&self) -> bool {
+        *self == GameMode::Versus
+    }
+
+    /// Whether each brick gets a target and leaves the board empty once placed.
+    pub fn is_finesse_training(&self) -> bool {
+        *self == GameMode::Finesse
+    }
+
+    pub fn is_dig(&self) -> bool {
+        matches!(self, GameMode::Dig(_) | GameMode::DigSurvival)
+    }
+
+    /// Modes that are won by finishing fast rank by time instead of score.
+    pub fn ranks_by_time(&self) -> bool {
+        matches!(self, GameMode::Dig(_))
+    }
+
+    fn results_title(&self) -> &'static str {
+        match self {
+            GameMode::Ultra(_) => "TIME UP",
+            GameMode::Marathon(..) => "COMPLETE",
+            GameMode::Dig(_) | GameMode::BType(_) => "CLEAR",
+            _ => "GAME OVER",
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct ModeText;
+
+#[derive(Component)]
+pub struct ResultsPanel;
+
+/// Summary shown on the results panel, above the initials entry.
+#[derive(Component)]
+pub struct ResultsText(pub String);
+
+pub fn format_countdown(duration: Duration) -> String {
+    let secs = duration.as_millis().div_ceil(1000);
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+pub fn spawn_mode_text(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    game_data: Single<&GameData, With<PlayerBoard>>,
+) {
+    commands.spawn((
+        TetrisEntity,
+        Text2d::new(format!("< {} >", game_data.mode.name())),
+        TextColor(Color::BLACK),
+        TextLayout::new_with_justify(JustifyText::Center),
+        TextFont {
+            font: game_assets.font.clone(),
+            font_size: 16.0,
+            font_smoothing: FontSmoothing::AntiAliased,
+        },
+        Transform::from_xyz(-40., 40., 300.),
+        ModeText,
+    ));
+}
+
+pub fn despawn_mode_text(mut commands: Commands, mode_text: Single<Entity, With<ModeText>>) {
+    commands.entity(mode_text.into_inner()).despawn();
+}
+
+pub fn mode_text_system(
+    game_data: Single<&GameData, With<PlayerBoard>>,
+    mode_text: Single<&mut Text2d, With<ModeText>>,
+) {
+    let text = format!("< {} >", game_data.mode.name());
+    let mut mode_text = mode_text.into_inner();
+    if **mode_text != text {
+        **mode_text = text;
+    }
+}
+
+/// Ends the game or adds garbage as the mode requires, run once per tick.
+pub fn update_mode_rules(game_data: &mut GameData) -> Option<GameEnd> {
+    // let a running line clear finish so its score is counted and its rows
+    // are gone before the board changes again
+    if game_data.erase_animation_step == EraseAnimationStep::Playing || !game_data.is_running() {
+        return None;
+    }
+
+    let is_time_up = game_data
+        .mode
+        .time_limit()
+        .is_some_and(|limit| game_data.play_duration >= limit);
+    let is_dug_out =
+        matches!(game_data.mode, GameMode::Dig(_)) && game_data.board.garbage_rows() == 0;
+    if is_time_up || is_dug_out {
+        game_data.ended = Some(GameEnd::Finished);
+        return game_data.ended;
+    }
+
+    if let Some(bonus) = goal_bonus(game_data) {
+        game_data.score += bonus;
+        game_data.ended = Some(GameEnd::Finished);
+        return game_data.ended;
+    }
+
+    if game_data.mode.garbage_interval().is_some()
+        && game_data.garbage_timer.tick(TICK_DURATION).just_finished()
+    {
+        if game_data.push_garbage_row() {
+            game_data.ended = Some(GameEnd::TopOut);
+            return game_data.ended;
+        }
+        game_data.lift_falling_brick();
+    }
+    None
+}
+
+/// The bonus for reaching the lines goal, once the game has.
+fn goal_bonus(game_data: &GameData) -> Option<u32> {
+    game_data
+        .mode
+        .lines_goal()
+        .is_some_and(|goal| game_data.cleans >= goal)
+        .then(|| game_data.mode.clear_bonus(game_data.level))
+}
+
+#[allow(clippy::type_complexity)]
+pub fn show_results(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    player_board: Single<(&GameData, Has<AiPlayer>, Has<TbpBot>), With<PlayerBoard>>,
+    high_scores: Res<HighScores>,
+    mut initials_entry: ResMut<InitialsEntry>,
+    replay_player: Res<ReplayPlayer>,
+) {
+    let (game_data, is_cpu, is_bot) = *player_board;
+    let mode = game_data.mode;
+    // versus has no records, `show_versus_results` names the winner instead
+    if mode.is_versus() {
+        return;
+    }
+    // a replay or a CPU or bot game shows its results against the bests but doesn't
+    // rank
+    let is_ranked = !replay_player.is_active() && !is_cpu && !is_bot;
+    let value = record_value(game_data);
+    let rank = high_scores.rank(mode, value);
+    if is_ranked && rank.is_some() {
+        initials_entry.start();
+    }
+    // the best is the top of the high-score table, this game isn't in it yet
+    let is_new_best = rank == Some(0);
+    let best = match high_scores.table(mode).first() {
+        Some(entry) if !is_new_best => entry.value,
+        _ => value,
+    };
+    let result = if mode.ranks_by_time() {
+        format!("Time {}", format_countdown(game_data.play_duration))
+    } else {
+        format!("Score {}", game_data.score)
+    };
+
+    // only a game that reached its goal got the bonus
+    let bonus = goal_bonus(game_data)
+        .filter(|_| game_data.ended == Some(GameEnd::Finished))
+        .unwrap_or(0);
+    let result = if bonus > 0 {
+        format!("Bonus {}\n{}", bonus, result)
+    } else {
+        result
+    };
+
+    let summary = format!(
+        "{}\n\n{}\nLines {}\nLevel {}\nTime {}\n{} {}{}",
+        mode.results_title(),
+        result,
+        game_data.cleans,
+        game_data.level,
+        format_countdown(game_data.play_duration),
+        if is_new_best { "New best" } else { "Best" },
+        format_value(mode, best),
+        hints_note(game_data),
+    );
+    spawn_results_panel(&mut commands, &game_assets, summary);
+}
+
+/// Spawns the panel covering the board, the text is filled by `results_text_system`.
+pub fn spawn_results_panel(commands: &mut Commands, game_assets: &GameAssets, summary: String) {
+    commands
+        .spawn((
+            TetrisEntity,
+            ShapeBundle {
+                path: GeometryBuilder::build_as(&shapes::Rectangle {
+                    extents: Vec2 { x: 130., y: 210. },
+                    ..shapes::Rectangle::default()
+                }),
+                transform: Transform::from_xyz(-40., 80., 500.),
+                ..default()
+            },
+            Fill::color(Srgba::hex("#9ead86").unwrap()),
+            Stroke::new(Color::BLACK, 2.0),
+            ResultsPanel,
+        ))
+        .with_child((
+            Text2d::default(),
+            TextColor(Color::BLACK),
+            TextLayout::new_with_justify(JustifyText::Center),
+            TextFont {
+                font: game_assets.font.clone(),
+                font_size: 14.0,
+                font_smoothing: FontSmoothing::AntiAliased,
+            },
+            Transform::from_xyz(0., 0., 10.),
+            ResultsText(summary),
+        ));
+}
+
+pub fn hide_results(mut commands: Commands, results_panel: Single<Entity, With<ResultsPanel>>) {
+    commands
+        .entity(results_panel.into_inner())
+        .despawn_recursive();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{GameMode, LevelGoal, format_countdown, goal_bonus, update_mode_rules};
+
+    use crate::{
+        constants::B_TYPE_LINES,
+        game_data::{GameData, GameEnd},
+    };
+
+    #[test]
+    fn test_mode_cycle() {
+        let modes = GameMode::all();
+        let mut mode = GameMode::default();
+        for _ in 0..modes.len() {
+            mode = mode.next();
+        }
+        assert_eq!(mode, GameMode::default());
+        let marathon = GameMode::Marathon(150, LevelGoal::Variable);
+        assert_eq!(marathon.next().prev(), GameMode::default());
+        assert_eq!(marathon.prev(), *modes.last().unwrap());
+        assert_eq!(
+            marathon.next_option(),
+            GameMode::Marathon(200, LevelGoal::Variable)
+        );
+        assert_eq!(
+            GameMode::Marathon(200, LevelGoal::Fixed).next_option(),
+            GameMode::Marathon(0, LevelGoal::Variable)
+        );
+        assert_eq!(GameMode::BType(3).next(), GameMode::Zen);
+        assert_eq!(GameMode::BType(5).next_option(), GameMode::BType(0));
+    }
+
+    #[test]
+    fn test_level_goal() {
+        assert_eq!(LevelGoal::Fixed.level(9), 0);
+        assert_eq!(LevelGoal::Fixed.level(150), 15);
+        assert_eq!(LevelGoal::Variable.level(4), 0);
+        assert_eq!(LevelGoal::Variable.level(5), 1);
+        assert_eq!(LevelGoal::Variable.level(14), 1);
+        assert_eq!(LevelGoal::Variable.level(15), 2);
+        assert_eq!(LevelGoal::Variable.level(150), 7);
+    }
+
+    #[test]
+    fn test_goal_bonus() {
+        let mut game_data = GameData {
+            mode: GameMode::BType(2),
+            level: 3,
+            cleans: B_TYPE_LINES - 1,
+            ..Default::default()
+        };
+        assert_eq!(goal_bonus(&game_data), None);
+        assert_eq!(update_mode_rules(&mut game_data), None);
+        assert_eq!(game_data.score, 0);
+
+        game_data.cleans = B_TYPE_LINES;
+        let bonus = GameMode::BType(2).clear_bonus(3);
+        assert_eq!(goal_bonus(&game_data), Some(bonus));
+        assert_eq!(update_mode_rules(&mut game_data), Some(GameEnd::Finished));
+        assert_eq!(game_data.score, bonus);
+
+        game_data.mode = GameMode::Zen;
+        assert_eq!(goal_bonus(&game_data), None);
+    }
+
+    #[test]
+    fn test_format_countdown() {
+        assert_eq!(format_countdown(Duration::from_secs(120)), "2:00");
+        assert_eq!(format_countdown(Duration::from_millis(59_001)), "1:00");
+        assert_eq!(format_countdown(Duration::from_millis(9_500)), "0:10");
+        assert_eq!(format_countdown(Duration::ZERO), "0:00");
+    }
+}
@@ -0,0 +1,742 @@
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, BufReader, Write},
+    net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver},
+    },
+    thread,
+};
+
+use bevy::{
+    ecs::{
+        component::Component,
+        entity::Entity,
+        event::EventReader,
+        query::{With, Without},
+        system::{Commands, Query, Res, ResMut, Resource, Single},
+    },
+    log::{info, warn},
+    state::state::NextState,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    GameAssets, TetrisConfig,
+    board::{BoardBricks, PlayerBoard, reset_game, spawn_bricks, start_new_game},
+    constants::{NET_MAX_LAG_TICKS, NET_SNAPSHOT_INTERVAL_TICKS},
+    events::{BoardEvent, GarbageSent},
+    game_data::{EraseAnimationStep, Fall, GameAction, GameData, GameEnd, InputBuffer},
+    mode::GameMode,
+    rng::GameRng,
+    save::SaveGame,
+    state::GameSate,
+    versus::{AttackTable, Versus, start_versus},
+};
+
+/// What both players must agree on before a match, taken from whoever asked
+/// for it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ruleset {
+    /// Columns and rows of the board.
+    pub board_size: (usize, usize),
+    pub attack_table: AttackTable,
+}
+
+/// What one player's board did in one tick, enough for the opponent to play
+/// the same tick on their copy of it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    /// The board's tick once this one has run.
+    pub tick: u64,
+    pub actions: Vec<GameAction>,
+    /// Attacks added to the board's garbage meter at the end of the tick.
+    pub garbage: Vec<u32>,
+}
+
+/// A player's board as it was after a tick, sent now and then so the
+/// opponent can check their copy of it and replace it if it drifted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BoardSnapshot {
+    pub tick: u64,
+    pub game: SaveGame,
+    pub ended: Option<GameEnd>,
+    pub last_action_rotated: bool,
+    pub meter: VecDeque<u32>,
+    pub combo: Option<u32>,
+    pub back_to_back: bool,
+    pub sent: u32,
+    pub hole_rng: GameRng,
+}
+
+impl BoardSnapshot {
+    pub fn capture(game_data: &GameData, versus: &Versus) -> Self {
+        BoardSnapshot {
+            tick: game_data.tick,
+            game: SaveGame::from_game_data(game_data),
+            ended: game_data.ended,
+            last_action_rotated: game_data.last_action_rotated,
+            meter: versus.meter.clone(),
+            combo: versus.combo,
+            back_to_back: versus.back_to_back,
+            sent: versus.sent,
+            hole_rng: versus.rng,
+        }
+    }
+
+    /// Puts the snapshot into a freshly reset `GameData` and its `Versus`.
+    pub fn restore(&self, game_data: &mut GameData, versus: &mut Versus) {
+        self.game.restore(game_data);
+        game_data.tick = self.tick;
+        game_data.ended = self.ended;
+        game_data.last_action_rotated = self.last_action_rotated;
+        versus.meter = self.meter.clone();
+        versus.combo = self.combo;
+        versus.back_to_back = self.back_to_back;
+        versus.sent = self.sent;
+        versus.rng = self.hole_rng;
+    }
+}
+
+/// Messages between a client and the relay, one JSON object per line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum NetMessage {
+    /// From the relay once an opponent is found, 0 for whoever connected
+    /// first.
+    Welcome {
+        player: usize,
+    },
+    /// Asks the relay for a match, it sends the first request back to both
+    /// players and drops the rest until the match is over.
+    Start {
+        seed: u64,
+        rules: Ruleset,
+    },
+    Frame(Frame),
+    Snapshot(Box<BoardSnapshot>),
+    /// The match ended for this player, the relay takes new requests once
+    /// both have sent it.
+    MatchOver,
+    /// From the relay when the opponent is gone. Clients also get it when
+    /// their own connection drops.
+    Disconnected,
+}
+
+pub fn write_message(writer: &mut impl Write, message: &NetMessage) -> io::Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())
+}
+
+/// Reads the next message, `Ok(None)` once the other end has closed.
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<NetMessage>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&line)?))
+}
+
+/// A connection to the relay. Messages are read on a thread of their own and
+/// picked up by `poll`.
+#[derive(Resource)]
+pub struct NetClient {
+    stream: TcpStream,
+    incoming: Mutex<Receiver<NetMessage>>,
+    /// Messages not handled yet, oldest first.
+    pub inbox: VecDeque<NetMessage>,
+    /// The rules this client asks for, and the board size it can play on.
+    pub rules: Ruleset,
+    /// Set once the relay has found an opponent.
+    pub player: Option<usize>,
+    /// Seed of a match the relay started, picked up by `start_game`.
+    pub pending_start: Option<u64>,
+    /// Tick of the last frame sent for the player's board.
+    pub sent_tick: u64,
+    /// How many of the board's recorded actions went out in frames.
+    pub sent_actions: usize,
+    pub snapshot_tick: u64,
+    /// Attacks on the player's board waiting for the next frame.
+    pub unsent_garbage: Vec<u32>,
+}
+
+impl NetClient {
+    pub fn connect(addr: impl ToSocketAddrs, rules: Ruleset) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            loop {
+                match read_message(&mut reader) {
+                    Ok(Some(message)) => {
+                        if sender.send(message).is_err() {
+                            return;
+                        }
+                    }
+                    Ok(None) | Err(_) => {
+                        let _ = sender.send(NetMessage::Disconnected);
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(NetClient {
+            stream,
+            incoming: Mutex::new(receiver),
+            inbox: VecDeque::new(),
+            rules,
+            player: None,
+            pending_start: None,
+            sent_tick: 0,
+            sent_actions: 0,
+            snapshot_tick: 0,
+            unsent_garbage: vec![],
+        })
+    }
+
+    pub fn send(&self, message: &NetMessage) {
+        if let Err(err) = write_message(&mut &self.stream, message) {
+            warn!("Failed to send to the relay: {}", err);
+        }
+    }
+
+    /// Moves the messages read so far into the inbox.
+    pub fn poll(&mut self) {
+        let incoming = self.incoming.get_mut().unwrap();
+        self.inbox.extend(incoming.try_iter());
+    }
+
+    /// Asks for a match on this client's rules with a new seed.
+    pub fn request_match(&self) {
+        if self.player.is_none() {
+            info!("Waiting for an opponent to connect to the relay");
+            return;
+        }
+        self.send(&NetMessage::Start {
+            seed: rand::random(),
+            rules: self.rules.clone(),
+        });
+    }
+}
+
+impl Drop for NetClient {
+    fn drop(&mut self) {
+        // also ends the reader thread, and the relay tells the opponent
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+/// The opponent's board in an online match, played from the frames they
+/// send.
+#[derive(Debug, Default, Component)]
+pub struct RemoteBoard {
+    pub frames: VecDeque<Frame>,
+    pub snapshots: VecDeque<BoardSnapshot>,
+    /// Tick of the last frame played.
+    pub tick: u64,
+    /// Attacks of the last frame played, added once the tick is over.
+    pub garbage: Vec<u32>,
+}
+
+/// Connects to the relay given in the config, the game stays offline if it
+/// can't be reached.
+pub fn connect_relay(
+    mut commands: Commands,
+    config: Res<TetrisConfig>,
+    attack_table: Res<AttackTable>,
+) {
+    let Some(addr) = &config.relay else {
+        return;
+    };
+    let rules = Ruleset {
+        board_size: (config.board_cols, config.board_rows),
+        attack_table: attack_table.clone(),
+    };
+    match NetClient::connect(addr, rules) {
+        Ok(net_client) => {
+            info!("Connected to the relay at {}", addr);
+            commands.insert_resource(net_client);
+        }
+        Err(err) => warn!("Failed to connect to the relay at {}: {}", addr, err),
+    }
+}
+
+/// Waits on the Ready screen for an opponent and for the relay to start a
+/// match.
+pub fn net_lobby_system(
+    mut commands: Commands,
+    mut net_client: ResMut<NetClient>,
+    mut next_state: ResMut<NextState<GameSate>>,
+) {
+    net_client.poll();
+    while let Some(message) = net_client.inbox.pop_front() {
+        match message {
+            NetMessage::Welcome { player } => {
+                info!("Matched with an opponent, playing as P{}", player + 1);
+                net_client.player = Some(player);
+            }
+            NetMessage::Start { seed, rules } => {
+                if rules.board_size != net_client.rules.board_size {
+                    warn!(
+                        "The opponent plays on a {:?} board, leaving the relay",
+                        rules.board_size
+                    );
+                    commands.remove_resource::<NetClient>();
+                    return;
+                }
+                commands.insert_resource(rules.attack_table);
+                net_client.pending_start = Some(seed);
+                next_state.set(GameSate::Playing);
+                // the rest belongs to the match
+                return;
+            }
+            NetMessage::Disconnected => {
+                warn!("Lost the connection to the relay");
+                commands.remove_resource::<NetClient>();
+                return;
+            }
+            // left over from the last match
+            NetMessage::Frame(_) | NetMessage::Snapshot(_) | NetMessage::MatchOver => {}
+        }
+    }
+}
+
+/// Starts the match the relay asked for, the opponent's board is played
+/// from their frames.
+pub fn start_online_match(
+    commands: &mut Commands,
+    game_assets: &GameAssets,
+    board: Entity,
+    game_data: &mut GameData,
+    net_client: &mut NetClient,
+    seed: u64,
+) {
+    game_data.mode = GameMode::Versus;
+    start_new_game(commands, board, game_data, seed);
+    let player = net_client.player.unwrap_or_default();
+    let opponent = start_versus(commands, game_assets, board, game_data, player);
+    commands.entity(opponent).insert(RemoteBoard::default());
+    net_client.sent_tick = 0;
+    net_client.sent_actions = 0;
+    net_client.snapshot_tick = 0;
+    net_client.unsent_garbage.clear();
+}
+
+/// Plays a queued frame on the opponent's board straight away, settling its
+/// placement like `garbage_exchange_system` does.
+fn catch_up(
+    frame: Frame,
+    attack_table: &AttackTable,
+    unsent_garbage: &mut Vec<u32>,
+    game_data: &mut GameData,
+    versus: &mut Versus,
+    player_versus: &mut Versus,
+) {
+    let outcome = game_data.tick_with(frame.actions);
+    if let Some(Fall::Locked(locked, _)) = outcome.fall
+        && game_data.is_running()
+    {
+        let (rows, _) = versus.place(attack_table, game_data, locked.lines.1, locked.t_spin);
+        if rows > 0 {
+            player_versus.meter.push_back(rows);
+            unsent_garbage.push(rows);
+        }
+    }
+    versus.meter.extend(frame.garbage);
+}
+
+/// Queues the opponent's frames and plays the next one on their board. The
+/// board waits when none has arrived, so it only ever runs the ticks its
+/// owner ran, and plays the frames that piled up past `NET_MAX_LAG_TICKS`
+/// at once.
+#[allow(clippy::type_complexity)]
+pub fn net_receive_system(
+    mut commands: Commands,
+    mut net_client: ResMut<NetClient>,
+    attack_table: Res<AttackTable>,
+    remote_board: Option<
+        Single<(
+            &mut GameData,
+            &mut InputBuffer,
+            &mut RemoteBoard,
+            &mut Versus,
+        )>,
+    >,
+    player_versus: Option<Single<&mut Versus, (With<PlayerBoard>, Without<RemoteBoard>)>>,
+) {
+    let (Some(remote_board), Some(player_versus)) = (remote_board, player_versus) else {
+        return;
+    };
+    let (mut game_data, mut input_buffer, mut remote, mut versus) = remote_board.into_inner();
+    let mut player_versus = player_versus.into_inner();
+    net_client.poll();
+    while let Some(message) = net_client.inbox.pop_front() {
+        match message {
+            NetMessage::Frame(frame) => remote.frames.push_back(frame),
+            NetMessage::Snapshot(snapshot) => remote.snapshots.push_back(*snapshot),
+            NetMessage::Disconnected => {
+                info!("The opponent left the match");
+                if game_data.ended.is_none() {
+                    game_data.ended = Some(GameEnd::TopOut);
+                }
+                commands.remove_resource::<NetClient>();
+                return;
+            }
+            // requests for another match while this one runs
+            NetMessage::Welcome { .. } | NetMessage::Start { .. } | NetMessage::MatchOver => {}
+        }
+    }
+    while remote.frames.len() > NET_MAX_LAG_TICKS {
+        let frame = remote.frames.pop_front().unwrap();
+        remote.tick = frame.tick;
+        game_data.paused = false;
+        catch_up(
+            frame,
+            &attack_table,
+            &mut net_client.unsent_garbage,
+            &mut game_data,
+            &mut versus,
+            &mut player_versus,
+        );
+    }
+    match remote.frames.pop_front() {
+        Some(frame) => {
+            input_buffer.0.extend(frame.actions);
+            remote.garbage = frame.garbage;
+            remote.tick = frame.tick;
+            game_data.paused = false;
+        }
+        None => game_data.paused = true,
+    }
+}
+
+/// Ends the tick online: adds the opponent's attacks to their board, checks
+/// it against their snapshot of this tick, and sends the player's frame.
+#[allow(clippy::type_complexity)]
+pub fn net_sync_system(
+    mut commands: Commands,
+    mut net_client: ResMut<NetClient>,
+    board_bricks: BoardBricks,
+    player_board: Single<(&GameData, &Versus), (With<PlayerBoard>, Without<RemoteBoard>)>,
+    remote_board: Option<Single<(Entity, &mut GameData, &mut Versus, &mut RemoteBoard)>>,
+    mut garbage_sent: EventReader<BoardEvent<GarbageSent>>,
+) {
+    let Some(remote_board) = remote_board else {
+        return;
+    };
+    let (remote_entity, mut remote_data, mut remote_versus, mut remote) = remote_board.into_inner();
+    let garbage = std::mem::take(&mut remote.garbage);
+    remote_versus.meter.extend(garbage);
+
+    while let Some(snapshot) = remote.snapshots.front()
+        && snapshot.tick <= remote.tick
+    {
+        let snapshot = remote.snapshots.pop_front().unwrap();
+        if snapshot.tick < remote.tick {
+            continue;
+        }
+        let is_in_sync = remote_data.tick == snapshot.tick
+            && BoardSnapshot::capture(&remote_data, &remote_versus) == snapshot;
+        if !is_in_sync {
+            warn!("Out of sync with the opponent at tick {}", snapshot.tick);
+            reset_game(
+                &mut commands,
+                remote_entity,
+                &board_bricks,
+                &mut remote_data,
+            );
+            snapshot.restore(&mut remote_data, &mut remote_versus);
+            spawn_bricks(&mut commands, remote_entity, &remote_data);
+        }
+    }
+
+    // attacks the opponent's board made on the player's this tick
+    let attacks = garbage_sent
+        .read()
+        .filter(|event| event.board == remote_entity)
+        .map(|event| event.event.rows);
+    net_client.unsent_garbage.extend(attacks);
+
+    let (game_data, versus) = *player_board;
+    if game_data.tick <= net_client.sent_tick {
+        return;
+    }
+    let actions = game_data.replay.actions[net_client.sent_actions..]
+        .iter()
+        .map(|(_, action)| *action)
+        .collect();
+    let frame = Frame {
+        tick: game_data.tick,
+        actions,
+        garbage: std::mem::take(&mut net_client.unsent_garbage),
+    };
+    net_client.send(&NetMessage::Frame(frame));
+    net_client.sent_tick = game_data.tick;
+    net_client.sent_actions = game_data.replay.actions.len();
+    // a snapshot can't hold a line clear half way through its animation
+    if game_data.tick >= net_client.snapshot_tick + NET_SNAPSHOT_INTERVAL_TICKS
+        && game_data.erase_animation_step == EraseAnimationStep::NotStart
+    {
+        let snapshot = BoardSnapshot::capture(game_data, versus);
+        net_client.send(&NetMessage::Snapshot(Box::new(snapshot)));
+        net_client.snapshot_tick = game_data.tick;
+    }
+}
+
+/// Tells the relay the match is over so either player can ask for another.
+pub fn net_match_over_system(
+    net_client: Res<NetClient>,
+    remote_boards: Query<(), With<RemoteBoard>>,
+) {
+    if !remote_boards.is_empty() {
+        net_client.send(&NetMessage::MatchOver);
+    }
+}
+
+/// The relay's side of two matched players.
+struct RelayPair {
+    streams: [TcpStream; 2],
+    in_match: bool,
+    /// Which players sent `MatchOver` for the current match.
+    over: [bool; 2],
+}
+
+impl RelayPair {
+    fn send(&mut self, player: usize, line: &str) {
+        // a failed write shows up as a closed connection on its reader
+        let _ = self.streams[player].write_all(line.as_bytes());
+    }
+}
+
+/// Whether a waiting client is still connected, it sends nothing before it
+/// is matched so any readable data means it hung up.
+fn is_open(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let is_open = matches!(
+        stream.peek(&mut [0]),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock
+    );
+    is_open && stream.set_nonblocking(false).is_ok()
+}
+
+/// Runs the relay: pairs clients in the order they connect and passes
+/// messages between the two players of each pair. The relay has no game
+/// logic, it only settles which match request wins.
+pub fn run_relay(listener: TcpListener) -> io::Result<()> {
+    let mut waiting: Option<TcpStream> = None;
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("Failed to accept a client: {}", err);
+                continue;
+            }
+        };
+        match waiting.take() {
+            Some(first) if is_open(&first) => {
+                if let Err(err) = relay_pair([first, stream]) {
+                    warn!("Failed to start a pair: {}", err);
+                }
+            }
+            _ => waiting = Some(stream),
+        }
+    }
+    Ok(())
+}
+
+fn relay_pair(streams: [TcpStream; 2]) -> io::Result<()> {
+    let readers = [streams[0].try_clone()?, streams[1].try_clone()?];
+    for (player, mut stream) in streams.iter().enumerate() {
+        stream.set_nodelay(true)?;
+        write_message(&mut stream, &NetMessage::Welcome { player })?;
+    }
+    let pair = Arc::new(Mutex::new(RelayPair {
+        streams,
+        in_match: false,
+        over: [false; 2],
+    }));
+    for (player, reader) in readers.into_iter().enumerate() {
+        let pair = pair.clone();
+        thread::spawn(move || relay_player(player, reader, &pair));
+    }
+    Ok(())
+}
+
+fn relay_player(player: usize, reader: TcpStream, pair: &Mutex<RelayPair>) {
+    let opponent = 1 - player;
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if !matches!(reader.read_line(&mut line), Ok(len) if len > 0) {
+            break;
+        }
+        let Ok(message) = serde_json::from_str::<NetMessage>(&line) else {
+            warn!("P{} sent something that isn't a message", player + 1);
+            break;
+        };
+        let mut pair = pair.lock().unwrap();
+        match message {
+            NetMessage::Start { .. } => {
+                if !pair.in_match {
+                    pair.in_match = true;
+                    pair.over = [false; 2];
+                    // sent under one lock, both players see the same request first
+                    pair.send(0, &line);
+                    pair.send(1, &line);
+                }
+            }
+            NetMessage::MatchOver => {
+                pair.over[player] = true;
+                if pair.over == [true; 2] {
+                    pair.in_match = false;
+                }
+            }
+            _ => pair.send(opponent, &line),
+        }
+    }
+    let mut pair = pair.lock().unwrap();
+    let mut disconnected = serde_json::to_string(&NetMessage::Disconnected).unwrap();
+    disconnected.push('\n');
+    pair.send(opponent, &disconnected);
+    let _ = pair.streams[opponent].shutdown(Shutdown::Both);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::BufReader,
+        net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+        thread,
+    };
+
+    use bevy::ecs::entity::Entity;
+
+    use super::{
+        BoardSnapshot, Frame, NetMessage, Ruleset, catch_up, read_message, run_relay, write_message,
+    };
+
+    use crate::{
+        game_data::{GameAction, GameData},
+        mode::GameMode,
+        versus::{AttackTable, Versus},
+    };
+
+    fn connect(addr: SocketAddr) -> (TcpStream, BufReader<TcpStream>) {
+        let stream = TcpStream::connect(addr).unwrap();
+        let reader = BufReader::new(stream.try_clone().unwrap());
+        (stream, reader)
+    }
+
+    #[test]
+    fn test_relay_over_loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || run_relay(listener));
+
+        let (mut first, mut first_reader) = connect(addr);
+        let (mut second, mut second_reader) = connect(addr);
+        let next = |reader: &mut BufReader<TcpStream>| read_message(reader).unwrap().unwrap();
+        assert_eq!(next(&mut first_reader), NetMessage::Welcome { player: 0 });
+        assert_eq!(next(&mut second_reader), NetMessage::Welcome { player: 1 });
+
+        let start = NetMessage::Start {
+            seed: 7,
+            rules: Ruleset {
+                board_size: (10, 20),
+                attack_table: AttackTable::default(),
+            },
+        };
+        write_message(&mut second, &start).unwrap();
+        assert_eq!(next(&mut first_reader), start);
+        assert_eq!(next(&mut second_reader), start);
+
+        // a second request during the match is dropped, frames go through
+        write_message(&mut first, &start).unwrap();
+        let frame = NetMessage::Frame(Frame {
+            tick: 1,
+            actions: vec![GameAction::Left],
+            garbage: vec![2],
+        });
+        write_message(&mut first, &frame).unwrap();
+        assert_eq!(next(&mut second_reader), frame);
+
+        first.shutdown(Shutdown::Both).unwrap();
+        assert_eq!(next(&mut second_reader), NetMessage::Disconnected);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut game_data = GameData {
+            mode: GameMode::Versus,
+            ..Default::default()
+        };
+        game_data.start(11);
+        let mut versus = Versus::new(1, Entity::PLACEHOLDER, 11);
+        versus.meter.push_back(3);
+        for tick in 0..300 {
+            let actions = if tick % 20 == 0 {
+                vec![GameAction::Rotate, GameAction::Drop]
+            } else {
+                vec![]
+            };
+            game_data.tick_with(actions);
+        }
+        let snapshot = BoardSnapshot::capture(&game_data, &versus);
+
+        let mut copy = GameData {
+            mode: GameMode::Versus,
+            ..Default::default()
+        };
+        copy.start(11);
+        let mut copy_versus = Versus::new(1, Entity::PLACEHOLDER, 11);
+        assert_ne!(BoardSnapshot::capture(&copy, &copy_versus), snapshot);
+        snapshot.restore(&mut copy, &mut copy_versus);
+        assert_eq!(BoardSnapshot::capture(&copy, &copy_versus), snapshot);
+        assert_eq!(copy.tick, game_data.tick);
+    }
+
+    #[test]
+    fn test_catch_up() {
+        let mut game_data = GameData {
+            mode: GameMode::Versus,
+            ..Default::default()
+        };
+        game_data.start(5);
+        let mut versus = Versus::new(1, Entity::PLACEHOLDER, 5);
+        let mut player_versus = Versus::new(0, Entity::PLACEHOLDER, 5);
+        let mut unsent_garbage = vec![];
+        for tick in 1..=60 {
+            let frame = Frame {
+                tick,
+                actions: if tick == 2 {
+                    vec![GameAction::Drop]
+                } else {
+                    vec![]
+                },
+                garbage: if tick == 1 { vec![2] } else { vec![] },
+            };
+            catch_up(
+                frame,
+                &AttackTable::default(),
+                &mut unsent_garbage,
+                &mut game_data,
+                &mut versus,
+                &mut player_versus,
+            );
+        }
+        assert_eq!(game_data.tick, 60);
+        // the dropped brick cleared nothing, so the attack came up under it
+        assert!(versus.meter.is_empty());
+        assert_eq!(
+            game_data.board.garbage.iter().filter(|row| **row).count(),
+            2
+        );
+        assert!(unsent_garbage.is_empty());
+        assert!(player_versus.meter.is_empty());
+    }
+}
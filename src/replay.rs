@@ -0,0 +1,403 @@
+use std::{fs, io, path::PathBuf};
+
+use bevy::{
+    color::Color,
+    ecs::{
+        component::Component,
+        entity::Entity,
+        event::EventWriter,
+        query::With,
+        system::{Commands, Res, ResMut, Resource, Single},
+    },
+    input::{ButtonInput, keyboard::KeyCode},
+    log::{info, warn},
+    state::state::NextState,
+    text::{FontSmoothing, JustifyText, Text2d, TextColor, TextFont, TextLayout},
+    time::{Time, Virtual},
+    transform::components::Transform,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    GameAssets, TetrisEntity,
+    board::PlayerBoard,
+    constants::{REPLAY_MAX_SPEED, REPLAY_MIN_SPEED, REPLAY_SEEK_TICKS},
+    demo::Demo,
+    events::{BoardEvent, GameFinished, TopOut},
+    game_data::{Board, GameAction, GameData, InputBuffer},
+    mode::GameMode,
+    save::{SaveGame, is_board_size_valid},
+    schedule::send_game_end,
+    state::GameSate,
+};
+
+/// Bumped whenever the replay format or the game rules change, since old
+/// replays would no longer play out the same.
+pub const REPLAY_VERSION: u32 = 3;
+
+/// A game as its seed, mode and the inputs given before each tick.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub seed: u64,
+    pub mode: GameMode,
+    /// Columns and rows of the board.
+    pub board_size: (usize, usize),
+    /// The saved game a resumed game started from.
+    pub start: Option<SaveGame>,
+    pub actions: Vec<(u64, GameAction)>,
+}
+
+impl Replay {
+    pub fn new(seed: u64, mode: GameMode, board: &Board) -> Self {
+        Replay {
+            version: REPLAY_VERSION,
+            seed,
+            mode,
+            board_size: (board.cols, board.rows),
+            start: None,
+            actions: vec![],
+        }
+    }
+
+    pub fn dir() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("rs-tetris").join("replays"))
+    }
+
+    /// Sets up a reset `GameData` as the game was when recording started.
+    pub fn begin(&self, game_data: &mut GameData) {
+        match &self.start {
+            Some(save) => save.restore(game_data),
+            None => {
+                let (cols, rows) = self.board_size;
+                game_data.board = Board::new(cols, rows);
+                game_data.mode = self.mode;
+                game_data.start(self.seed);
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        let replay: Replay = serde_json::from_str(json)?;
+        if replay.version != REPLAY_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "replay version {} is not supported, expected {}",
+                    replay.version, REPLAY_VERSION
+                ),
+            ));
+        }
+        let (cols, rows) = replay.board_size;
+        if !is_board_size_valid(cols, rows) || !replay.mode.is_valid() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the replay's board size or mode is invalid",
+            ));
+        }
+        if let Some(start) = &replay.start {
+            start
+                .validate()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        }
+        Ok(replay)
+    }
+
+    pub fn save(&self) -> io::Result<PathBuf> {
+        let dir = Self::dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no user data directory"))?;
+        fs::create_dir_all(&dir)?;
+        let name = chrono::Local::now().format("%Y%m%d-%H%M%S%.3f").to_string();
+        let path = dir.join(format!("{}.json", name));
+        fs::write(&path, self.to_json()?)?;
+        Ok(path)
+    }
+
+    /// Loads the most recently saved replay, if any.
+    pub fn latest() -> io::Result<Option<Self>> {
+        let Some(dir) = Self::dir() else {
+            return Ok(None);
+        };
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let latest = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .max();
+        match latest {
+            Some(path) => Self::from_json(&fs::read_to_string(path)?).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Saves the replay of the game that just ended, skipping empty games.
+pub fn save_replay(game_data: &GameData) {
+    // the opponent's inputs and garbage aren't recorded, so versus can't be replayed
+    if game_data.replay.actions.is_empty() || game_data.mode.is_versus() {
+        return;
+    }
+    if let Err(err) = game_data.replay.save() {
+        warn!("Failed to save the replay: {}", err);
+    }
+}
+
+/// Feeds a loaded replay back through the engine instead of the controls.
+#[derive(Debug, Default, Resource)]
+pub struct ReplayPlayer {
+    pub replay: Option<Replay>,
+    pub cursor: usize,
+}
+
+impl ReplayPlayer {
+    pub fn is_active(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    /// Queues the recorded actions that were given before tick `tick`.
+    pub fn feed(&mut self, tick: u64, input_buffer: &mut InputBuffer) {
+        let Some(replay) = &self.replay else {
+            return;
+        };
+        while let Some((action_tick, action)) = replay.actions.get(self.cursor)
+            && *action_tick <= tick
+        {
+            input_buffer.push(*action);
+            self.cursor += 1;
+        }
+    }
+
+    /// Plays the replay from its start up to `tick`.
+    pub fn seek(&mut self, game_data: &mut GameData, tick: u64) {
+        let Some(replay) = &self.replay else {
+            return;
+        };
+        game_data.reset();
+        replay.begin(game_data);
+        self.cursor = 0;
+        let mut input_buffer = InputBuffer::default();
+        while game_data.tick < tick && game_data.ended.is_none() {
+            self.feed(game_data.tick, &mut input_buffer);
+            game_data.tick_with(input_buffer.0.drain(..));
+        }
+    }
+}
+
+pub fn is_live_game(replay_player: Res<ReplayPlayer>) -> bool {
+    !replay_player.is_active()
+}
+
+pub fn is_replaying(replay_player: Res<ReplayPlayer>) -> bool {
+    replay_player.is_active()
+}
+
+#[derive(Component)]
+pub struct ReplayText;
+
+pub fn spawn_replay_text(commands: &mut Commands, game_assets: &GameAssets) {
+    commands.spawn((
+        TetrisEntity,
+        Text2d::default(),
+        TextColor(Color::BLACK),
+        TextLayout::new_with_justify(JustifyText::Center),
+        TextFont {
+            font: game_assets.font.clone(),
+            font_size: 12.0,
+            font_smoothing: FontSmoothing::AntiAliased,
+        },
+        Transform::from_xyz(-40., 242., 300.),
+        ReplayText,
+    ));
+}
+
+/// Starts playing the latest replay from the Ready screen.
+pub fn control_start_replay(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut replay_player: ResMut<ReplayPlayer>,
+    mut next_state: ResMut<NextState<GameSate>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::KeyP) {
+        return;
+    }
+    match Replay::latest() {
+        Ok(Some(replay)) => {
+            replay_player.replay = Some(replay);
+            replay_player.cursor = 0;
+            next_state.set(GameSate::Playing);
+        }
+        Ok(None) => info!("No replay to play"),
+        Err(err) => warn!("Can't play the replay: {}", err),
+    }
+}
+
+/// Space pauses, up/down change the speed and left/right seek.
+pub fn control_replay_playback(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut time: ResMut<Time<Virtual>>,
+    mut replay_player: ResMut<ReplayPlayer>,
+    player_board: Single<(Entity, &mut GameData), With<PlayerBoard>>,
+    mut top_out: EventWriter<BoardEvent<TopOut>>,
+    mut game_finished: EventWriter<BoardEvent<GameFinished>>,
+) {
+    let (board, mut game_data) = player_board.into_inner();
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        if time.is_paused() {
+            time.unpause();
+        } else {
+            time.pause();
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowUp) {
+        let speed = (time.relative_speed() * 2.).min(REPLAY_MAX_SPEED);
+        time.set_relative_speed(speed);
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowDown) {
+        let speed = (time.relative_speed() / 2.).max(REPLAY_MIN_SPEED);
+        time.set_relative_speed(speed);
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowLeft) {
+        let tick = game_data.tick.saturating_sub(REPLAY_SEEK_TICKS);
+        replay_player.seek(&mut game_data, tick);
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowRight) {
+        let tick = game_data.tick + REPLAY_SEEK_TICKS;
+        replay_player.seek(&mut game_data, tick);
+        // seeking past the end skips the tick that would have reported it
+        send_game_end(board, game_data.ended, &mut top_out, &mut game_finished);
+    }
+}
+
+pub fn replay_text_system(
+    time: Res<Time<Virtual>>,
+    demo: Res<Demo>,
+    replay_text: Single<&mut Text2d, With<ReplayText>>,
+) {
+    let value = if demo.active {
+        "DEMO".to_string()
+    } else if time.is_paused() {
+        "REPLAY paused".to_string()
+    } else {
+        format!("REPLAY x{}", time.relative_speed())
+    };
+    let mut text = replay_text.into_inner();
+    if **text != value {
+        **text = value;
+    }
+}
+
+/// Leaves playback when back on the Ready screen.
+pub fn stop_replay(
+    mut commands: Commands,
+    mut time: ResMut<Time<Virtual>>,
+    mut replay_player: ResMut<ReplayPlayer>,
+    replay_text: Option<Single<Entity, With<ReplayText>>>,
+) {
+    replay_player.replay = None;
+    replay_player.cursor = 0;
+    time.set_relative_speed(1.);
+    time.unpause();
+    if let Some(replay_text) = replay_text {
+        commands.entity(replay_text.into_inner()).despawn();
+    }
+}
+
+pub fn save_replay_system(
+    replay_player: Res<ReplayPlayer>,
+    game_data: Single<&GameData, With<PlayerBoard>>,
+) {
+    if !replay_player.is_active() {
+        save_replay(&game_data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Replay, ReplayPlayer};
+
+    use crate::{
+        game_data::{Board, GameAction, GameData},
+        mode::GameMode,
+        save::SaveGame,
+    };
+
+    fn play(mode: GameMode, seed: u64) -> GameData {
+        let mut game_data = GameData {
+            mode,
+            ..Default::default()
+        };
+        game_data.start(seed);
+        let actions = [
+            GameAction::Left,
+            GameAction::Rotate,
+            GameAction::Drop,
+            GameAction::Right,
+            GameAction::Right,
+            GameAction::Drop,
+            GameAction::Down,
+        ];
+        for i in 0..3000 {
+            if game_data.ended.is_some() {
+                break;
+            }
+            let action = (i % 7 == 0).then(|| actions[(i / 7) % actions.len()]);
+            game_data.tick_with(action);
+        }
+        game_data
+    }
+
+    #[test]
+    fn test_replay_plays_back_the_same_game() {
+        for mode in [
+            GameMode::default(),
+            GameMode::DigSurvival,
+            GameMode::Ultra(120),
+        ] {
+            let game_data = play(mode, 1234);
+            let replay = Replay::from_json(&game_data.replay.to_json().unwrap()).unwrap();
+
+            let mut replay_player = ReplayPlayer {
+                replay: Some(replay),
+                cursor: 0,
+            };
+            let mut played = GameData::default();
+            replay_player.seek(&mut played, game_data.tick);
+            assert_eq!(played.tick, game_data.tick);
+            assert_eq!(played.board, game_data.board);
+            assert_eq!(played.score, game_data.score);
+            assert_eq!(played.falling_brick_node, game_data.falling_brick_node);
+            assert_eq!(played.rng, game_data.rng);
+        }
+    }
+
+    #[test]
+    fn test_replay_version() {
+        let mut replay = Replay::new(1, GameMode::Zen, &Board::default());
+        replay.version = 0;
+        assert!(Replay::from_json(&replay.to_json().unwrap()).is_err());
+        assert!(Replay::from_json("{").is_err());
+    }
+
+    #[test]
+    fn test_invalid_replay_is_refused() {
+        let mut game_data = GameData::default();
+        game_data.start(1);
+        let mut replay = Replay::new(1, GameMode::default(), &game_data.board);
+        assert!(Replay::from_json(&replay.to_json().unwrap()).is_ok());
+
+        replay.board_size = (64, 20);
+        assert!(Replay::from_json(&replay.to_json().unwrap()).is_err());
+
+        replay.board_size = (game_data.board.cols, game_data.board.rows);
+        let mut start = SaveGame::from_game_data(&game_data);
+        start.level = u32::MAX;
+        replay.start = Some(start);
+        assert!(Replay::from_json(&replay.to_json().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,53 @@
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+/// SplitMix64 generator. The whole state is one `u64`, so a game can be
+/// reproduced from its seed and the state can be copied or stored as is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameRng {
+    pub state: u64,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+}
+
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn fill_bytes(&mut self, dst: &mut [u8]) {
+        for chunk in dst.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, RngCore};
+
+    use super::GameRng;
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut a = GameRng::new(42);
+        let mut b = GameRng::new(42);
+        let xs: Vec<u32> = (0..16).map(|_| a.random_range(0..7)).collect();
+        let ys: Vec<u32> = (0..16).map(|_| b.random_range(0..7)).collect();
+        assert_eq!(xs, ys);
+        assert_ne!(GameRng::new(1).next_u64(), GameRng::new(2).next_u64());
+    }
+}
@@ -0,0 +1,447 @@
+use std::{fmt, fs, io, path::PathBuf, time::Duration};
+
+use bevy::{
+    app::AppExit,
+    color::Color,
+    ecs::{
+        component::Component,
+        entity::Entity,
+        event::EventReader,
+        query::With,
+        system::{Commands, Res, ResMut, Resource, Single},
+    },
+    log::warn,
+    state::state::State,
+    text::{FontSmoothing, JustifyText, Text2d, TextColor, TextFont, TextLayout},
+    time::{Timer, TimerMode},
+    transform::components::Transform,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    GameAssets, TetrisEntity,
+    board::PlayerBoard,
+    brick::{Brick, BrickNode, BrickShape},
+    constants::MAX_LEVEL,
+    finesse::Finesse,
+    game_data::{Board, EraseAnimationStep, GameData, MAX_BOARD_COLS},
+    mode::GameMode,
+    replay::{Replay, ReplayPlayer},
+    rng::GameRng,
+    state::GameSate,
+};
+
+/// Bumped whenever the layout of `SaveGame` changes, older files are refused.
+pub const SAVE_VERSION: u32 = 2;
+
+const SAVE_FILE: &str = "save.json";
+
+/// Rows above the board the falling brick may be in. It spawns 3 above the
+/// top and rotation kicks can lift it a little further.
+const SPAWN_HEADROOM: usize = 8;
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    Corrupt(serde_json::Error),
+    Version(u32),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(err) => write!(f, "can't access the saved game: {}", err),
+            SaveError::Corrupt(err) => write!(f, "the saved game is corrupt: {}", err),
+            SaveError::Version(version) => write!(
+                f,
+                "the saved game has version {}, expected {}",
+                version, SAVE_VERSION
+            ),
+            SaveError::Invalid(reason) => write!(f, "the saved game is invalid: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        SaveError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for SaveError {
+    fn from(err: serde_json::Error) -> Self {
+        SaveError::Corrupt(err)
+    }
+}
+
+#[derive(Deserialize)]
+struct SaveHeader {
+    version: u32,
+}
+
+/// Everything needed to continue a game where it was left.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SaveGame {
+    pub version: u32,
+    pub mode: GameMode,
+    pub seed: u64,
+    pub rng: GameRng,
+    pub board: Board,
+    pub falling_brick_shape: BrickShape,
+    pub falling_brick_node: BrickNode,
+    pub next_brick_shape: BrickShape,
+    pub score: u32,
+    pub level: u32,
+    pub cleans: u32,
+    pub falling_timer_duration: Duration,
+    pub falling_timer_elapsed: Duration,
+    pub garbage_timer_elapsed: Duration,
+    pub play_duration: Duration,
+    #[serde(default)]
+    pub hints_used: bool,
+    #[serde(default)]
+    pub finesse: Finesse,
+}
+
+impl SaveGame {
+    pub fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("rs-tetris").join(SAVE_FILE))
+    }
+
+    /// Whether the game is between pieces, the only point it can be saved.
+    pub fn can_save(game_data: &GameData) -> bool {
+        // a versus match needs both boards, only the first player's is saved
+        game_data.ended.is_none()
+            && game_data.erase_animation_step == EraseAnimationStep::NotStart
+            && !game_data.mode.is_versus()
+    }
+
+    pub fn from_game_data(game_data: &GameData) -> Self {
+        SaveGame {
+            version: SAVE_VERSION,
+            mode: game_data.mode,
+            seed: game_data.seed,
+            rng: game_data.rng,
+            board: game_data.board.clone(),
+            falling_brick_shape: game_data.falling_brick_shape,
+            falling_brick_node: game_data.falling_brick_node,
+            next_brick_shape: game_data.next_brick_shape,
+            score: game_data.score,
+            level: game_data.level,
+            cleans: game_data.cleans,
+            falling_timer_duration: game_data.falling_timer.duration(),
+            falling_timer_elapsed: game_data.falling_timer.elapsed(),
+            garbage_timer_elapsed: game_data.garbage_timer.elapsed(),
+            play_duration: game_data.play_duration,
+            hints_used: game_data.hints_used,
+            finesse: game_data.finesse,
+        }
+    }
+
+    /// Puts the saved game into a freshly reset `GameData`.
+    pub fn restore(&self, game_data: &mut GameData) {
+        game_data.mode = self.mode;
+        game_data.seed = self.seed;
+        game_data.rng = self.rng;
+        game_data.board = self.board.clone();
+        game_data.falling_brick_shape = self.falling_brick_shape;
+        game_data.falling_brick_node = self.falling_brick_node;
+        game_data.previous_falling_brick_node = self.falling_brick_node;
+        game_data.next_brick_shape = self.next_brick_shape;
+        game_data.score = self.score;
+        game_data.level = self.level;
+        game_data.cleans = self.cleans;
+        game_data.falling_timer = Timer::new(self.falling_timer_duration, TimerMode::Repeating);
+        game_data
+            .falling_timer
+            .set_elapsed(self.falling_timer_elapsed);
+        if let Some(interval) = self.mode.garbage_interval() {
+            game_data.garbage_timer = Timer::new(interval, TimerMode::Repeating);
+            game_data
+                .garbage_timer
+                .set_elapsed(self.garbage_timer_elapsed);
+        }
+        game_data.play_duration = self.play_duration;
+        game_data.hints_used = self.hints_used;
+        game_data.finesse = self.finesse;
+        game_data.tick = 0;
+        game_data.replay = Replay {
+            start: Some(self.clone()),
+            ..Replay::new(self.seed, self.mode, &self.board)
+        };
+        if self.mode.has_undo() {
+            game_data.push_history();
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, SaveError> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Parses a saved game, checking the version before the rest of the data.
+    pub fn from_json(json: &str) -> Result<Self, SaveError> {
+        let header: SaveHeader = serde_json::from_str(json)?;
+        if header.version != SAVE_VERSION {
+            return Err(SaveError::Version(header.version));
+        }
+        let save: SaveGame = serde_json::from_str(json)?;
+        save.validate()?;
+        Ok(save)
+    }
+
+    /// Checks everything the game indexes or does arithmetic with, so a
+    /// corrupt file is refused instead of panicking once restored.
+    pub fn validate(&self) -> Result<(), SaveError> {
+        let board = &self.board;
+        if !is_board_size_valid(board.cols, board.rows) || board.garbage.len() != board.rows {
+            return Err(SaveError::Invalid("wrong board size"));
+        }
+        if !self.falling_brick_shape.is_valid() || !self.next_brick_shape.is_valid() {
+            return Err(SaveError::Invalid("unknown brick shape"));
+        }
+        if !self.is_falling_brick_on_board() {
+            return Err(SaveError::Invalid("falling brick off the board"));
+        }
+        if !self.mode.is_valid() {
+            return Err(SaveError::Invalid("unknown mode setting"));
+        }
+        if self.level > MAX_LEVEL {
+            return Err(SaveError::Invalid("level out of range"));
+        }
+        if self.falling_timer_duration.is_zero() {
+            return Err(SaveError::Invalid("zero falling speed"));
+        }
+        Ok(())
+    }
+
+    fn is_falling_brick_on_board(&self) -> bool {
+        let brick: Brick = self.falling_brick_shape.into();
+        let BrickNode(x, y) = self.falling_brick_node;
+        let cols = self.board.cols as i32;
+        let rows = (self.board.rows + SPAWN_HEADROOM) as i32;
+        brick.nodes.iter().all(|node| {
+            (0..cols).contains(&(x as i32 + node.0 as i32))
+                && (0..rows).contains(&(y as i32 - node.1 as i32))
+        })
+    }
+
+    pub fn write(&self) -> Result<(), SaveError> {
+        let path = Self::path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no user data directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    /// Reads the saved game, `Ok(None)` when there is none.
+    pub fn read() -> Result<Option<Self>, SaveError> {
+        let Some(path) = Self::path() else {
+            return Ok(None);
+        };
+        match fs::read_to_string(path) {
+            Ok(json) => Self::from_json(&json).map(Some),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn remove() {
+        if let Some(path) = Self::path()
+            && let Err(err) = fs::remove_file(path)
+            && err.kind() != io::ErrorKind::NotFound
+        {
+            warn!("Failed to remove the saved game: {}", err);
+        }
+    }
+}
+
+/// Whether a saved board of this size can be played, with room above it for
+/// the falling brick in `BrickNode`'s coordinates.
+pub fn is_board_size_valid(cols: usize, rows: usize) -> bool {
+    (4..=MAX_BOARD_COLS).contains(&cols) && rows >= 4 && rows + SPAWN_HEADROOM <= i8::MAX as usize
+}
+
+/// Saves the game if it is at a point where it can be resumed.
+pub fn autosave(game_data: &GameData) {
+    if !SaveGame::can_save(game_data) {
+        return;
+    }
+    if let Err(err) = SaveGame::from_game_data(game_data).write() {
+        warn!("Failed to save the game: {}", err);
+    }
+}
+
+/// The saved game offered on the Ready screen.
+#[derive(Debug, Default, Resource)]
+pub struct SaveSlot {
+    pub save: Option<SaveGame>,
+    pub error: Option<String>,
+    pub resume: bool,
+}
+
+#[derive(Component)]
+pub struct ResumeText;
+
+pub fn load_save_slot(mut save_slot: ResMut<SaveSlot>) {
+    *save_slot = match SaveGame::read() {
+        Ok(save) => SaveSlot {
+            save,
+            ..Default::default()
+        },
+        Err(err) => {
+            warn!("Can't resume: {}", err);
+            SaveSlot {
+                error: Some(err.to_string()),
+                ..Default::default()
+            }
+        }
+    };
+}
+
+pub fn spawn_resume_text(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    save_slot: Res<SaveSlot>,
+) {
+    let text = if let Some(save) = &save_slot.save {
+        format!("Down: resume {}", save.mode.name())
+    } else if save_slot.error.is_some() {
+        "Saved game unreadable".into()
+    } else {
+        String::new()
+    };
+    commands.spawn((
+        TetrisEntity,
+        Text2d::new(text),
+        TextColor(Color::BLACK),
+        TextLayout::new_with_justify(JustifyText::Center),
+        TextFont {
+            font: game_assets.font.clone(),
+            font_size: 12.0,
+            font_smoothing: FontSmoothing::AntiAliased,
+        },
+        Transform::from_xyz(-40., -4., 300.),
+        ResumeText,
+    ));
+}
+
+pub fn despawn_resume_text(mut commands: Commands, resume_text: Single<Entity, With<ResumeText>>) {
+    commands.entity(resume_text.into_inner()).despawn();
+}
+
+/// Saves the running game when the app is closed.
+pub fn autosave_on_exit_system(
+    mut app_exit_events: EventReader<AppExit>,
+    state: Res<State<GameSate>>,
+    replay_player: Res<ReplayPlayer>,
+    game_data: Single<&GameData, With<PlayerBoard>>,
+) {
+    if app_exit_events.read().next().is_some()
+        && *state.get() == GameSate::Playing
+        && !replay_player.is_active()
+    {
+        autosave(&game_data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SAVE_VERSION, SaveError, SaveGame};
+
+    use crate::{
+        brick::{Brick, BrickNode},
+        game_data::GameData,
+        mode::GameMode,
+    };
+
+    fn game_data() -> GameData {
+        let mut game_data = GameData {
+            mode: GameMode::DigSurvival,
+            ..Default::default()
+        };
+        game_data.start(42);
+        game_data.board.update_occupied_by_brick(&Brick {
+            nodes: [
+                BrickNode(0, 10),
+                BrickNode(1, 10),
+                BrickNode(2, 10),
+                BrickNode(3, 10),
+            ],
+        });
+        game_data.score = 1200;
+        game_data.cleans = 4;
+        game_data.falling_brick_node = BrickNode(3, 12);
+        game_data
+    }
+
+    #[test]
+    fn test_save_round_trip() {
+        let game_data = game_data();
+        let json = SaveGame::from_game_data(&game_data).to_json().unwrap();
+
+        let mut restored = GameData::default();
+        SaveGame::from_json(&json).unwrap().restore(&mut restored);
+        assert_eq!(restored.mode, game_data.mode);
+        assert_eq!(restored.rng, game_data.rng);
+        assert_eq!(restored.board, game_data.board);
+        assert_eq!(restored.board.garbage, game_data.board.garbage);
+        assert_eq!(restored.falling_brick_shape, game_data.falling_brick_shape);
+        assert_eq!(restored.falling_brick_node, game_data.falling_brick_node);
+        assert_eq!(restored.next_brick_shape, game_data.next_brick_shape);
+        assert_eq!(restored.score, 1200);
+        assert_eq!(restored.cleans, 4);
+        assert_eq!(
+            restored.garbage_timer.duration(),
+            game_data.garbage_timer.duration()
+        );
+    }
+
+    #[test]
+    fn test_save_errors() {
+        let mut save = SaveGame::from_game_data(&game_data());
+        assert!(matches!(
+            SaveGame::from_json("{\"version\":"),
+            Err(SaveError::Corrupt(_))
+        ));
+        assert!(matches!(
+            SaveGame::from_json("{\"version\":0}"),
+            Err(SaveError::Version(0))
+        ));
+        assert!(matches!(
+            SaveGame::from_json(&format!("{{\"version\":{}}}", SAVE_VERSION)),
+            Err(SaveError::Corrupt(_))
+        ));
+
+        let is_invalid = |save: &SaveGame| {
+            matches!(
+                SaveGame::from_json(&save.to_json().unwrap()),
+                Err(SaveError::Invalid(_))
+            )
+        };
+        assert!(!is_invalid(&save));
+        let mut json = serde_json::to_value(&save).unwrap();
+        json["board"]["occupied"].as_array_mut().unwrap().pop();
+        assert!(matches!(
+            SaveGame::from_json(&json.to_string()),
+            Err(SaveError::Corrupt(_))
+        ));
+        let mut corrupt = save.clone();
+        corrupt.board.garbage.pop();
+        assert!(is_invalid(&corrupt));
+        let mut corrupt = save.clone();
+        corrupt.falling_brick_node = BrickNode(127, 12);
+        assert!(is_invalid(&corrupt));
+        corrupt.falling_brick_node = BrickNode(3, -128);
+        assert!(is_invalid(&corrupt));
+        let mut corrupt = save.clone();
+        corrupt.level = u32::MAX;
+        assert!(is_invalid(&corrupt));
+        save.mode = GameMode::BType(99);
+        assert!(is_invalid(&save));
+    }
+}
@@ -0,0 +1,326 @@
+use bevy::{
+    ecs::{
+        entity::Entity,
+        event::{EventReader, EventWriter},
+        query::{Has, With},
+        schedule::SystemSet,
+        system::{Query, ResMut, Single},
+    },
+    state::state::NextState,
+};
+
+use crate::{
+    board::PlayerBoard,
+    events::{
+        BoardEvent, DropStarted, GameFinished, LevelUp, LinesCleared, PieceLocked, PieceMoved,
+        PieceRotated, PieceSpawned, TopOut,
+    },
+    game_data::{ActionOutcome, Fall, GameData, GameEnd, InputBuffer},
+    replay::ReplayPlayer,
+    state::GameSate,
+    versus::Versus,
+};
+
+/// The phases of a game in the order they run. `Input` and `Presentation`
+/// run every frame, the rest once per fixed tick for every board.
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameSet {
+    Input,
+    Movement,
+    GravityLock,
+    LineClear,
+    Scoring,
+    Presentation,
+}
+
+/// The last event of this tick that happened on `board`.
+fn last_on<E: Copy + Send + Sync + 'static>(events: &[BoardEvent<E>], board: Entity) -> Option<E> {
+    events
+        .iter()
+        .rev()
+        .find(|event| event.board == board)
+        .map(|event| event.event)
+}
+
+/// Queues the replay's inputs for this tick on the player's board and
+/// remembers where each falling brick starts it.
+pub fn feed_inputs_system(
+    mut boards: Query<(&mut GameData, &mut InputBuffer, Has<PlayerBoard>)>,
+    mut replay_player: ResMut<ReplayPlayer>,
+) {
+    for (mut game_data, mut input_buffer, is_player) in &mut boards {
+        game_data.previous_falling_brick_node = game_data.falling_brick_node;
+        if is_player {
+            replay_player.feed(game_data.tick, &mut input_buffer);
+        }
+    }
+}
+
+pub fn movement_system(
+    mut boards: Query<(Entity, &mut GameData, &mut InputBuffer)>,
+    mut piece_moved: EventWriter<BoardEvent<PieceMoved>>,
+    mut piece_rotated: EventWriter<BoardEvent<PieceRotated>>,
+    mut drop_started: EventWriter<BoardEvent<DropStarted>>,
+    mut piece_spawned: EventWriter<BoardEvent<PieceSpawned>>,
+) {
+    for (board, mut game_data, mut input_buffer) in &mut boards {
+        for action in input_buffer.0.drain(..) {
+            match game_data.apply_action(action) {
+                Some(ActionOutcome::Moved(event)) => {
+                    piece_moved.send(BoardEvent { board, event });
+                }
+                Some(ActionOutcome::Rotated(event)) => {
+                    piece_rotated.send(BoardEvent { board, event });
+                }
+                Some(ActionOutcome::DropStarted(event)) => {
+                    drop_started.send(BoardEvent { board, event });
+                }
+                Some(ActionOutcome::Spawned(event)) => {
+                    piece_spawned.send(BoardEvent { board, event });
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+pub fn gravity_lock_system(
+    mut boards: Query<(Entity, &mut GameData)>,
+    mut drop_started: EventReader<BoardEvent<DropStarted>>,
+    mut piece_moved: EventWriter<BoardEvent<PieceMoved>>,
+    mut piece_locked: EventWriter<BoardEvent<PieceLocked>>,
+    mut piece_spawned: EventWriter<BoardEvent<PieceSpawned>>,
+    mut top_out: EventWriter<BoardEvent<TopOut>>,
+) {
+    let drop_started: Vec<_> = drop_started.read().copied().collect();
+    for (board, mut game_data) in &mut boards {
+        if last_on(&drop_started, board).is_some() {
+            game_data.start_drop();
+        }
+        if !game_data.advance_tick() {
+            continue;
+        }
+        match game_data.update_gravity() {
+            Some(Fall::Moved(event)) => {
+                piece_moved.send(BoardEvent { board, event });
+            }
+            Some(Fall::Locked(locked, spawned)) => {
+                piece_locked.send(BoardEvent {
+                    board,
+                    event: locked,
+                });
+                piece_spawned.send(BoardEvent {
+                    board,
+                    event: spawned,
+                });
+            }
+            Some(Fall::TopOut) => {
+                top_out.send(BoardEvent {
+                    board,
+                    event: TopOut,
+                });
+            }
+            None => {}
+        }
+    }
+}
+
+pub fn line_clear_system(
+    mut boards: Query<(Entity, &mut GameData)>,
+    mut piece_locked: EventReader<BoardEvent<PieceLocked>>,
+    mut lines_cleared: EventWriter<BoardEvent<LinesCleared>>,
+) {
+    let piece_locked: Vec<_> = piece_locked.read().copied().collect();
+    for (board, mut game_data) in &mut boards {
+        if !game_data.is_running() {
+            continue;
+        }
+        if let Some(event) = game_data.update_line_clear(last_on(&piece_locked, board)) {
+            lines_cleared.send(BoardEvent { board, event });
+        }
+    }
+}
+
+pub fn scoring_system(
+    mut boards: Query<(Entity, &mut GameData)>,
+    mut piece_locked: EventReader<BoardEvent<PieceLocked>>,
+    mut lines_cleared: EventReader<BoardEvent<LinesCleared>>,
+    mut level_up: EventWriter<BoardEvent<LevelUp>>,
+    mut top_out: EventWriter<BoardEvent<TopOut>>,
+    mut game_finished: EventWriter<BoardEvent<GameFinished>>,
+) {
+    let piece_locked: Vec<_> = piece_locked.read().copied().collect();
+    let lines_cleared: Vec<_> = lines_cleared.read().copied().collect();
+    for (board, mut game_data) in &mut boards {
+        if !game_data.is_running() {
+            continue;
+        }
+        let (level, end) = game_data.update_scoring(
+            last_on(&piece_locked, board),
+            last_on(&lines_cleared, board),
+        );
+        if let Some(event) = level {
+            level_up.send(BoardEvent { board, event });
+        }
+        send_game_end(board, end, &mut top_out, &mut game_finished);
+    }
+}
+
+pub fn send_game_end(
+    board: Entity,
+    end: Option<GameEnd>,
+    top_out: &mut EventWriter<BoardEvent<TopOut>>,
+    game_finished: &mut EventWriter<BoardEvent<GameFinished>>,
+) {
+    match end {
+        Some(GameEnd::TopOut) => {
+            top_out.send(BoardEvent {
+                board,
+                event: TopOut,
+            });
+        }
+        Some(GameEnd::Finished) => {
+            game_finished.send(BoardEvent {
+                board,
+                event: GameFinished,
+            });
+        }
+        None => {}
+    }
+}
+
+/// Leaves the board for the game over or results screen once the player's
+/// game ends.
+pub fn game_end_system(
+    player_board: Single<(Entity, Has<Versus>), With<PlayerBoard>>,
+    mut top_out: EventReader<BoardEvent<TopOut>>,
+    mut game_finished: EventReader<BoardEvent<GameFinished>>,
+    mut next_state: ResMut<NextState<GameSate>>,
+) {
+    let (player_board, is_versus) = *player_board;
+    // a versus match goes on until one player is left, see `versus_end_system`
+    if is_versus {
+        return;
+    }
+    if top_out.read().any(|event| event.board == player_board) {
+        next_state.set(GameSate::GameOver);
+    } else if game_finished
+        .read()
+        .any(|event| event.board == player_board)
+    {
+        next_state.set(GameSate::Results);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        app::{App, FixedUpdate},
+        ecs::schedule::{IntoSystemConfigs, IntoSystemSetConfigs},
+    };
+
+    use super::{
+        GameSet, feed_inputs_system, gravity_lock_system, line_clear_system, movement_system,
+        scoring_system,
+    };
+
+    use crate::{
+        events::{
+            BoardEvent, DropStarted, GameFinished, LevelUp, LinesCleared, PieceLocked, PieceMoved,
+            PieceRotated, PieceSpawned, TopOut,
+        },
+        game_data::{GameAction, GameData, InputBuffer},
+        mode::GameMode,
+        replay::ReplayPlayer,
+    };
+
+    fn actions(tick: u64) -> Vec<GameAction> {
+        match tick % 40 {
+            0 => vec![GameAction::Rotate, GameAction::Right],
+            10 => vec![GameAction::Left, GameAction::Left, GameAction::Left],
+            20 => vec![GameAction::Drop],
+            _ => vec![],
+        }
+    }
+
+    fn new_game(mode: GameMode, seed: u64) -> GameData {
+        let mut game_data = GameData {
+            mode,
+            ..Default::default()
+        };
+        game_data.start(seed);
+        game_data
+    }
+
+    #[test]
+    fn test_system_sets_match_step_per_board() {
+        let mut games = [
+            new_game(GameMode::DigSurvival, 5),
+            new_game(GameMode::default(), 9),
+        ];
+
+        let mut app = App::new();
+        app.add_event::<BoardEvent<PieceSpawned>>()
+            .add_event::<BoardEvent<PieceMoved>>()
+            .add_event::<BoardEvent<PieceRotated>>()
+            .add_event::<BoardEvent<DropStarted>>()
+            .add_event::<BoardEvent<PieceLocked>>()
+            .add_event::<BoardEvent<LevelUp>>()
+            .add_event::<BoardEvent<LinesCleared>>()
+            .add_event::<BoardEvent<TopOut>>()
+            .add_event::<BoardEvent<GameFinished>>()
+            .init_resource::<ReplayPlayer>()
+            .configure_sets(
+                FixedUpdate,
+                (
+                    GameSet::Input,
+                    GameSet::Movement,
+                    GameSet::GravityLock,
+                    GameSet::LineClear,
+                    GameSet::Scoring,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                FixedUpdate,
+                (
+                    feed_inputs_system.in_set(GameSet::Input),
+                    movement_system.in_set(GameSet::Movement),
+                    gravity_lock_system.in_set(GameSet::GravityLock),
+                    line_clear_system.in_set(GameSet::LineClear),
+                    scoring_system.in_set(GameSet::Scoring),
+                ),
+            );
+        let boards = [
+            app.world_mut()
+                .spawn((new_game(GameMode::DigSurvival, 5), InputBuffer::default()))
+                .id(),
+            app.world_mut()
+                .spawn((new_game(GameMode::default(), 9), InputBuffer::default()))
+                .id(),
+        ];
+
+        for tick in 0..4000 {
+            // the second board gets its inputs a few ticks later
+            let inputs = [actions(tick), actions(tick + 7)];
+            for (board, inputs) in boards.iter().zip(inputs.clone()) {
+                app.world_mut().get_mut::<InputBuffer>(*board).unwrap().0 = inputs;
+            }
+            app.world_mut().run_schedule(FixedUpdate);
+            for (game_data, inputs) in games.iter_mut().zip(inputs) {
+                game_data.tick_with(inputs);
+            }
+        }
+        for (board, game_data) in boards.iter().zip(&games) {
+            let app_game_data = app.world().get::<GameData>(*board).unwrap();
+            assert!(game_data.cleans > 0 || game_data.ended.is_some());
+            assert_eq!(app_game_data.tick, game_data.tick);
+            assert_eq!(app_game_data.ended, game_data.ended);
+            assert_eq!(app_game_data.board, game_data.board);
+            assert_eq!(app_game_data.score, game_data.score);
+            assert_eq!(app_game_data.cleans, game_data.cleans);
+            assert_eq!(app_game_data.rng, game_data.rng);
+        }
+        assert_ne!(games[0].board, games[1].board);
+    }
+}
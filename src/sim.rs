@@ -0,0 +1,429 @@
+use std::{
+    collections::BTreeMap,
+    fmt, io, thread,
+    time::{Duration, Instant},
+};
+
+use rand::seq::SliceRandom;
+
+use crate::{
+    ai::{AiLevel, AiPlayer, AiWeights},
+    brick::BrickShape,
+    constants::BRICKS_DICT,
+    game_data::{Fall, GameData, GameEnd},
+    mode::GameMode,
+    rng::GameRng,
+    tbp::{Piece, TbpBot},
+};
+
+/// Who plays the simulated games.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimBot {
+    Ai(AiLevel),
+    /// A Tetris Bot Protocol engine, started once per game. The game runs
+    /// much faster than real time, so ticks wait while it thinks.
+    Tbp(String),
+}
+
+/// How the simulated games deal bricks. The game itself always uses
+/// `Uniform`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Randomizer {
+    /// Every brick drawn independently, as in the game.
+    #[default]
+    Uniform,
+    /// Each run of seven is a shuffled set of all seven bricks.
+    Bag,
+}
+
+/// Deals the bricks of one game for `Randomizer::Bag`.
+struct BagDealer {
+    rng: GameRng,
+    bag: Vec<BrickShape>,
+}
+
+impl BagDealer {
+    fn new(seed: u64) -> Self {
+        BagDealer {
+            rng: GameRng::new(seed.rotate_left(29)),
+            bag: vec![],
+        }
+    }
+
+    fn next(&mut self) -> BrickShape {
+        if self.bag.is_empty() {
+            self.bag = (0..BRICKS_DICT.len()).map(|i| BrickShape(i, 0)).collect();
+            self.bag.shuffle(&mut self.rng);
+        }
+        self.bag.pop().unwrap()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimConfig {
+    pub bot: SimBot,
+    pub mode: GameMode,
+    pub randomizer: Randomizer,
+    pub games: u32,
+    /// Seed of the first game, the others follow on from it.
+    pub seed: u64,
+    /// Pieces after which a game is stopped.
+    pub max_pieces: u32,
+    pub threads: usize,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            bot: SimBot::Ai(AiLevel::Hard),
+            mode: GameMode::default(),
+            randomizer: Randomizer::default(),
+            games: 100,
+            seed: 0,
+            max_pieces: 1000,
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+        }
+    }
+}
+
+/// Why a simulated game stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SimEnd {
+    /// The stack reached the top while this brick was falling, by placing it
+    /// or by rising garbage.
+    TopOut(Piece),
+    Finished,
+    PieceLimit,
+    /// The bot stopped placing bricks.
+    Stalled,
+}
+
+impl fmt::Display for SimEnd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimEnd::TopOut(piece) => write!(f, "top out on {:?}", piece),
+            SimEnd::Finished => write!(f, "finished"),
+            SimEnd::PieceLimit => write!(f, "piece limit"),
+            SimEnd::Stalled => write!(f, "stalled"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameStats {
+    pub seed: u64,
+    pub lines: u32,
+    pub score: u32,
+    pub pieces: u32,
+    /// Every brick dealt, in order.
+    pub shapes: Vec<BrickShape>,
+    pub end: SimEnd,
+}
+
+/// Ticks a bot may go without locking a brick before the game is given up.
+const STALL_TICKS: u32 = 3600;
+
+/// How long a TBP bot may think about one brick before the game is given up.
+const BOT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Plays one game with the real rules, tick by tick.
+pub fn play_game(config: &SimConfig, seed: u64, weights: &AiWeights) -> io::Result<GameStats> {
+    let mut game_data = GameData {
+        mode: config.mode,
+        ..Default::default()
+    };
+    game_data.start(seed);
+    play_started(config, game_data, weights)
+}
+
+/// Plays a game that was already started, until it ends or a limit of
+/// `config` is reached.
+fn play_started(
+    config: &SimConfig,
+    mut game_data: GameData,
+    weights: &AiWeights,
+) -> io::Result<GameStats> {
+    let seed = game_data.seed;
+    let mut dealer = (config.randomizer == Randomizer::Bag).then(|| BagDealer::new(seed));
+    if let Some(dealer) = &mut dealer {
+        game_data.falling_brick_shape = dealer.next();
+        game_data.next_brick_shape = dealer.next();
+    }
+    let mut ai_player = AiPlayer::new(AiLevel::Hard, seed);
+    let mut tbp_bot = match &config.bot {
+        SimBot::Ai(level) => {
+            ai_player.level = *level;
+            None
+        }
+        SimBot::Tbp(command) => Some(TbpBot::spawn(command)?),
+    };
+
+    let mut shapes = vec![game_data.falling_brick_shape];
+    let mut pieces = 0;
+    let mut idle = 0;
+    let mut thinking_since = None;
+    let end = loop {
+        let action = match &mut tbp_bot {
+            Some(bot) => {
+                let action = bot.act(&game_data);
+                if bot.is_thinking() {
+                    let since = *thinking_since.get_or_insert_with(Instant::now);
+                    if since.elapsed() >= BOT_TIMEOUT {
+                        break SimEnd::Stalled;
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                    continue;
+                }
+                thinking_since = None;
+                action
+            }
+            None => ai_player.act(&game_data, weights),
+        };
+        let shape = game_data.falling_brick_shape;
+        let outcome = game_data.tick_with(action);
+        idle += 1;
+        if let Some(Fall::Locked(..)) = outcome.fall {
+            pieces += 1;
+            idle = 0;
+            if let Some(dealer) = &mut dealer {
+                game_data.next_brick_shape = dealer.next();
+            }
+            shapes.push(game_data.falling_brick_shape);
+        }
+        match game_data.ended {
+            Some(GameEnd::TopOut) => break SimEnd::TopOut(Piece::of(shape)),
+            Some(GameEnd::Finished) => break SimEnd::Finished,
+            None => {}
+        }
+        if pieces >= config.max_pieces {
+            break SimEnd::PieceLimit;
+        }
+        if idle >= STALL_TICKS {
+            break SimEnd::Stalled;
+        }
+    };
+    Ok(GameStats {
+        seed,
+        lines: game_data.cleans,
+        score: game_data.score,
+        pieces,
+        shapes,
+        end,
+    })
+}
+
+/// Plays `config.games` games spread over `config.threads`.
+pub fn simulate(config: &SimConfig, weights: &AiWeights) -> io::Result<Vec<GameStats>> {
+    let seeds: Vec<u64> = (0..config.games as u64)
+        .map(|i| config.seed.wrapping_add(i))
+        .collect();
+    let chunk = seeds.len().div_ceil(config.threads.max(1)).max(1);
+    thread::scope(|scope| {
+        let handles: Vec<_> = seeds
+            .chunks(chunk)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|seed| play_game(config, *seed, weights))
+                        .collect::<io::Result<Vec<_>>>()
+                })
+            })
+            .collect();
+        let mut games = vec![];
+        for handle in handles {
+            games.extend(handle.join().unwrap()?);
+        }
+        Ok(games)
+    })
+}
+
+/// The longest and mean number of bricks dealt between two of the same kind,
+/// or before the first one. `None` for kinds never dealt.
+pub fn droughts(shapes: &[BrickShape]) -> Vec<(Piece, Option<(u32, f64)>)> {
+    (0..BRICKS_DICT.len())
+        .map(|kind| {
+            let mut gaps = vec![];
+            let mut gap = 0;
+            for shape in shapes {
+                if shape.0 == kind {
+                    gaps.push(gap);
+                    gap = 0;
+                } else {
+                    gap += 1;
+                }
+            }
+            let stats = gaps.iter().max().map(|longest| {
+                let mean = gaps.iter().sum::<u32>() as f64 / gaps.len() as f64;
+                (*longest, mean)
+            });
+            (Piece::of(BrickShape(kind, 0)), stats)
+        })
+        .collect()
+}
+
+/// Aggregate statistics of a batch of games.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub games: usize,
+    pub mean_lines: f64,
+    /// Min, quartiles and max.
+    pub score: [u32; 5],
+    pub mean_score: f64,
+    pub pieces: u64,
+    pub pieces_per_second: f64,
+    /// Longest drought of each kind over all games, and the mean gap.
+    pub droughts: Vec<(Piece, Option<(u32, f64)>)>,
+    pub ends: BTreeMap<SimEnd, usize>,
+}
+
+impl Summary {
+    pub fn of(games: &[GameStats], elapsed: Duration) -> Self {
+        let count = games.len().max(1);
+        let mut scores: Vec<u32> = games.iter().map(|game| game.score).collect();
+        scores.sort();
+        let quantile = |q: f64| {
+            scores
+                .get(((scores.len().max(1) - 1) as f64 * q).round() as usize)
+                .copied()
+                .unwrap_or(0)
+        };
+        let pieces = games.iter().map(|game| game.pieces as u64).sum();
+
+        let per_game: Vec<_> = games.iter().map(|game| droughts(&game.shapes)).collect();
+        let droughts = (0..BRICKS_DICT.len())
+            .map(|kind| {
+                let stats: Vec<(u32, f64)> =
+                    per_game.iter().filter_map(|game| game[kind].1).collect();
+                let longest = stats.iter().map(|(longest, _)| *longest).max();
+                let mean =
+                    stats.iter().map(|(_, mean)| mean).sum::<f64>() / stats.len().max(1) as f64;
+                (
+                    Piece::of(BrickShape(kind, 0)),
+                    longest.map(|longest| (longest, mean)),
+                )
+            })
+            .collect();
+
+        let mut ends = BTreeMap::new();
+        for game in games {
+            *ends.entry(game.end).or_insert(0) += 1;
+        }
+        Summary {
+            games: games.len(),
+            mean_lines: games.iter().map(|game| game.lines as f64).sum::<f64>() / count as f64,
+            score: [0., 0.25, 0.5, 0.75, 1.].map(quantile),
+            mean_score: games.iter().map(|game| game.score as f64).sum::<f64>() / count as f64,
+            pieces,
+            pieces_per_second: pieces as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+            droughts,
+            ends,
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "games        {}", self.games)?;
+        writeln!(f, "mean lines   {:.1}", self.mean_lines)?;
+        let [min, q1, median, q3, max] = self.score;
+        writeln!(
+            f,
+            "score        mean {:.0}, min {} / q1 {} / median {} / q3 {} / max {}",
+            self.mean_score, min, q1, median, q3, max
+        )?;
+        writeln!(
+            f,
+            "pieces       {} ({:.0} per second)",
+            self.pieces, self.pieces_per_second
+        )?;
+        writeln!(f, "droughts     longest / mean gap")?;
+        for (piece, stats) in &self.droughts {
+            match stats {
+                Some((longest, mean)) => {
+                    writeln!(f, "  {:?}          {} / {:.2}", piece, longest, mean)?
+                }
+                None => writeln!(f, "  {:?}          never dealt", piece)?,
+            }
+        }
+        writeln!(f, "endings")?;
+        for (end, count) in &self.ends {
+            writeln!(f, "  {:<14} {}", end.to_string(), count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Randomizer, SimConfig, SimEnd, Summary, droughts, play_started, simulate};
+
+    use crate::{
+        ai::AiWeights, brick::BrickShape, game_data::GameData, mode::GameMode, tbp::Piece,
+    };
+
+    #[test]
+    fn test_droughts() {
+        let shapes = [0, 1, 1, 2, 0, 1].map(|kind| BrickShape(kind, 0));
+        let droughts = droughts(&shapes);
+        assert_eq!(droughts[0], (Piece::O, Some((3, 1.5))));
+        assert_eq!(droughts[1], (Piece::I, Some((2, 1.))));
+        assert_eq!(droughts[6], (Piece::T, None));
+    }
+
+    #[test]
+    fn test_simulate_with_bag() {
+        let config = SimConfig {
+            randomizer: Randomizer::Bag,
+            games: 2,
+            seed: 5,
+            max_pieces: 21,
+            threads: 2,
+            ..Default::default()
+        };
+        let games = simulate(&config, &AiWeights::default()).unwrap();
+        assert_eq!(games.len(), 2);
+        for game in &games {
+            assert_eq!(game.end, SimEnd::PieceLimit);
+            // every run of seven deals each brick once
+            for bag in game.shapes[..21].chunks(7) {
+                let mut kinds: Vec<usize> = bag.iter().map(|shape| shape.0).collect();
+                kinds.sort();
+                assert_eq!(kinds, (0..7).collect::<Vec<_>>());
+            }
+        }
+        let summary = Summary::of(&games, Duration::from_secs(1));
+        assert_eq!(summary.pieces, 42);
+        assert_eq!(summary.ends[&SimEnd::PieceLimit], 2);
+        assert!(
+            summary
+                .droughts
+                .iter()
+                .all(|(_, stats)| stats.unwrap().0 <= 12)
+        );
+    }
+
+    #[test]
+    fn test_garbage_top_out() {
+        let config = SimConfig {
+            mode: GameMode::DigSurvival,
+            ..Default::default()
+        };
+        let mut game_data = GameData {
+            mode: config.mode,
+            ..Default::default()
+        };
+        game_data.start(2);
+        // the next garbage row pushes this cell out of the top
+        let rows = game_data.board.rows;
+        game_data.board.set_occupied(0, rows - 1, true);
+        let game = play_started(&config, game_data, &AiWeights::default()).unwrap();
+        assert!(
+            matches!(game.end, SimEnd::TopOut(_)),
+            "ended by {}",
+            game.end
+        );
+    }
+}
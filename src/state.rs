@@ -1,12 +1,11 @@
-
 use bevy::state::state::States;
 
-
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
 pub enum GameSate {
     #[default]
     Loading,
     Ready,
     Playing,
+    Results,
     GameOver,
 }
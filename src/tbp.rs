@@ -0,0 +1,652 @@
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, BufReader, Read, Write},
+    process::{Child, Command, Stdio},
+    sync::{
+        Mutex,
+        mpsc::{self, Receiver},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    ecs::{
+        component::Component,
+        entity::Entity,
+        query::With,
+        system::{Commands, Query},
+    },
+    log::{info, warn},
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{
+    ai::Placement,
+    brick::{BrickShape, brick_at},
+    constants::TBP_MOVE_TICKS,
+    finder::{Route, find_routes},
+    game_data::{Board, EraseAnimationStep, GameAction, GameData, InputBuffer},
+};
+
+/// Rows of the board sent to bots, the spec asks for 40.
+const TBP_BOARD_ROWS: usize = 40;
+
+/// How long a bot gets to quit on its own before it's killed.
+const TBP_QUIT_TIMEOUT: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Piece {
+    I,
+    O,
+    T,
+    L,
+    J,
+    S,
+    Z,
+}
+
+impl Piece {
+    pub fn of(shape: BrickShape) -> Self {
+        [
+            Piece::O,
+            Piece::I,
+            Piece::L,
+            Piece::J,
+            Piece::Z,
+            Piece::S,
+            Piece::T,
+        ][shape.0]
+    }
+
+    /// Cells of the piece facing north around its center, y pointing up.
+    fn cells(&self) -> [(i32, i32); 4] {
+        match self {
+            Piece::I => [(-1, 0), (0, 0), (1, 0), (2, 0)],
+            Piece::O => [(0, 0), (1, 0), (0, 1), (1, 1)],
+            Piece::T => [(-1, 0), (0, 0), (1, 0), (0, 1)],
+            Piece::L => [(-1, 0), (0, 0), (1, 0), (1, 1)],
+            Piece::J => [(-1, 0), (0, 0), (1, 0), (-1, 1)],
+            Piece::S => [(-1, 0), (0, 0), (0, 1), (1, 1)],
+            Piece::Z => [(-1, 1), (0, 1), (0, 0), (1, 0)],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Orientation {
+    pub const ALL: [Orientation; 4] = [
+        Orientation::North,
+        Orientation::East,
+        Orientation::South,
+        Orientation::West,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Spin {
+    None,
+    Mini,
+    Full,
+}
+
+/// Where a piece locks, by the center it rotates around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Location {
+    #[serde(rename = "type")]
+    pub piece: Piece,
+    pub orientation: Orientation,
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Location {
+    /// The board cells the piece covers, bottom row 0.
+    pub fn cells(&self) -> [(i32, i32); 4] {
+        self.piece.cells().map(|(x, y)| {
+            let (x, y) = match self.orientation {
+                Orientation::North => (x, y),
+                Orientation::East => (y, -x),
+                Orientation::South => (-x, -y),
+                Orientation::West => (-y, x),
+            };
+            (self.x + x, self.y + y)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Move {
+    pub location: Location,
+    pub spin: Spin,
+}
+
+/// The position a bot starts searching from. Every filled cell is sent as
+/// garbage, bots only care where the stack is.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Start {
+    pub hold: Option<Piece>,
+    pub queue: Vec<Piece>,
+    pub combo: u32,
+    pub back_to_back: bool,
+    /// Rows from the bottom up, `None` for an empty cell.
+    pub board: Vec<Vec<Option<char>>>,
+}
+
+/// Messages the game sends to a bot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FrontendMessage {
+    Rules,
+    Start(Start),
+    Stop,
+    Suggest,
+    Play {
+        #[serde(rename = "move")]
+        mv: Move,
+    },
+    NewPiece {
+        piece: Piece,
+    },
+    Quit,
+}
+
+/// Messages a bot sends to the game.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BotMessage {
+    Info {
+        name: String,
+        version: String,
+        author: String,
+        features: Vec<String>,
+    },
+    Ready,
+    Error {
+        reason: String,
+    },
+    Suggestion {
+        moves: Vec<Move>,
+    },
+}
+
+pub fn write_message(writer: &mut impl Write, message: &impl Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())?;
+    writer.flush()
+}
+
+/// Reads the next message, `Ok(None)` once the other end has closed.
+pub fn read_message<M: DeserializeOwned>(reader: &mut impl BufRead) -> io::Result<Option<M>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&line)?))
+}
+
+/// The start message for the falling brick of `game_data`.
+pub fn start_message(game_data: &GameData) -> Start {
+    let board = &game_data.board;
+    Start {
+        hold: None,
+        queue: vec![
+            Piece::of(game_data.falling_brick_shape),
+            Piece::of(game_data.next_brick_shape),
+        ],
+        combo: 0,
+        back_to_back: false,
+        board: (0..TBP_BOARD_ROWS.max(board.rows))
+            .map(|y| {
+                (0..board.cols)
+                    .map(|x| (y < board.rows && board.is_occupied(x, y)).then_some('G'))
+                    .collect()
+            })
+            .collect(),
+    }
+}
+
+/// The route that locks the falling brick of `game_data` on the cells of
+/// `mv`, if it can get there.
+pub fn route_for_move(game_data: &GameData, mv: &Move) -> Option<Route> {
+    if mv.location.piece != Piece::of(game_data.falling_brick_shape) {
+        return None;
+    }
+    let mut cells = mv.location.cells();
+    cells.sort();
+    find_routes(
+        &game_data.board,
+        game_data.falling_brick_shape,
+        game_data.falling_brick_node,
+    )
+    .into_iter()
+    .find(|route| {
+        let mut route_cells = brick_at(route.shape, route.node)
+            .nodes
+            .map(|node| (node.0 as i32, node.1 as i32));
+        route_cells.sort();
+        route_cells == cells
+    })
+}
+
+/// An external engine playing a board through the Tetris Bot Protocol, one
+/// JSON message per line. The bot's moves go through the board's
+/// `InputBuffer` like a player's.
+#[derive(Component)]
+pub struct TbpBot {
+    process: Option<Child>,
+    writer: Mutex<Box<dyn Write + Send>>,
+    incoming: Mutex<Receiver<BotMessage>>,
+    pub name: Option<String>,
+    ready: bool,
+    /// Set once a start message went out, until the bot is stopped.
+    started: bool,
+    waiting: bool,
+    /// The spawn tick of the brick the last move was played for.
+    played_brick: Option<u64>,
+    /// The board the bot expects after its last move, it is restarted when
+    /// garbage or a missed move leaves a different one.
+    expected: Option<Board>,
+    inputs: VecDeque<GameAction>,
+    cooldown: u32,
+    /// Suggestions none of whose moves could be reached.
+    pub missed: u32,
+    failed: bool,
+}
+
+impl TbpBot {
+    /// Runs `command`, split on whitespace, as the bot.
+    pub fn spawn(command: &str) -> io::Result<Self> {
+        let mut args = command.split_whitespace();
+        let program = args
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty bot command"))?;
+        let mut process = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = process.stdin.take().unwrap();
+        let stdout = process.stdout.take().unwrap();
+        let mut bot = Self::from_streams(stdout, stdin);
+        bot.process = Some(process);
+        Ok(bot)
+    }
+
+    /// Talks to a bot over `reader` and `writer`, messages are read on a
+    /// thread of their own.
+    pub fn from_streams(
+        reader: impl Read + Send + 'static,
+        writer: impl Write + Send + 'static,
+    ) -> Self {
+        let mut reader = BufReader::new(reader);
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            loop {
+                match read_message(&mut reader) {
+                    Ok(Some(message)) => {
+                        if sender.send(message).is_err() {
+                            return;
+                        }
+                    }
+                    Ok(None) => {
+                        let _ = sender.send(BotMessage::Error {
+                            reason: "the bot closed its output".to_string(),
+                        });
+                        return;
+                    }
+                    Err(err) => {
+                        let _ = sender.send(BotMessage::Error {
+                            reason: err.to_string(),
+                        });
+                        return;
+                    }
+                }
+            }
+        });
+        TbpBot {
+            process: None,
+            writer: Mutex::new(Box::new(writer)),
+            incoming: Mutex::new(receiver),
+            name: None,
+            ready: false,
+            started: false,
+            waiting: false,
+            played_brick: None,
+            expected: None,
+            inputs: VecDeque::new(),
+            cooldown: 0,
+            missed: 0,
+            failed: false,
+        }
+    }
+
+    pub fn send(&self, message: &FrontendMessage) {
+        if let Err(err) = write_message(&mut *self.writer.lock().unwrap(), message) {
+            warn!("Failed to send to the bot: {}", err);
+        }
+    }
+
+    /// Whether a suggestion has been asked for and not received yet.
+    pub fn is_waiting(&self) -> bool {
+        self.waiting
+    }
+
+    /// Whether the bot is still starting up or working out a suggestion.
+    pub fn is_thinking(&self) -> bool {
+        !self.failed && (!self.ready || self.waiting)
+    }
+
+    fn handle(&mut self, message: BotMessage, game_data: &GameData) {
+        match message {
+            BotMessage::Info { name, .. } => {
+                info!("Bot {} connected", name);
+                self.name = Some(name);
+                self.send(&FrontendMessage::Rules);
+            }
+            BotMessage::Ready => self.ready = true,
+            BotMessage::Error { reason } => {
+                warn!("Bot failed: {}", reason);
+                self.failed = true;
+            }
+            BotMessage::Suggestion { moves } => {
+                if !self.waiting {
+                    return;
+                }
+                self.waiting = false;
+                self.played_brick = Some(game_data.spawn_tick);
+                let chosen = moves
+                    .iter()
+                    .find_map(|mv| route_for_move(game_data, mv).map(|route| (mv, route)));
+                match chosen {
+                    Some((mv, route)) => {
+                        self.send(&FrontendMessage::Play { mv: *mv });
+                        self.expected =
+                            Some(Placement::new(&game_data.board, route.shape, route.node).board);
+                        self.inputs = route.inputs.into();
+                    }
+                    None => {
+                        // drop the brick where it is and start the bot over
+                        self.missed += 1;
+                        self.expected = None;
+                        self.inputs = VecDeque::from([GameAction::Drop]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The next input for the board: the moves of the last suggestion one
+    /// at a time, or nothing while the bot thinks.
+    pub fn act(&mut self, game_data: &GameData) -> Option<GameAction> {
+        let messages: Vec<_> = self.incoming.lock().unwrap().try_iter().collect();
+        for message in messages {
+            self.handle(message, game_data);
+        }
+        if self.failed
+            || !self.ready
+            || self.waiting
+            || !game_data.is_running()
+            || game_data.is_dropping()
+            || game_data.erase_animation_step == EraseAnimationStep::Playing
+        {
+            return None;
+        }
+        if !self.inputs.is_empty() {
+            if self.cooldown > 0 {
+                self.cooldown -= 1;
+                return None;
+            }
+            self.cooldown = TBP_MOVE_TICKS;
+            return self.inputs.pop_front();
+        }
+        if self.played_brick == Some(game_data.spawn_tick) {
+            return None;
+        }
+        if self.started && self.expected.as_ref() == Some(&game_data.board) {
+            self.send(&FrontendMessage::NewPiece {
+                piece: Piece::of(game_data.next_brick_shape),
+            });
+        } else {
+            if self.started {
+                self.send(&FrontendMessage::Stop);
+            }
+            self.send(&FrontendMessage::Start(start_message(game_data)));
+            self.started = true;
+        }
+        self.send(&FrontendMessage::Suggest);
+        self.waiting = true;
+        None
+    }
+}
+
+impl Drop for TbpBot {
+    fn drop(&mut self) {
+        self.send(&FrontendMessage::Quit);
+        let Some(process) = &mut self.process else {
+            return;
+        };
+        // the game waits on this, so a bot that doesn't quit is killed
+        let started = Instant::now();
+        while started.elapsed() < TBP_QUIT_TIMEOUT {
+            if !matches!(process.try_wait(), Ok(None)) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        let _ = process.kill();
+        let _ = process.wait();
+    }
+}
+
+pub fn tbp_input_system(mut boards: Query<(&GameData, &mut InputBuffer, &mut TbpBot)>) {
+    for (game_data, mut input_buffer, mut bot) in &mut boards {
+        if let Some(action) = bot.act(game_data) {
+            input_buffer.push(action);
+        }
+    }
+}
+
+/// Sends the bots off on the Ready screen.
+pub fn end_tbp(mut commands: Commands, boards: Query<Entity, With<TbpBot>>) {
+    for board in &boards {
+        commands.entity(board).remove::<TbpBot>();
+    }
+}
+
+/// A bot for testing the protocol: it drops the first piece of its queue
+/// straight down wherever that leaves it lowest.
+pub fn run_test_bot(reader: impl BufRead, mut writer: impl Write) -> io::Result<()> {
+    let mut reader = reader;
+    write_message(
+        &mut writer,
+        &BotMessage::Info {
+            name: "rs-tetris test bot".into(),
+            version: env!("CARGO_PKG_VERSION").into(),
+            author: "rs-tetris".into(),
+            features: vec![],
+        },
+    )?;
+    let mut board: Vec<Vec<Option<char>>> = vec![];
+    let mut queue = VecDeque::new();
+    while let Some(message) = read_message::<FrontendMessage>(&mut reader)? {
+        match message {
+            FrontendMessage::Rules => write_message(&mut writer, &BotMessage::Ready)?,
+            FrontendMessage::Start(start) => {
+                board = start.board;
+                queue = start.queue.into();
+            }
+            FrontendMessage::Stop => {
+                board.clear();
+                queue.clear();
+            }
+            FrontendMessage::Suggest => {
+                let moves = queue
+                    .front()
+                    .map_or_else(Vec::new, |piece| drops(&board, *piece));
+                write_message(&mut writer, &BotMessage::Suggestion { moves })?;
+            }
+            FrontendMessage::Play { mv } => {
+                for (x, y) in mv.location.cells() {
+                    board[y as usize][x as usize] = Some('G');
+                }
+                board.retain(|row| row.iter().any(|cell| cell.is_none()));
+                let cols = board.first().map_or(0, |row| row.len());
+                board.resize(TBP_BOARD_ROWS, vec![None; cols]);
+                queue.pop_front();
+            }
+            FrontendMessage::NewPiece { piece } => queue.push_back(piece),
+            FrontendMessage::Quit => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+/// Every straight drop of `piece` from the top of the visible board, the
+/// ones ending lowest first.
+fn drops(board: &[Vec<Option<char>>], piece: Piece) -> Vec<Move> {
+    let cols = board.first().map_or(0, |row| row.len()) as i32;
+    let is_free = |location: &Location| {
+        location.cells().iter().all(|(x, y)| {
+            (0..cols).contains(x)
+                && *y >= 0
+                && board
+                    .get(*y as usize)
+                    .is_none_or(|row| row[*x as usize].is_none())
+        })
+    };
+    let mut moves: Vec<Move> = Orientation::ALL
+        .iter()
+        .flat_map(|orientation| (-1..=cols).map(move |x| (*orientation, x)))
+        .filter_map(|(orientation, x)| {
+            let mut location = Location {
+                piece,
+                orientation,
+                x,
+                y: 21,
+            };
+            if !is_free(&location) {
+                return None;
+            }
+            while is_free(&Location {
+                y: location.y - 1,
+                ..location
+            }) {
+                location.y -= 1;
+            }
+            Some(Move {
+                location,
+                spin: Spin::None,
+            })
+        })
+        .collect();
+    moves.sort_by_key(|mv| mv.location.cells().iter().map(|(_, y)| *y).max());
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::BufReader,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{FrontendMessage, Location, Orientation, Piece, TbpBot, run_test_bot};
+
+    use crate::{
+        brick::BrickShape,
+        game_data::{GameAction, GameData},
+        mode::GameMode,
+    };
+
+    #[test]
+    fn test_messages() {
+        let json = serde_json::to_string(&FrontendMessage::NewPiece { piece: Piece::T }).unwrap();
+        assert_eq!(json, r#"{"type":"new_piece","piece":"T"}"#);
+        let location = Location {
+            piece: Piece::T,
+            orientation: Orientation::East,
+            x: 4,
+            y: 1,
+        };
+        let mut cells = location.cells();
+        cells.sort();
+        assert_eq!(cells, [(4, 0), (4, 1), (4, 2), (5, 1)]);
+        assert_eq!(Piece::of(BrickShape(6, 0)), Piece::T);
+    }
+
+    /// Plays `mode` with the test bot until it dropped `drops` bricks or
+    /// stalled.
+    fn play(mode: GameMode, drops: usize) -> (TbpBot, GameData) {
+        let (bot_reader, frontend_writer) = std::io::pipe().unwrap();
+        let (frontend_reader, bot_writer) = std::io::pipe().unwrap();
+        thread::spawn(move || run_test_bot(BufReader::new(bot_reader), bot_writer));
+        let mut bot = TbpBot::from_streams(frontend_reader, frontend_writer);
+
+        let mut game_data = GameData {
+            mode,
+            ..Default::default()
+        };
+        game_data.start(9);
+        for _ in 0..100_000 {
+            if dropped(&game_data) >= drops || game_data.ended.is_some() {
+                break;
+            }
+            let action = bot.act(&game_data);
+            // the game doesn't wait for a slow bot to start, the test does
+            if bot.is_waiting() || !bot.ready {
+                thread::sleep(Duration::from_millis(1));
+            } else {
+                game_data.tick_with(action);
+            }
+        }
+        (bot, game_data)
+    }
+
+    fn dropped(game_data: &GameData) -> usize {
+        game_data
+            .replay
+            .actions
+            .iter()
+            .filter(|(_, action)| *action == GameAction::Drop)
+            .count()
+    }
+
+    #[test]
+    fn test_bot_plays_through_protocol() {
+        let (bot, game_data) = play(GameMode::default(), 20);
+        assert_eq!(dropped(&game_data), 20);
+        assert_eq!(bot.missed, 0);
+        assert!(game_data.ended.is_none());
+        assert_eq!(bot.name.as_deref(), Some("rs-tetris test bot"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stuck_bot_is_killed() {
+        let bot = TbpBot::spawn("sleep 30").unwrap();
+        let started = Instant::now();
+        drop(bot);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_bot_keeps_playing_on_unchanged_board() {
+        // the trainer never adds the bricks to the board
+        let (bot, game_data) = play(GameMode::Finesse, 5);
+        assert_eq!(dropped(&game_data), 5);
+        assert_eq!(bot.missed, 0);
+        assert!(game_data.board.occupied().all(|cell| !cell));
+    }
+}
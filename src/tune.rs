@@ -0,0 +1,224 @@
+use std::{f64::consts::TAU, thread};
+
+use rand::{Rng, RngCore};
+
+use crate::{
+    ai::{AiWeights, best_placement},
+    brick::BrickShape,
+    constants::{BOARD_BRICK_NODE_COLS, BOARD_BRICK_NODE_ROWS},
+    game_data::Board,
+    rng::GameRng,
+};
+
+/// Plays a game a placement at a time with `weights` on an empty board of
+/// `cols` by `rows`, the bricks coming in the order a game started with `seed`
+/// deals them. Returns the lines cleared before topping out or placing
+/// `max_pieces`.
+pub fn play_placements(
+    (cols, rows): (usize, usize),
+    seed: u64,
+    weights: &AiWeights,
+    max_pieces: u32,
+) -> u32 {
+    let mut rng = GameRng::new(seed);
+    let mut board = Board::new(cols, rows);
+    let mut lines = 0;
+    for _ in 0..max_pieces {
+        let shape = BrickShape::rand(&mut rng);
+        let Some(placement) = best_placement(&board, shape, weights) else {
+            break;
+        };
+        lines += placement.lines as u32;
+        board = placement.board;
+    }
+    lines
+}
+
+/// Settings of a cross-entropy search over evaluator weights.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TuneConfig {
+    /// Everything random in a run comes from it, the same seed gives the same
+    /// weights.
+    pub seed: u64,
+    pub generations: u32,
+    /// Candidates tried each generation.
+    pub population: usize,
+    /// Best candidates the next generation is sampled around.
+    pub elite: usize,
+    /// Games each candidate plays, on the same seeds within a generation.
+    pub games: u32,
+    /// Pieces after which a game is stopped.
+    pub pieces: u32,
+    /// Threads the games are spread over.
+    pub threads: usize,
+}
+
+impl Default for TuneConfig {
+    fn default() -> Self {
+        TuneConfig {
+            seed: 0,
+            generations: 20,
+            population: 24,
+            elite: 6,
+            games: 4,
+            pieces: 500,
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+        }
+    }
+}
+
+/// How a generation went, reported as the search goes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Generation {
+    pub index: u32,
+    /// Mean lines per game of the best candidate.
+    pub best_lines: f64,
+    /// Mean lines per game over the whole population.
+    pub mean_lines: f64,
+    pub best: AiWeights,
+}
+
+/// Only the ratios between weights matter to the evaluator, so candidates
+/// are kept at unit length.
+fn normalized(weights: [f64; 5]) -> [f64; 5] {
+    let length = weights
+        .iter()
+        .map(|weight| weight * weight)
+        .sum::<f64>()
+        .sqrt();
+    if length == 0. {
+        return weights;
+    }
+    weights.map(|weight| weight / length)
+}
+
+fn to_weights(weights: [f64; 5]) -> AiWeights {
+    AiWeights::from_array(weights.map(|weight| weight as f32))
+}
+
+/// A standard normal sample, by the Box-Muller transform.
+fn gaussian(rng: &mut impl Rng) -> f64 {
+    let u = 1. - rng.random::<f64>();
+    let v = rng.random::<f64>();
+    (-2. * u.ln()).sqrt() * (TAU * v).cos()
+}
+
+/// Mean lines per game of each candidate, spread over `threads`.
+fn evaluate(candidates: &[[f64; 5]], seeds: &[u64], pieces: u32, threads: usize) -> Vec<f64> {
+    let size = (BOARD_BRICK_NODE_COLS, BOARD_BRICK_NODE_ROWS);
+    let chunk = candidates.len().div_ceil(threads.max(1)).max(1);
+    thread::scope(|scope| {
+        let handles: Vec<_> = candidates
+            .chunks(chunk)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|candidate| {
+                            let weights = to_weights(*candidate);
+                            let lines: u32 = seeds
+                                .iter()
+                                .map(|seed| play_placements(size, *seed, &weights, pieces))
+                                .sum();
+                            lines as f64 / seeds.len() as f64
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+/// Searches for evaluator weights that clear the most lines with the
+/// cross-entropy method: each generation samples candidates around a mean,
+/// plays them on the same seeded games and moves the mean and spread to the
+/// best of them. `report` is called after every generation.
+pub fn tune(config: &TuneConfig, mut report: impl FnMut(&Generation)) -> AiWeights {
+    let mut rng = GameRng::new(config.seed);
+    let mut mean = normalized(AiWeights::default().to_array().map(f64::from));
+    let mut deviation = [0.5; 5];
+    let mut best = (f64::MIN, to_weights(mean));
+    let elite = config.elite.clamp(1, config.population.max(1));
+    for index in 0..config.generations {
+        let seeds: Vec<u64> = (0..config.games).map(|_| rng.next_u64()).collect();
+        let candidates: Vec<[f64; 5]> = (0..config.population.max(1))
+            .map(|_| {
+                let mut candidate = mean;
+                candidate
+                    .iter_mut()
+                    .zip(deviation)
+                    .for_each(|(weight, deviation)| *weight += deviation * gaussian(&mut rng));
+                normalized(candidate)
+            })
+            .collect();
+        let lines = evaluate(&candidates, &seeds, config.pieces, config.threads);
+        let mut ranked: Vec<usize> = (0..candidates.len()).collect();
+        ranked.sort_by(|a, b| lines[*b].total_cmp(&lines[*a]));
+        let elites: Vec<[f64; 5]> = ranked[..elite].iter().map(|i| candidates[*i]).collect();
+        for dim in 0..5 {
+            mean[dim] = elites.iter().map(|elite| elite[dim]).sum::<f64>() / elite as f64;
+            let variance = elites
+                .iter()
+                .map(|elite| (elite[dim] - mean[dim]).powi(2))
+                .sum::<f64>()
+                / elite as f64;
+            // a little extra noise that fades out keeps the search from
+            // collapsing early
+            deviation[dim] = variance.sqrt() + 0.1 / (index + 1) as f64;
+        }
+        mean = normalized(mean);
+
+        let generation_best = (lines[ranked[0]], to_weights(candidates[ranked[0]]));
+        if generation_best.0 > best.0 {
+            best = generation_best;
+        }
+        report(&Generation {
+            index,
+            best_lines: generation_best.0,
+            mean_lines: lines.iter().sum::<f64>() / lines.len() as f64,
+            best: best.1,
+        });
+    }
+    best.1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TuneConfig, play_placements, tune};
+
+    use crate::ai::AiWeights;
+
+    #[test]
+    fn test_play_placements() {
+        let size = (10, 20);
+        let weights = AiWeights::default();
+        let lines = play_placements(size, 4, &weights, 200);
+        assert!(lines >= 60, "cleared {}", lines);
+        assert_eq!(play_placements(size, 4, &weights, 200), lines);
+        // stacking as high as possible tops out early
+        let tower = AiWeights::from_array([1., 0., 0., 0., 0.]);
+        assert!(play_placements(size, 4, &tower, 200) < lines);
+    }
+
+    #[test]
+    fn test_tune_is_reproducible() {
+        let config = TuneConfig {
+            seed: 9,
+            generations: 2,
+            population: 4,
+            elite: 2,
+            games: 1,
+            pieces: 40,
+            threads: 2,
+        };
+        let mut generations = vec![];
+        let best = tune(&config, |generation| generations.push(*generation));
+        assert_eq!(generations.len(), 2);
+        assert_eq!(generations[1].best, best);
+        assert_eq!(tune(&config, |_| {}), best);
+    }
+}
@@ -0,0 +1,536 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    color::Color,
+    ecs::{
+        component::Component,
+        entity::Entity,
+        event::{EventReader, EventWriter},
+        query::{Has, Or, With, Without},
+        system::{Commands, Query, Res, ResMut, Resource, Single},
+    },
+    hierarchy::{BuildChildren, ChildBuild, DespawnRecursiveExt, Parent},
+    input::{
+        ButtonInput,
+        gamepad::{Gamepad, GamepadButton},
+        keyboard::KeyCode,
+    },
+    math::{Vec2, Vec3},
+    sprite::{Anchor, Sprite},
+    state::state::NextState,
+    text::{FontSmoothing, JustifyText, Text2d, TextColor, TextFont, TextLayout},
+    transform::components::Transform,
+    utils::default,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    GameAssets,
+    ai::AiPlayer,
+    board::{PlayerBoard, spawn_bricks, spawn_game_board},
+    constants::{
+        BRICK_NODE_WIDTH, BRICKS_CONTAINER_BOUNDING_LEFT, BRICKS_CONTAINER_BOUNDING_TOP,
+        VERSUS_BOARD_OFFSET_X, VERSUS_BOARD_SCALE,
+    },
+    events::{BoardEvent, GarbageSent, PieceLocked, TopOut},
+    game_data::{GameAction, GameData, GameEnd, InputBuffer},
+    mode::{GameMode, format_countdown, spawn_results_panel},
+    net::RemoteBoard,
+    rng::GameRng,
+    state::GameSate,
+    tbp::TbpBot,
+};
+
+/// Keys of each player, the left and right halves of the keyboard.
+const PLAYER_KEYS: [[(KeyCode, GameAction); 5]; 2] = [
+    [
+        (KeyCode::KeyA, GameAction::Left),
+        (KeyCode::KeyD, GameAction::Right),
+        (KeyCode::KeyS, GameAction::Down),
+        (KeyCode::KeyW, GameAction::Rotate),
+        (KeyCode::Space, GameAction::Drop),
+    ],
+    [
+        (KeyCode::ArrowLeft, GameAction::Left),
+        (KeyCode::ArrowRight, GameAction::Right),
+        (KeyCode::ArrowDown, GameAction::Down),
+        (KeyCode::ArrowUp, GameAction::Rotate),
+        (KeyCode::Enter, GameAction::Drop),
+    ],
+];
+
+const GAMEPAD_BUTTONS: [(GamepadButton, GameAction); 6] = [
+    (GamepadButton::DPadLeft, GameAction::Left),
+    (GamepadButton::DPadRight, GameAction::Right),
+    (GamepadButton::DPadDown, GameAction::Down),
+    (GamepadButton::North, GameAction::Rotate),
+    (GamepadButton::East, GameAction::Rotate),
+    (GamepadButton::South, GameAction::Drop),
+];
+
+/// How many garbage rows a clear sends to the opponent.
+#[derive(Debug, Clone, PartialEq, Eq, Resource, Serialize, Deserialize)]
+pub struct AttackTable {
+    /// Rows sent for clearing 1, 2, 3 and 4 lines.
+    pub lines: [u32; 4],
+    /// T-spin clears send this many times the rows above.
+    pub t_spin_multiplier: u32,
+    /// Added by the number of clears in a row before this one, the last
+    /// entry holds for longer combos.
+    pub combo: Vec<u32>,
+    /// Added to a Tetris or T-spin clear that follows another one.
+    pub back_to_back: u32,
+}
+
+impl Default for AttackTable {
+    fn default() -> Self {
+        AttackTable {
+            lines: [0, 1, 2, 4],
+            t_spin_multiplier: 2,
+            combo: vec![0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5],
+            back_to_back: 1,
+        }
+    }
+}
+
+impl AttackTable {
+    fn combo_bonus(&self, combo: u32) -> u32 {
+        self.combo
+            .get(combo as usize)
+            .or(self.combo.last())
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// A player's side of a versus match, kept on their board entity.
+#[derive(Debug, Clone, Component)]
+pub struct Versus {
+    /// 0 for the first player, picks the keys and gamepad.
+    pub player: usize,
+    pub opponent: Entity,
+    /// Incoming garbage, one entry per attack, oldest first.
+    pub meter: VecDeque<u32>,
+    /// Clears in a row before the last one, `None` once a placement clears
+    /// nothing.
+    pub combo: Option<u32>,
+    /// Whether the last clear was a Tetris or a T-spin.
+    pub back_to_back: bool,
+    /// Rows sent over the whole match.
+    pub sent: u32,
+    /// Picks the hole columns, apart from the bricks so both players keep
+    /// getting the same ones.
+    pub rng: GameRng,
+}
+
+impl Versus {
+    pub fn new(player: usize, opponent: Entity, seed: u64) -> Self {
+        Versus {
+            player,
+            opponent,
+            meter: VecDeque::new(),
+            combo: None,
+            back_to_back: false,
+            sent: 0,
+            rng: GameRng::new(seed.wrapping_add(player as u64 + 1)),
+        }
+    }
+
+    /// Rows a placement clearing `lines` sends before cancelling, keeping
+    /// track of the combo and back-to-back.
+    pub fn attack(&mut self, table: &AttackTable, lines: usize, t_spin: bool) -> u32 {
+        if lines == 0 {
+            self.combo = None;
+            return 0;
+        }
+        let combo = self.combo.map_or(0, |combo| combo + 1);
+        self.combo = Some(combo);
+        let mut rows = table.lines[lines.min(4) - 1];
+        if t_spin {
+            rows *= table.t_spin_multiplier;
+        }
+        let is_difficult = lines >= 4 || t_spin;
+        if is_difficult && self.back_to_back {
+            rows += table.back_to_back;
+        }
+        self.back_to_back = is_difficult;
+        rows + table.combo_bonus(combo)
+    }
+
+    /// Incoming rows waiting in the meter.
+    pub fn pending(&self) -> u32 {
+        self.meter.iter().sum()
+    }
+
+    /// Cancels incoming garbage with `rows`, returns the rows left to send.
+    pub fn cancel(&mut self, mut rows: u32) -> u32 {
+        while rows > 0
+            && let Some(incoming) = self.meter.front_mut()
+        {
+            let cancelled = rows.min(*incoming);
+            *incoming -= cancelled;
+            rows -= cancelled;
+            if *incoming == 0 {
+                self.meter.pop_front();
+            }
+        }
+        rows
+    }
+
+    /// Settles a placement on the board that cleared `lines`: its attack less
+    /// what cancels incoming garbage, or the meter raised into the board when
+    /// nothing was cleared. Returns the rows sent and whether the board topped
+    /// out.
+    pub fn place(
+        &mut self,
+        table: &AttackTable,
+        game_data: &mut GameData,
+        lines: usize,
+        t_spin: bool,
+    ) -> (u32, bool) {
+        let rows = self.attack(table, lines, t_spin);
+        let rows = self.cancel(rows);
+        self.sent += rows;
+        let is_topped_out = lines == 0 && self.raise_garbage(game_data);
+        if is_topped_out {
+            game_data.ended = Some(GameEnd::TopOut);
+        }
+        (rows, is_topped_out)
+    }
+
+    /// Raises the whole meter into the board, each attack with its own hole
+    /// column. Returns true on top-out.
+    pub fn raise_garbage(&mut self, game_data: &mut GameData) -> bool {
+        while let Some(rows) = self.meter.pop_front() {
+            let hole_col = self.rng.random_range(0..game_data.board.cols);
+            if game_data.push_garbage_rows(rows, hole_col) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Height of the incoming garbage, drawn left of the board's cells.
+#[derive(Component)]
+pub struct GarbageMeter;
+
+#[derive(Component)]
+pub struct PlayerLabel;
+
+fn versus_transform(player: usize) -> Transform {
+    let x = if player == 0 {
+        -VERSUS_BOARD_OFFSET_X
+    } else {
+        VERSUS_BOARD_OFFSET_X
+    };
+    // the frame is centered 90 above the board's origin, keep it at that height
+    Transform::from_xyz(x, 90. * (1. - VERSUS_BOARD_SCALE), 0.).with_scale(Vec3::new(
+        VERSUS_BOARD_SCALE,
+        VERSUS_BOARD_SCALE,
+        1.,
+    ))
+}
+
+/// Starts the opponent's board next to the player's one with the same seed,
+/// so both get the same bricks, and shrinks both to fit the window. `player`
+/// is the player's side, returns the opponent's board.
+pub fn start_versus(
+    commands: &mut Commands,
+    game_assets: &GameAssets,
+    player_board: Entity,
+    game_data: &GameData,
+    player: usize,
+) -> Entity {
+    let mut opponent_data = GameData {
+        board: game_data.board.cleared(),
+        mode: GameMode::Versus,
+        ..Default::default()
+    };
+    opponent_data.start(game_data.seed);
+    let opponent = spawn_game_board(commands, game_assets, opponent_data.clone(), Vec2::ZERO);
+    spawn_bricks(commands, opponent, &opponent_data);
+
+    let meter_x = BRICKS_CONTAINER_BOUNDING_LEFT - 9.;
+    let meter_y = BRICKS_CONTAINER_BOUNDING_TOP - game_data.board.rows as f32 * BRICK_NODE_WIDTH;
+    let sides = [
+        (player, player_board, opponent),
+        (1 - player, opponent, player_board),
+    ];
+    for (player, board, opponent) in sides {
+        commands
+            .entity(board)
+            .insert((
+                versus_transform(player),
+                Versus::new(player, opponent, game_data.seed),
+            ))
+            .with_children(|child_builder| {
+                child_builder.spawn((
+                    Sprite {
+                        color: Color::BLACK,
+                        custom_size: Some(Vec2::new(4., 0.)),
+                        anchor: Anchor::BottomCenter,
+                        ..default()
+                    },
+                    Transform::from_xyz(meter_x, meter_y, 300.),
+                    GarbageMeter,
+                ));
+                child_builder.spawn((
+                    Text2d::new(format!("P{}", player + 1)),
+                    TextColor(Color::BLACK),
+                    TextLayout::new_with_justify(JustifyText::Center),
+                    TextFont {
+                        font: game_assets.font.clone(),
+                        font_size: 20.0,
+                        font_smoothing: FontSmoothing::AntiAliased,
+                    },
+                    Transform::from_xyz(0., 256., 100.),
+                    PlayerLabel,
+                ));
+            });
+    }
+    opponent
+}
+
+/// Removes the second player's board and puts the first one back in place.
+#[allow(clippy::type_complexity)]
+pub fn end_versus(
+    mut commands: Commands,
+    boards: Query<(Entity, Has<PlayerBoard>), With<Versus>>,
+    versus_children: Query<(Entity, &Parent), Or<(With<GarbageMeter>, With<PlayerLabel>)>>,
+) {
+    for (board, is_player) in &boards {
+        if !is_player {
+            commands.entity(board).despawn_recursive();
+            continue;
+        }
+        commands
+            .entity(board)
+            .remove::<Versus>()
+            .insert(Transform::default());
+        for (entity, parent) in &versus_children {
+            if parent.get() == board {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+    }
+}
+
+/// Reads each player's half of the keyboard and their gamepad, the first
+/// gamepad goes to the first player.
+#[allow(clippy::type_complexity)]
+pub fn versus_input_system(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    gamepads: Query<&Gamepad>,
+    mut boards: Query<
+        (&Versus, &GameData, &mut InputBuffer),
+        (Without<RemoteBoard>, Without<AiPlayer>, Without<TbpBot>),
+    >,
+) {
+    let gamepads: Vec<&Gamepad> = gamepads.iter().collect();
+    for (versus, game_data, mut input_buffer) in &mut boards {
+        if game_data.paused {
+            continue;
+        }
+        for (key, action) in PLAYER_KEYS[versus.player % PLAYER_KEYS.len()] {
+            if keyboard_input.just_pressed(key) {
+                input_buffer.push(action);
+            }
+        }
+        if let Some(gamepad) = gamepads.get(versus.player) {
+            for (button, action) in GAMEPAD_BUTTONS {
+                if gamepad.just_pressed(button) {
+                    input_buffer.push(action);
+                }
+            }
+        }
+    }
+}
+
+/// Turns placements into attacks, cancels them against the player's own
+/// meter and sends the rest to the opponent. A placement that clears nothing
+/// raises the meter into the board.
+pub fn garbage_exchange_system(
+    attack_table: Res<AttackTable>,
+    mut boards: Query<(&mut GameData, &mut Versus, Has<RemoteBoard>)>,
+    mut piece_locked: EventReader<BoardEvent<PieceLocked>>,
+    mut garbage_sent: EventWriter<BoardEvent<GarbageSent>>,
+    mut top_out: EventWriter<BoardEvent<TopOut>>,
+) {
+    let mut attacks = vec![];
+    for BoardEvent { board, event } in piece_locked.read().copied() {
+        let Ok((mut game_data, mut versus, _)) = boards.get_mut(board) else {
+            continue;
+        };
+        if !game_data.is_running() {
+            continue;
+        }
+        let (rows, is_topped_out) =
+            versus.place(&attack_table, &mut game_data, event.lines.1, event.t_spin);
+        if rows > 0 {
+            attacks.push((versus.opponent, rows));
+            garbage_sent.send(BoardEvent {
+                board,
+                event: GarbageSent { rows },
+            });
+        }
+        if is_topped_out {
+            top_out.send(BoardEvent {
+                board,
+                event: TopOut,
+            });
+        }
+    }
+    // added once every board has placed, so the order boards run in doesn't matter
+    for (opponent, rows) in attacks {
+        // an online opponent's meter is filled from their own frames, see `net_sync_system`
+        if let Ok((_, mut versus, false)) = boards.get_mut(opponent) {
+            versus.meter.push_back(rows);
+        }
+    }
+}
+
+/// Pausing the first player's board pauses the whole match.
+#[allow(clippy::type_complexity)]
+pub fn versus_pause_system(
+    player_board: Single<&GameData, With<PlayerBoard>>,
+    mut boards: Query<&mut GameData, (With<Versus>, Without<PlayerBoard>, Without<RemoteBoard>)>,
+) {
+    for mut game_data in &mut boards {
+        if game_data.paused != player_board.paused {
+            game_data.paused = player_board.paused;
+        }
+    }
+}
+
+pub fn garbage_meter_system(
+    boards: Query<(&Versus, &GameData)>,
+    mut meters: Query<(&Parent, &mut Sprite), With<GarbageMeter>>,
+) {
+    for (parent, mut sprite) in &mut meters {
+        let Ok((versus, game_data)) = boards.get(parent.get()) else {
+            continue;
+        };
+        let height = versus.pending().min(game_data.board.rows as u32) as f32 * BRICK_NODE_WIDTH;
+        if sprite.custom_size.is_none_or(|size| size.y != height) {
+            sprite.custom_size = Some(Vec2::new(4., height));
+        }
+    }
+}
+
+/// Ends the match once at most one player hasn't topped out, the survivor
+/// wins. An online opponent's board runs behind, so the match waits until it
+/// has reached the tick the first board topped out on.
+pub fn versus_end_system(
+    mut boards: Query<&mut GameData, With<Versus>>,
+    mut next_state: ResMut<NextState<GameSate>>,
+) {
+    let Some(first_end) = boards
+        .iter()
+        .filter(|game_data| game_data.ended.is_some())
+        .map(|game_data| game_data.tick)
+        .min()
+    else {
+        return;
+    };
+    let standing: Vec<_> = boards
+        .iter()
+        .filter(|game_data| game_data.ended.is_none())
+        .collect();
+    if standing.len() > 1 || standing.iter().any(|game_data| game_data.tick < first_end) {
+        return;
+    }
+    for mut game_data in &mut boards {
+        // topping out on a later tick than the first board still outlasted it
+        if game_data.ended.is_none() || game_data.tick > first_end {
+            game_data.ended = Some(GameEnd::Finished);
+        }
+    }
+    next_state.set(GameSate::Results);
+}
+
+pub fn show_versus_results(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    boards: Query<(&Versus, &GameData)>,
+) {
+    let mut players: Vec<_> = boards.iter().collect();
+    if players.is_empty() {
+        return;
+    }
+    players.sort_by_key(|(versus, _)| versus.player);
+    let title = players
+        .iter()
+        .find(|(_, game_data)| game_data.ended == Some(GameEnd::Finished))
+        .map_or("DRAW".to_string(), |(versus, _)| {
+            format!("P{} WINS", versus.player + 1)
+        });
+    let sent: Vec<String> = players
+        .iter()
+        .map(|(versus, _)| format!("P{} sent {}", versus.player + 1, versus.sent))
+        .collect();
+    let summary = format!(
+        "{}\n\n{}\nTime {}",
+        title,
+        sent.join("\n"),
+        format_countdown(players[0].1.play_duration),
+    );
+    spawn_results_panel(&mut commands, &game_assets, summary);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::entity::Entity;
+
+    use super::{AttackTable, Versus};
+
+    use crate::game_data::GameData;
+
+    #[test]
+    fn test_attack_table() {
+        let table = AttackTable::default();
+        let mut versus = Versus::new(0, Entity::PLACEHOLDER, 1);
+        assert_eq!(versus.attack(&table, 1, false), 0);
+        versus.attack(&table, 0, false);
+        assert_eq!(versus.attack(&table, 2, false), 1);
+        versus.attack(&table, 0, false);
+        assert_eq!(versus.attack(&table, 3, false), 2);
+        versus.attack(&table, 0, false);
+        assert_eq!(versus.attack(&table, 4, false), 4);
+        versus.attack(&table, 0, false);
+        // back-to-back survives placements without a clear
+        assert_eq!(versus.attack(&table, 2, true), 2 + 1);
+        versus.attack(&table, 0, false);
+        assert_eq!(versus.attack(&table, 2, false), 1);
+        versus.attack(&table, 0, false);
+        assert_eq!(versus.attack(&table, 4, false), 4);
+        versus.attack(&table, 0, false);
+
+        // combos: 0, 1, 1, 2 extra rows
+        let rows: Vec<u32> = (0..4).map(|_| versus.attack(&table, 2, false)).collect();
+        assert_eq!(rows, vec![1, 2, 2, 3]);
+        versus.attack(&table, 0, false);
+        assert_eq!(versus.combo, None);
+    }
+
+    #[test]
+    fn test_cancel_and_raise_garbage() {
+        let mut versus = Versus::new(1, Entity::PLACEHOLDER, 7);
+        versus.meter.extend([2, 3]);
+        assert_eq!(versus.cancel(3), 0);
+        assert_eq!(versus.meter, [2]);
+        assert_eq!(versus.cancel(4), 2);
+        assert_eq!(versus.pending(), 0);
+
+        let mut game_data = GameData::default();
+        game_data.start(7);
+        versus.meter.extend([1, 2]);
+        assert!(!versus.raise_garbage(&mut game_data));
+        assert_eq!(game_data.board.garbage_rows(), 3);
+        assert_eq!(versus.pending(), 0);
+
+        versus.meter.push_back(game_data.board.rows as u32);
+        assert!(versus.raise_garbage(&mut game_data));
+    }
+}